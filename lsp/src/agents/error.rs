@@ -1,4 +1,5 @@
-use crate::error::error_chain_fmt;
+use crate::{config::espx::ModelProvider, error::error_chain_fmt};
+use espionox::language_models::completions::CompletionProvider;
 use lsp_types::Uri;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
@@ -9,7 +10,8 @@ pub enum AgentsError {
     #[error(transparent)]
     Undefined(#[from] anyhow::Error),
     DocAgentNotPresent(Uri),
-    CustomAgentNotPresent(char),
+    CustomAgentNotPresent(String),
+    ModelNotFromProvider(CompletionProvider, ModelProvider),
 }
 
 impl Debug for AgentsError {
@@ -25,8 +27,11 @@ impl Display for AgentsError {
             Self::DocAgentNotPresent(uri) => {
                 format!("No agent present for document: {}", uri.to_string())
             }
-            Self::CustomAgentNotPresent(char) => {
-                format!("No agent present for character: {char}")
+            Self::CustomAgentNotPresent(scope) => {
+                format!("No agent present for scope: {scope}")
+            }
+            Self::ModelNotFromProvider(model, provider) => {
+                format!("{model:?} is not available from the configured provider: {provider:?}")
             }
         };
         write!(f, "{}", display)
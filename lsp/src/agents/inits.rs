@@ -19,11 +19,22 @@ pub const SUMMARIZER_AGENT_SYSTEM_PROMPT: &str = r#"
     Please be thorough in your summaries.
 "#;
 
-pub(super) fn summarizer(cfg: &ModelConfig) -> Agent {
-    let provider: CompletionProvider = match cfg.provider {
+pub const INLINE_COMPLETION_SYSTEM_PROMPT: &str = r#"
+You are a code completion engine. You will be given the code before and after the user's cursor,
+respond with only the code that belongs at the cursor. Do not repeat the code around the cursor,
+do not explain yourself and do not wrap your response in a code block.
+"#;
+
+/// The provider's cheapest model
+pub(super) fn cheapest_provider(cfg: &ModelConfig) -> CompletionProvider {
+    match cfg.provider {
         ModelProvider::OpenAi => OpenAiCompletionModel::Gpt3.into(),
         ModelProvider::Anthropic => AnthropicCompletionModel::Haiku.into(),
-    };
+    }
+}
+
+pub(super) fn summarizer(cfg: &ModelConfig) -> Agent {
+    let provider = cheapest_provider(cfg);
     let params = ModelParameters::default();
     Agent::new(
         Some(SUMMARIZER_AGENT_SYSTEM_PROMPT),
@@ -31,11 +42,16 @@ pub(super) fn summarizer(cfg: &ModelConfig) -> Agent {
     )
 }
 
-pub(super) fn global(cfg: &ModelConfig) -> Agent {
-    let provider: CompletionProvider = match cfg.provider {
+/// The model every assistant agent completes with, unless a flag overrides it
+pub(super) fn assistant_provider(cfg: &ModelConfig) -> CompletionProvider {
+    match cfg.provider {
         ModelProvider::OpenAi => OpenAiCompletionModel::Gpt4.into(),
         ModelProvider::Anthropic => AnthropicCompletionModel::Sonnet.into(),
-    };
+    }
+}
+
+pub(super) fn global(cfg: &ModelConfig) -> Agent {
+    let provider = assistant_provider(cfg);
     let params = ModelParameters::default();
     Agent::new(
         Some(ASSISTANT_AGENT_SYSTEM_PROMPT),
@@ -51,10 +67,7 @@ pub fn doc_control_role() -> MessageRole {
 }
 
 pub(super) fn document(cfg: &ModelConfig, doc_content: &str) -> Agent {
-    let provider: CompletionProvider = match cfg.provider {
-        ModelProvider::OpenAi => OpenAiCompletionModel::Gpt4.into(),
-        ModelProvider::Anthropic => AnthropicCompletionModel::Sonnet.into(),
-    };
+    let provider = assistant_provider(cfg);
     let params = ModelParameters::default();
     let mut agent = Agent::new(
         Some(ASSISTANT_AGENT_SYSTEM_PROMPT),
@@ -71,10 +84,7 @@ pub(super) fn document(cfg: &ModelConfig, doc_content: &str) -> Agent {
 }
 
 pub(super) fn custom(cfg: &ModelConfig, sys_prompt: String) -> Agent {
-    let provider: CompletionProvider = match cfg.provider {
-        ModelProvider::OpenAi => OpenAiCompletionModel::Gpt4.into(),
-        ModelProvider::Anthropic => AnthropicCompletionModel::Sonnet.into(),
-    };
+    let provider = assistant_provider(cfg);
 
     let params = ModelParameters::default();
     let agent = Agent::new(
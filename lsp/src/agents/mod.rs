@@ -1,21 +1,28 @@
 use std::collections::HashMap;
 pub mod error;
-use crate::config::espx::ModelConfig;
+use crate::{
+    config::espx::{ModelConfig, ModelProvider},
+    interact::args::InteractFlag,
+};
 use error::{AgentsError, AgentsResult};
 use espionox::{
-    agents::{memory::MessageStackRef, Agent},
-    prelude::Message,
+    agents::Agent,
+    language_models::completions::{CompletionModel, CompletionProvider, ModelParameters},
+    prelude::{Message, MessageRole},
 };
-pub use inits::{doc_control_role, ASSISTANT_AGENT_SYSTEM_PROMPT};
-use lsp_types::{MarkedString, Uri};
+pub use inits::{doc_control_role, ASSISTANT_AGENT_SYSTEM_PROMPT, INLINE_COMPLETION_SYSTEM_PROMPT};
+use lsp_types::Uri;
 mod inits;
 
+/// Most tokens a single inline completion can be
+const INLINE_COMPLETION_MAX_TOKENS: u32 = 256;
+
 #[derive(Debug)]
 pub struct Agents {
     pub config: ModelConfig,
     global: Agent,
     document: HashMap<Uri, Agent>,
-    custom: HashMap<char, Agent>,
+    custom: HashMap<String, Agent>,
 }
 
 impl From<ModelConfig> for Agents {
@@ -30,25 +37,6 @@ impl From<ModelConfig> for Agents {
     }
 }
 
-pub fn message_stack_into_marked_string(mut stack: MessageStackRef<'_>) -> MarkedString {
-    let mut content = String::new();
-    while let Some(message) = stack.pop(None) {
-        content.push_str(&format!(
-            r#"
-## {:?}
-{}
-        "#,
-            message.role,
-            message.content.trim_start()
-        ));
-    }
-
-    MarkedString::LanguageString(lsp_types::LanguageString {
-        language: "markdown".to_string(),
-        value: content,
-    })
-}
-
 impl Agents {
     pub fn global_agent_ref(&self) -> &Agent {
         &self.global
@@ -62,7 +50,7 @@ impl Agents {
         self.document.iter()
     }
 
-    pub fn custom_agents_iter(&self) -> std::collections::hash_map::Iter<'_, char, Agent> {
+    pub fn custom_agents_iter(&self) -> std::collections::hash_map::Iter<'_, String, Agent> {
         self.custom.iter()
     }
 
@@ -78,16 +66,16 @@ impl Agents {
             .ok_or(AgentsError::DocAgentNotPresent(uri.clone()))
     }
 
-    pub fn custom_agent_mut(&mut self, char: char) -> AgentsResult<&mut Agent> {
+    pub fn custom_agent_mut(&mut self, scope: &str) -> AgentsResult<&mut Agent> {
         self.custom
-            .get_mut(&char)
-            .ok_or(AgentsError::CustomAgentNotPresent(char))
+            .get_mut(scope)
+            .ok_or(AgentsError::CustomAgentNotPresent(scope.to_owned()))
     }
 
-    pub fn custom_agent_ref(&self, char: char) -> AgentsResult<&Agent> {
+    pub fn custom_agent_ref(&self, scope: &str) -> AgentsResult<&Agent> {
         self.custom
-            .get(&char)
-            .ok_or(AgentsError::CustomAgentNotPresent(char))
+            .get(scope)
+            .ok_or(AgentsError::CustomAgentNotPresent(scope.to_owned()))
     }
 
     pub fn update_or_create_doc_agent(&mut self, uri: &Uri, doc_content: &str) {
@@ -107,13 +95,160 @@ impl Agents {
         }
     }
 
-    pub fn create_custom_agent(&mut self, char: char, sys_prompt: String) {
+    pub fn create_custom_agent(&mut self, scope: String, sys_prompt: String) {
         let agent = self::inits::custom(&self.config, sys_prompt);
-        self.custom.insert(char, agent);
+        self.custom.insert(scope, agent);
+    }
+
+    /// Errors if the flag can't be used with the configured provider
+    pub fn validate_flag(&self, flag: &InteractFlag) -> AgentsResult<()> {
+        match (flag, &self.config.provider) {
+            (InteractFlag::Model(CompletionProvider::OpenAi(_)), ModelProvider::OpenAi)
+            | (InteractFlag::Model(CompletionProvider::Anthropic(_)), ModelProvider::Anthropic) => {
+                Ok(())
+            }
+            (InteractFlag::Model(model), provider) => Err(AgentsError::ModelNotFromProvider(
+                model.clone(),
+                provider.clone(),
+            )),
+            _ => Ok(()),
+        }
     }
 
-    pub fn get_last_n_messages(agent: &Agent, n: usize) -> MessageStackRef {
-        let messages: Vec<&Message> = agent.cache.as_ref().iter().rev().take(n).collect();
-        MessageStackRef::from(messages)
+    /// Model for a single completion, the assistant defaults with the given flags applied on top
+    pub fn completion_model_with_flags(
+        &self,
+        flags: &[InteractFlag],
+    ) -> AgentsResult<CompletionModel> {
+        let (provider, params) = self.completion_params_with_flags(flags)?;
+        Ok(CompletionModel::new(provider, params, &self.config.api_key))
+    }
+
+    /// Provider and parameters of the model `completion_model_with_flags` returns
+    pub fn completion_params_with_flags(
+        &self,
+        flags: &[InteractFlag],
+    ) -> AgentsResult<(CompletionProvider, ModelParameters)> {
+        let mut provider = self::inits::assistant_provider(&self.config);
+        let mut params = ModelParameters::default();
+        for flag in flags {
+            self.validate_flag(flag)?;
+            match flag {
+                InteractFlag::Model(model) => provider = model.clone(),
+                // espionox takes temperature as a number between 0 and 200
+                InteractFlag::Temperature(temp) => {
+                    params.temperature = Some((temp * 100.0).round() as u8)
+                }
+                InteractFlag::MaxTokens(max) => params.max_tokens = Some(*max),
+            }
+        }
+        Ok((provider, params))
+    }
+
+    /// Agent for a single inline completion. It has the document agent's context, apart from the
+    /// document itself, which is replaced by the code before and after the cursor
+    pub fn inline_completion_agent(
+        &self,
+        uri: &Uri,
+        prefix: &str,
+        suffix: &str,
+    ) -> AgentsResult<Agent> {
+        let provider = match self.config.inline_completion.model {
+            Some(name) => {
+                let provider = CompletionProvider::from(name);
+                self.validate_flag(&InteractFlag::Model(provider.clone()))?;
+                provider
+            }
+            None => self::inits::cheapest_provider(&self.config),
+        };
+        let params = ModelParameters {
+            max_tokens: Some(INLINE_COMPLETION_MAX_TOKENS),
+            ..Default::default()
+        };
+        let mut agent = Agent::new(
+            Some(INLINE_COMPLETION_SYSTEM_PROMPT),
+            CompletionModel::new(provider, params, &self.config.api_key),
+        );
+
+        let doc_role = doc_control_role();
+        for message in self.doc_agent_ref(uri)?.cache.as_ref() {
+            if message.role != MessageRole::System && message.role != doc_role {
+                agent.cache.push(message.clone());
+            }
+        }
+        agent.cache.push(Message::new_user(&format!(
+            "<before_cursor>{prefix}</before_cursor><after_cursor>{suffix}</after_cursor>"
+        )));
+        Ok(agent)
+    }
+
+    /// The first system message in the agent's cache
+    pub fn system_prompt(agent: &Agent) -> Option<&str> {
+        agent
+            .cache
+            .as_ref()
+            .iter()
+            .find(|message| message.role == MessageRole::System)
+            .map(|message| message.content.as_str())
+    }
+
+    /// Roughly how many tokens the agent's cache is, at about four characters a token
+    pub fn estimated_tokens(agent: &Agent) -> usize {
+        agent
+            .cache
+            .as_ref()
+            .iter()
+            .map(|message| message.content.chars().count().div_ceil(4))
+            .sum()
+    }
+
+    /// The last prompt in the agent's cache along with the answer to it, if it was answered
+    pub fn last_exchange(agent: &Agent) -> Option<(&Message, Option<&Message>)> {
+        let messages = agent.cache.as_ref();
+        let prompt_idx = messages
+            .iter()
+            .rposition(|message| message.role == MessageRole::User)?;
+        let answer = messages[prompt_idx + 1..]
+            .iter()
+            .find(|message| message.role == MessageRole::Assistant);
+        Some((&messages[prompt_idx], answer))
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+    #[allow(unused)]
+    use espionox::{
+        agents::memory::MessageStack,
+        language_models::completions::anthropic::{
+            builder::AnthropicCompletionModel, requests::AnthropicIoRequest,
+        },
+    };
+
+    #[allow(unused)]
+    fn anthropic_agents() -> Agents {
+        Agents::from(ModelConfig {
+            provider: ModelProvider::Anthropic,
+            api_key: "key".to_owned(),
+            inline_completion: Default::default(),
+        })
+    }
+
+    #[test]
+    fn temperature_flag_reaches_the_request() {
+        let agents = anthropic_agents();
+        for temp in [0.0, 0.2, 1.5, 2.0] {
+            let (_, params) = agents
+                .completion_params_with_flags(&[InteractFlag::Temperature(temp)])
+                .unwrap();
+            let request = AnthropicIoRequest::new(
+                &MessageStack::init(),
+                &params,
+                AnthropicCompletionModel::Haiku,
+                false,
+            );
+            assert_eq!(request.temperature, temp);
+        }
     }
 }
@@ -1,3 +1,7 @@
+use espionox::language_models::completions::{
+    anthropic::builder::AnthropicCompletionModel, openai::builder::OpenAiCompletionModel,
+    CompletionProvider,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -10,4 +14,50 @@ pub enum ModelProvider {
 pub struct ModelConfig {
     pub provider: ModelProvider,
     pub api_key: String,
+    #[serde(default)]
+    pub inline_completion: InlineCompletionConfig,
+}
+
+/// A model that can be named in the config, it must be one of the provider's
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelName {
+    Opus,
+    Sonnet,
+    Haiku,
+    Gpt3,
+    Gpt4,
+}
+
+/// Model generated continuations at the cursor, off unless enabled
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct InlineCompletionConfig {
+    pub enabled: bool,
+    /// Defaults to the provider's cheapest model
+    pub model: Option<ModelName>,
+    /// How long typing has to pause for before the model is asked for a completion
+    pub debounce_ms: u64,
+}
+
+impl Default for InlineCompletionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: None,
+            debounce_ms: 300,
+        }
+    }
+}
+
+impl From<ModelName> for CompletionProvider {
+    fn from(name: ModelName) -> Self {
+        match name {
+            ModelName::Opus => AnthropicCompletionModel::Opus.into(),
+            ModelName::Sonnet => AnthropicCompletionModel::Sonnet.into(),
+            ModelName::Haiku => AnthropicCompletionModel::Haiku.into(),
+            ModelName::Gpt3 => OpenAiCompletionModel::Gpt3.into(),
+            ModelName::Gpt4 => OpenAiCompletionModel::Gpt4.into(),
+        }
+    }
 }
@@ -1,9 +1,11 @@
 pub mod database;
 pub mod espx;
+pub mod prompt;
 pub mod scopes;
 use database::{DatabaseConfig, DatabaseConfigFromFile};
 use espx::ModelConfig;
-use scopes::{ScopeConfig, ScopeConfigFromFile, ScopeSettings};
+use prompt::{PromptConfig, PromptConfigFromFile};
+use scopes::{ScopeConfig, ScopeConfigFromFile};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -19,6 +21,7 @@ pub struct Config {
     pub model: Option<ModelConfig>,
     pub database: Option<DatabaseConfig>,
     pub scopes: Option<ScopeConfig>,
+    pub prompt: Option<PromptConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
@@ -26,18 +29,19 @@ pub struct ConfigFromFile {
     model: Option<ModelConfig>,
     database: Option<DatabaseConfigFromFile>,
     scopes: Option<ScopeConfigFromFile>,
+    prompt: Option<PromptConfigFromFile>,
 }
 
 impl From<(ConfigFromFile, PathBuf)> for Config {
     fn from((cfg, pwd): (ConfigFromFile, PathBuf)) -> Self {
-        let scopes: Option<HashMap<char, ScopeSettings>> = {
+        let scopes: Option<ScopeConfig> = {
             if cfg.scopes.is_none() || cfg.scopes.as_ref().is_some_and(|hm| hm.is_empty()) {
                 None
             } else {
                 let mut map = HashMap::new();
 
-                for (char, settings) in cfg.scopes.unwrap() {
-                    map.insert(char, settings.into());
+                for (token, settings) in cfg.scopes.unwrap() {
+                    map.insert(token, settings.into());
                 }
                 Some(map)
             }
@@ -48,6 +52,7 @@ impl From<(ConfigFromFile, PathBuf)> for Config {
             model: cfg.model,
             database: cfg.database.and_then(|db| Some(db.into())),
             scopes,
+            prompt: cfg.prompt.map(PromptConfig::from),
         }
     }
 }
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the answer to a prompt ends up
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseMode {
+    /// Shown in a message popup, the prompt text is removed from the buffer
+    #[default]
+    Popup,
+    /// Written into the buffer as a comment under the prompt, replacing any previous answer
+    Buffer,
+    /// Like `Buffer`, but written into the buffer while it is generated
+    Stream,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PromptConfig {
+    pub response_mode: ResponseMode,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub(super) struct PromptConfigFromFile {
+    response_mode: Option<ResponseMode>,
+}
+
+impl From<PromptConfigFromFile> for PromptConfig {
+    fn from(value: PromptConfigFromFile) -> Self {
+        Self {
+            response_mode: value.response_mode.unwrap_or_default(),
+        }
+    }
+}
@@ -4,8 +4,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::agents::ASSISTANT_AGENT_SYSTEM_PROMPT;
 
-pub type ScopeConfigFromFile = HashMap<char, ScopeSettingsFromFile>;
-pub type ScopeConfig = HashMap<char, ScopeSettings>;
+pub type ScopeConfigFromFile = HashMap<String, ScopeSettingsFromFile>;
+pub type ScopeConfig = HashMap<String, ScopeSettings>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ScopeSettings {
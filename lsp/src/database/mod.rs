@@ -13,7 +13,8 @@ use surrealdb::{
 };
 use tracing::debug;
 
-#[derive(Debug)]
+/// Clones share the connection
+#[derive(Debug, Clone)]
 pub struct Database {
     pub config: DatabaseConfig,
     pub client: Surreal<Db>,
@@ -17,32 +17,35 @@ pub struct DBAgentMemory {
     pub messages: MessageStack,
 }
 
+/// Scope tokens can be any string, so they are prefixed with something outside of the base64
+/// alphabet to keep them from being mistaken for an encoded uri
+const SCOPE_ID_PREFIX: &str = "scope:";
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum AgentID {
     EncodedUri(String),
-    Char(char),
+    Scope(String),
 }
 
 impl TryFrom<Thing> for AgentID {
     type Error = anyhow::Error;
     fn try_from(value: Thing) -> Result<Self, Self::Error> {
         match value.id {
-            surrealdb::sql::Id::String(string) => {
-                if string.chars().count() == 1 {
-                    Ok(Self::Char(string.chars().next().unwrap()))
-                } else {
-                    // let uri = Uri::from_str(&string)?;
-                    Ok(Self::EncodedUri(string))
-                }
-            }
+            surrealdb::sql::Id::String(string) => match string.strip_prefix(SCOPE_ID_PREFIX) {
+                Some(token) => Ok(Self::Scope(token.to_owned())),
+                // memories saved before scopes could be named are under their unprefixed char.
+                // No encoded uri is a single char
+                None if string.chars().count() == 1 => Ok(Self::Scope(string)),
+                None => Ok(Self::EncodedUri(string)),
+            },
             other => Err(anyhow!("{other:?} cannot be turned into an AgentID")),
         }
     }
 }
 
-impl From<&char> for AgentID {
-    fn from(value: &char) -> Self {
-        Self::Char(value.to_owned())
+impl From<&str> for AgentID {
+    fn from(value: &str) -> Self {
+        Self::Scope(value.to_owned())
     }
 }
 
@@ -57,7 +60,7 @@ impl ToString for AgentID {
     fn to_string(&self) -> String {
         match self {
             Self::EncodedUri(uri) => uri.to_string(),
-            Self::Char(char) => char.to_string(),
+            Self::Scope(token) => format!("{SCOPE_ID_PREFIX}{token}"),
         }
     }
 }
@@ -153,7 +156,7 @@ impl<'l> DatabaseStruct<'l, DBAgentMemoryParams<'l>> for DBAgentMemory {
         let id = params.id.to_string();
 
         Ok(format!(
-            "UPSERT {}:{id} {};",
+            "UPSERT {}:⟨{id}⟩ {};",
             Self::db_id(),
             Self::content(params)?
         ))
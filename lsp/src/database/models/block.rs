@@ -1,9 +1,5 @@
 use super::{DatabaseStruct, IntoOneOf};
-use crate::{
-    database::error::DatabaseError,
-    interact::lexer::{Token, TokenVec},
-    util::OneOf,
-};
+use crate::{database::error::DatabaseError, interact::lexer::TokenVec, util::OneOf};
 use anyhow::anyhow;
 use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
 use lsp_types::Uri;
@@ -17,9 +13,25 @@ pub struct DBBlock {
     pub id: Thing,
     pub uri: Uri,
     pub content: String,
+    /// Blocks saved before line ranges were tracked have none
+    #[serde(default)]
+    pub lines: Option<BlockLines>,
     pub content_embedding: Option<Vec<f32>>,
 }
 
+/// The first and last line of the document a block was taken from, both inclusive and 0 based
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockLines {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl std::fmt::Display for BlockLines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "lines {}-{}", self.start + 1, self.end + 1)
+    }
+}
+
 const LINES_PER_BLOCK: usize = 25;
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
@@ -30,6 +42,7 @@ pub struct DBBlockParams {
     id: DBBlockID,
     pub uri: Option<Uri>,
     pub content: Option<String>,
+    pub lines: Option<BlockLines>,
     content_embedding: Option<Vec<f32>>,
 }
 
@@ -74,6 +87,7 @@ impl DBBlockParams {
             id,
             uri: Some(uri),
             content,
+            lines: None,
             content_embedding: None,
         }
     }
@@ -82,10 +96,22 @@ impl DBBlockParams {
 pub fn block_params_from(tokens: &TokenVec, uri: Uri) -> Vec<DBBlockParams> {
     let mut all = vec![];
     let mut whole_doc_buffer = String::new();
-    for token in tokens.as_ref() {
-        if let Token::Block(block) = token {
-            whole_doc_buffer.push_str(&block);
+    // the document line each line of the buffer starts on, comments are left out of the buffer
+    // so these can skip ahead
+    let mut line_numbers = vec![];
+    for (block, start) in tokens.blocks().filter(|(block, _)| !block.is_empty()) {
+        if whole_doc_buffer.is_empty() || whole_doc_buffer.ends_with('\n') {
+            line_numbers.push(start.line);
+        }
+        let mut line = start.line;
+        for (idx, _) in block.match_indices('\n') {
+            line += 1;
+            // a line begun at the very end of the block is numbered by whatever comes next
+            if idx + 1 < block.len() {
+                line_numbers.push(line);
+            }
         }
+        whole_doc_buffer.push_str(&block);
     }
 
     let lines = whole_doc_buffer.lines().collect::<Vec<&str>>();
@@ -105,7 +131,12 @@ pub fn block_params_from(tokens: &TokenVec, uri: Uri) -> Vec<DBBlockParams> {
             break;
         }
 
-        let block_params = DBBlockParams::new(uri.clone(), chunks_taken, Some(content));
+        let mut block_params = DBBlockParams::new(uri.clone(), chunks_taken, Some(content));
+        let end = (start + LINES_PER_BLOCK).min(lines.len()) - 1;
+        block_params.lines = Some(BlockLines {
+            start: line_numbers[start],
+            end: line_numbers[end],
+        });
 
         all.push(block_params);
         chunks_taken += 1;
@@ -150,10 +181,12 @@ impl<'l> DatabaseStruct<'l, DBBlockParams> for DBBlock {
                     r#"CONTENT {{
                 uri: {},
                 content: {},
+                lines: {},
                 {}
                 }}"#,
                     serde_json::to_value(&me.uri)?,
                     serde_json::to_value(&me.content)?,
+                    serde_json::to_value(me.lines)?,
                     content_embedding_string
                 ))
             }
@@ -174,6 +207,14 @@ impl<'l> DatabaseStruct<'l, DBBlockParams> for DBBlock {
                     }
                 };
 
+                let lines_string = {
+                    if let Some(lines) = &params.lines {
+                        format!(",lines: {}", serde_json::to_value(lines)?)
+                    } else {
+                        String::new()
+                    }
+                };
+
                 let content_embedding_string = {
                     if let Some(emb) = &params.content_embedding {
                         format!(",content_embedding: {}", serde_json::to_value(emb)?)
@@ -184,10 +225,15 @@ impl<'l> DatabaseStruct<'l, DBBlockParams> for DBBlock {
 
                 Ok(format!(
                     r#"CONTENT {{ {} }}"#,
-                    [uri_string, content_string, content_embedding_string]
-                        .join(" ")
-                        .trim()
-                        .trim_start_matches(','),
+                    [
+                        uri_string,
+                        content_string,
+                        lines_string,
+                        content_embedding_string
+                    ]
+                    .join(" ")
+                    .trim()
+                    .trim_start_matches(','),
                 ))
             }
         }
@@ -211,7 +257,7 @@ impl<'l> DatabaseStruct<'l, DBBlockParams> for DBBlock {
 }
 
 mod tests {
-    use super::{block_params_from, DBBlockID, DBBlockParams};
+    use super::{block_params_from, BlockLines, DBBlockID, DBBlockParams};
     use crate::{
         database::models::block::LINES_PER_BLOCK,
         interact::{lexer::Lexer, registry::InteractRegistry},
@@ -306,10 +352,13 @@ fn again_again() {
         .expect("failed to read io");
 }"#,
         ));
-        let expected = vec![
+        let mut expected = vec![
             DBBlockParams::new(test_doc_1_uri.clone(), 0, first_chunk_content),
             DBBlockParams::new(test_doc_1_uri.clone(), 1, second_chunk_content),
         ];
+        // the comments aren't in the content, but the lines still count them
+        expected[0].lines = Some(BlockLines { start: 0, end: 27 });
+        expected[1].lines = Some(BlockLines { start: 28, end: 33 });
 
         let out = block_params_from(&tokens, test_doc_1_uri);
 
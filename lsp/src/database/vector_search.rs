@@ -1,8 +1,21 @@
 use super::*;
 use anyhow::anyhow;
-use models::{block::DBBlock, DatabaseStruct, FieldQuery, QueryBuilder};
+use models::{
+    block::{DBBlock, DBBlockParams},
+    DatabaseStruct, FieldQuery, QueryBuilder,
+};
 
 impl DBBlock {
+    /// Saves the blocks in a single transaction, dropping the embeddings of those already saved
+    pub async fn save_all(db: &Database, all_params: Vec<DBBlockParams>) -> DatabaseResult<()> {
+        let mut q = QueryBuilder::begin();
+        for params in all_params {
+            q.push(&Self::upsert(&params)?)
+        }
+        db.client.query(q.end()).await?;
+        Ok(())
+    }
+
     pub async fn all_with_no_embeddings(db: &Database) -> DatabaseResult<Vec<DBBlock>> {
         let field_query = FieldQuery::new("content_embedding", Option::<Vec<f32>>::None)?;
         let mut query = QueryBuilder::begin();
@@ -13,33 +26,40 @@ impl DBBlock {
         Ok(all)
     }
 
+    /// Embeds every block saved without an embedding, returning how many there were
+    pub async fn embed_missing(db: &Database) -> DatabaseResult<usize> {
+        let mut all_with_no_embeddings = Self::all_with_no_embeddings(db).await?;
+        if all_with_no_embeddings.is_empty() {
+            return Ok(0);
+        }
+        debug!(
+            "have to generate embeddings for the following documents: {:?}.",
+            all_with_no_embeddings
+                .iter()
+                .map(|u| u.uri.as_str())
+                .collect::<Vec<&str>>(),
+        );
+
+        DBBlock::embed_all(&mut all_with_no_embeddings)?;
+        let embedded = all_with_no_embeddings.len();
+        let mut q = QueryBuilder::begin();
+        for block in all_with_no_embeddings {
+            q.push(&Self::update(block.thing(), &block)?)
+        }
+        db.client.query(q.end()).await?;
+        Ok(embedded)
+    }
+
+    /// At most `limit` blocks more similar to the embedding than `threshold`, most similar first
     pub async fn get_relavent(
         db: &Database,
         embedding: Vec<f32>,
         threshold: f32,
+        limit: usize,
     ) -> DatabaseResult<Vec<Self>> {
-        let mut all_with_no_embeddings = Self::all_with_no_embeddings(db).await?;
-        if !all_with_no_embeddings.is_empty() {
-            debug!(
-                "have to generate embeddings for the following documents: {:?}.",
-                all_with_no_embeddings
-                    .iter()
-                    .map(|u| u.uri.as_str())
-                    .collect::<Vec<&str>>(),
-            );
-
-            DBBlock::embed_all(&mut all_with_no_embeddings)?;
-            let mut q = QueryBuilder::begin();
-            for block in all_with_no_embeddings {
-                q.push(&Self::update(block.thing(), &block)?)
-            }
-            db.client
-                .query(q.end())
-                .await
-                .expect("failed to query transaction");
-        }
+        Self::embed_missing(db).await?;
 
-        let query = format!("SELECT * FROM {} WHERE vector::similarity::cosine($this.content_embedding, $embedding) > {};", DBBlock::db_id(), threshold );
+        let query = format!("SELECT *, vector::similarity::cosine(content_embedding, $embedding) AS similarity FROM {} WHERE vector::similarity::cosine($this.content_embedding, $embedding) > {} ORDER BY similarity DESC LIMIT {};", DBBlock::db_id(), threshold, limit);
         let mut response = db
             .client
             .query(query)
@@ -1,4 +1,5 @@
-use crate::{agents::error::AgentsError, database::error::DatabaseError};
+use crate::{agents::error::AgentsError, database::error::DatabaseError, interact::InteractError};
+use lsp_types::Uri;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
 #[allow(unused_must_use)]
@@ -21,10 +22,12 @@ pub enum StateError {
     #[error(transparent)]
     Undefined(#[from] anyhow::Error),
     DatabaseNotPresent,
+    DocumentNotPresent(Uri),
     RegistryNotPresent,
     AgentsNotPresent,
     Database(#[from] DatabaseError),
     Agents(#[from] AgentsError),
+    Interact(#[from] InteractError),
 }
 
 impl Debug for StateError {
@@ -38,10 +41,12 @@ impl Display for StateError {
         let display = match self {
             Self::Undefined(err) => err.to_string(),
             Self::DatabaseNotPresent => String::from("Database Not Present"),
+            Self::DocumentNotPresent(uri) => format!("Document Not Present: {}", uri.as_str()),
             Self::RegistryNotPresent => String::from("Registry Not Present"),
             Self::AgentsNotPresent => String::from("Agents Not Present"),
             Self::Agents(err) => err.to_string(),
             Self::Database(err) => err.to_string(),
+            Self::Interact(err) => err.to_string(),
         };
         write!(f, "{}", display)
     }
@@ -1,22 +1,58 @@
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
-use crate::handle::buffer_operations::BufferOpChannelError;
+use crate::handle::{buffer_operations::BufferOpChannelError, outgoing::OutgoingRequests};
 
 use super::{BufferOpChannelResult, BufferOperation};
 use futures::{self, Stream};
 use lsp_types::{
-    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressEnd, WorkDoneProgressReport,
+    request::{ApplyWorkspaceEdit, ShowMessageRequest},
+    ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, MessageActionItem, ProgressToken,
+    ShowMessageRequestParams, WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressEnd,
+    WorkDoneProgressReport,
 };
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
 pub type BufferOpChannel = Box<dyn Stream<Item = BufferOpChannelResult<BufferOpChannelStatus>>>;
 
 pub type BufferOpChannelReceiver =
     tokio::sync::mpsc::Receiver<BufferOpChannelResult<BufferOpChannelStatus>>;
+/// Title of progress which is begun without one
+const PROGRESS_TITLE: &str = "espx";
+
+static NEXT_PROGRESS_TOKEN: AtomicU32 = AtomicU32::new(0);
+
+/// A token no other progress reported by the server uses
+pub fn new_progress_token() -> ProgressToken {
+    let n = NEXT_PROGRESS_TOKEN.fetch_add(1, Ordering::Relaxed);
+    ProgressToken::String(format!("espx-ls/{n}"))
+}
+
 #[derive(Clone, Debug)]
-pub struct BufferOpChannelSender(
-    tokio::sync::mpsc::Sender<BufferOpChannelResult<BufferOpChannelStatus>>,
-);
+pub struct BufferOpChannelSender {
+    channel: tokio::sync::mpsc::Sender<BufferOpChannelResult<BufferOpChannelStatus>>,
+    /// Shared by every clone, so all of a handler's work done is reported as one progress
+    progress: Arc<Mutex<HandlerProgress>>,
+    /// Whether the handler has responded to its request
+    responded: Arc<AtomicBool>,
+    /// Cancelled once the handler is done changing the state, messages ordered after it don't
+    /// wait on the rest of it
+    ordered: CancellationToken,
+}
+
+#[derive(Debug)]
+struct HandlerProgress {
+    token: ProgressToken,
+    /// Tokens the client sent already exist, the server has to create its own
+    from_client: bool,
+    begun: bool,
+}
 
 #[derive(Debug)]
 pub struct BufferOpChannelHandler {
@@ -41,7 +77,16 @@ impl BufferOpChannelHandler {
         let channel =
             tokio::sync::mpsc::channel::<BufferOpChannelResult<BufferOpChannelStatus>>(55);
         Self {
-            sender: BufferOpChannelSender(channel.0),
+            sender: BufferOpChannelSender {
+                channel: channel.0,
+                progress: Arc::new(Mutex::new(HandlerProgress {
+                    token: new_progress_token(),
+                    from_client: false,
+                    begun: false,
+                })),
+                responded: Arc::new(AtomicBool::new(false)),
+                ordered: CancellationToken::new(),
+            },
             receiver: channel.1,
         }
     }
@@ -50,8 +95,14 @@ impl BufferOpChannelHandler {
 impl BufferOpChannelSender {
     pub async fn send_operation(&mut self, op: BufferOperation) -> BufferOpChannelResult<()> {
         debug!("sending buffer operation to client: {:?}", op);
-        let result =
-            tokio::time::timeout(Duration::from_millis(1000), self.0.send(Ok(op.into()))).await;
+        if op.is_response() {
+            self.responded.store(true, Ordering::Relaxed);
+        }
+        let result = tokio::time::timeout(
+            Duration::from_millis(1000),
+            self.channel.send(Ok(op.into())),
+        )
+        .await;
 
         match result {
             Ok(send_result) => send_result.map_err(|err| err.into()),
@@ -62,7 +113,7 @@ impl BufferOpChannelSender {
     pub async fn send_finish(&self) -> BufferOpChannelResult<()> {
         let result = tokio::time::timeout(
             Duration::from_millis(1000),
-            self.0.send(Ok(BufferOpChannelStatus::Finished)),
+            self.channel.send(Ok(BufferOpChannelStatus::Finished)),
         )
         .await;
 
@@ -72,18 +123,82 @@ impl BufferOpChannelSender {
         }
     }
 
+    /// Sends a request to the client and waits on its response
+    pub async fn send_request<R: lsp_types::request::Request>(
+        &mut self,
+        outgoing: &OutgoingRequests,
+        params: R::Params,
+    ) -> BufferOpChannelResult<R::Result> {
+        // the client can take as long as it likes to respond
+        self.release_order();
+        let (request, pending) = outgoing.request::<R>(params)?;
+        self.send_operation(BufferOperation::ClientRequest(request))
+            .await?;
+        pending.response().await
+    }
+
+    /// Unlike sending a [`BufferOperation::WorkspaceEdit`], lets the caller know whether the
+    /// client applied the edit
+    pub async fn apply_edit(
+        &mut self,
+        outgoing: &OutgoingRequests,
+        edit: ApplyWorkspaceEditParams,
+    ) -> BufferOpChannelResult<ApplyWorkspaceEditResponse> {
+        self.send_request::<ApplyWorkspaceEdit>(outgoing, edit)
+            .await
+    }
+
+    /// Returns the action the user picked, if any
+    pub async fn show_message_request(
+        &mut self,
+        outgoing: &OutgoingRequests,
+        params: ShowMessageRequestParams,
+    ) -> BufferOpChannelResult<Option<MessageActionItem>> {
+        self.send_request::<ShowMessageRequest>(outgoing, params)
+            .await
+    }
+
+    /// Lets the messages ordered after the handler be handled, for when it is done changing the
+    /// state and only waits on the model or the client from here on
+    pub fn release_order(&self) {
+        self.ordered.cancel();
+    }
+
+    /// Cancelled once the handler releases its order
+    pub fn order_released(&self) -> CancellationToken {
+        self.ordered.clone()
+    }
+
+    /// Whether a response to the handler's request has been sent
+    pub fn has_responded(&self) -> bool {
+        self.responded.load(Ordering::Relaxed)
+    }
+
+    /// Reports work done with the token the client sent along with its request, if it sent one
+    pub fn use_work_done_token(&self, token: Option<ProgressToken>) {
+        let Some(token) = token else {
+            return;
+        };
+        let mut progress = self.lock_progress();
+        if progress.begun {
+            warn!("progress already begun, not switching to the client's token");
+            return;
+        }
+        progress.token = token;
+        progress.from_client = true;
+    }
+
     pub async fn start_work_done(&mut self, message: Option<&str>) -> BufferOpChannelResult<()> {
         let work_done = WorkDoneProgressBegin {
+            title: PROGRESS_TITLE.to_owned(),
             message: message.and_then(|s| Some(s.to_string())),
             ..Default::default()
         };
-        self.send_operation(BufferOperation::WorkDone(WorkDoneProgress::Begin(
-            work_done,
-        )))
-        .await?;
-        Ok(())
+        self.send_work_done(WorkDoneProgress::Begin(work_done))
+            .await
     }
 
+    /// Begins the progress if the handler has not
     #[tracing::instrument(name = "send work done report", skip_all)]
     pub async fn send_work_done_report(
         &mut self,
@@ -98,12 +213,8 @@ impl BufferOpChannelSender {
 
         warn!("report: {work_done:#?}");
 
-        self.send_operation(BufferOperation::WorkDone(WorkDoneProgress::Report(
-            work_done,
-        )))
-        .await?;
-
-        Ok(())
+        self.send_work_done(WorkDoneProgress::Report(work_done))
+            .await
     }
 
     pub async fn send_work_done_end(&mut self, message: Option<&str>) -> BufferOpChannelResult<()> {
@@ -111,10 +222,61 @@ impl BufferOpChannelSender {
             message: message.and_then(|s| Some(s.to_string())),
             ..Default::default()
         };
-        self.send_operation(BufferOperation::WorkDone(WorkDoneProgress::End(work_done)))
-            .await?;
+        self.send_work_done(WorkDoneProgress::End(work_done)).await
+    }
+
+    async fn send_work_done(&mut self, work: WorkDoneProgress) -> BufferOpChannelResult<()> {
+        let mut ops = vec![];
+        {
+            let mut progress = self.lock_progress();
+            let token = progress.token.clone();
+            let create = !progress.from_client;
+            match (&work, progress.begun) {
+                (WorkDoneProgress::Begin(_), true) => {
+                    warn!("progress {token:?} already begun");
+                    return Ok(());
+                }
+                (WorkDoneProgress::End(_), false) => return Ok(()),
+                (WorkDoneProgress::Report(_), false) => {
+                    let begin = WorkDoneProgressBegin {
+                        title: PROGRESS_TITLE.to_owned(),
+                        ..Default::default()
+                    };
+                    ops.push(BufferOperation::WorkDone {
+                        token: token.clone(),
+                        create,
+                        work: WorkDoneProgress::Begin(begin),
+                    });
+                    progress.begun = true;
+                }
+                (WorkDoneProgress::Begin(_), false) => progress.begun = true,
+                (WorkDoneProgress::End(_), true) => {
+                    // a token is done once its progress ends, any more progress gets a new one
+                    progress.token = new_progress_token();
+                    progress.from_client = false;
+                    progress.begun = false;
+                }
+                (WorkDoneProgress::Report(_), true) => {}
+            }
+            ops.push(BufferOperation::WorkDone {
+                token,
+                create,
+                work,
+            });
+        }
+
+        for op in ops {
+            self.send_operation(op).await?;
+        }
         Ok(())
     }
+
+    fn lock_progress(&self) -> std::sync::MutexGuard<'_, HandlerProgress> {
+        // progress is never left half updated, so a poisoned lock is still usable
+        self.progress
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
 }
 
 mod tests {
@@ -149,4 +311,69 @@ mod tests {
         }
         assert_eq!(5, counter);
     }
+
+    #[allow(unused)]
+    fn received_work_done(handler: &mut BufferOpChannelHandler) -> Vec<(ProgressToken, bool)> {
+        let mut received = vec![];
+        while let Ok(Ok(BufferOpChannelStatus::Working(BufferOperation::WorkDone {
+            token,
+            create,
+            work,
+        }))) = handler.receiver.try_recv()
+        {
+            let begins = matches!(work, WorkDoneProgress::Begin(_));
+            received.push((token, create && begins));
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn handlers_report_progress_with_their_own_tokens() {
+        let mut first = BufferOpChannelHandler::new();
+        let mut second = BufferOpChannelHandler::new();
+        let mut first_sender = first.sender.clone();
+        let mut second_sender = second.sender.clone();
+
+        first_sender
+            .send_work_done_report(None, None)
+            .await
+            .unwrap();
+        first.sender.send_work_done_end(None).await.unwrap();
+        first_sender
+            .send_work_done_report(None, None)
+            .await
+            .unwrap();
+        second_sender.start_work_done(None).await.unwrap();
+
+        let first_ops = received_work_done(&mut first);
+        let second_ops = received_work_done(&mut second);
+        assert_eq!(first_ops.len(), 5);
+        // reporting without having begun begins the progress, creating its token first
+        assert!(first_ops[0].1);
+        assert!(first_ops[..3]
+            .iter()
+            .all(|(token, _)| *token == first_ops[0].0));
+        // a token is never used again once its progress ends
+        assert!(first_ops[3].1);
+        assert_ne!(first_ops[3].0, first_ops[0].0);
+        assert_ne!(second_ops[0].0, first_ops[0].0);
+        assert_ne!(second_ops[0].0, first_ops[3].0);
+
+        let mut from_client = BufferOpChannelHandler::new();
+        let client_token = ProgressToken::String("client".to_owned());
+        from_client
+            .sender
+            .use_work_done_token(Some(client_token.clone()));
+        from_client
+            .sender
+            .clone()
+            .send_work_done_report(None, None)
+            .await
+            .unwrap();
+        let ops = received_work_done(&mut from_client);
+        assert_eq!(
+            ops,
+            vec![(client_token.clone(), false), (client_token, false)]
+        );
+    }
 }
@@ -1,7 +1,7 @@
 use crate::error::error_chain_fmt;
 use anyhow::anyhow;
 use crossbeam_channel::SendError;
-use lsp_server::Message;
+use lsp_server::{Message, ResponseError};
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
 pub type BufferOpResult<T> = Result<T, BufferOpError>;
@@ -41,6 +41,10 @@ pub enum BufferOpChannelError {
     Timeout,
     CrossBeamSend(#[from] SendError<Message>),
     Json(#[from] serde_json::Error),
+    /// The client responded to a request with an error
+    Client(ResponseError),
+    /// The client never responded to a request
+    NoResponse,
 }
 
 impl<E> From<tokio::sync::mpsc::error::SendError<E>> for BufferOpChannelError {
@@ -63,6 +67,8 @@ impl Display for BufferOpChannelError {
             Self::Timeout => "Timeout".to_string(),
             Self::Json(err) => err.to_string(),
             Self::CrossBeamSend(err) => err.to_string(),
+            Self::Client(err) => format!("Client Error {}: {}", err.code, err.message),
+            Self::NoResponse => "Client never responded".to_string(),
         };
         write!(f, "{}", display)
     }
@@ -0,0 +1,136 @@
+use super::{BufferOpChannelResult, BufferOpChannelSender, BufferOperation};
+use crate::interact::encoding::PositionEncoding;
+use lsp_types::{Position, Range, TextEdit, Uri};
+use std::time::{Duration, Instant};
+
+/// Longest text is held before it is sent
+const STREAM_BATCH_INTERVAL: Duration = Duration::from_millis(100);
+/// Most bytes held before they are sent
+const STREAM_BATCH_BYTES: usize = 256;
+
+/// Inserts text into a document as it arrives, batching it into as few workspace edits as
+/// possible. Every batch is inserted at the end of the last one
+#[derive(Debug)]
+pub struct StreamedInsert {
+    sender: BufferOpChannelSender,
+    uri: Uri,
+    encoding: PositionEncoding,
+    start: Position,
+    end: Position,
+    pending: String,
+    last_sent: Instant,
+}
+
+impl BufferOpChannelSender {
+    /// Starts inserting text into the document at `start`
+    pub fn streamed_insert(
+        &self,
+        uri: Uri,
+        start: Position,
+        encoding: PositionEncoding,
+    ) -> StreamedInsert {
+        StreamedInsert {
+            sender: self.clone(),
+            uri,
+            encoding,
+            start,
+            end: start,
+            pending: String::new(),
+            last_sent: Instant::now(),
+        }
+    }
+}
+
+impl StreamedInsert {
+    /// Range of everything that has been sent so far
+    pub fn range(&self) -> Range {
+        Range::new(self.start, self.end)
+    }
+
+    /// Holds the text until enough of it has built up, or enough time has passed, to send it
+    pub async fn push(&mut self, text: &str) -> BufferOpChannelResult<()> {
+        self.pending.push_str(text);
+        if self.pending.len() >= STREAM_BATCH_BYTES
+            || self.last_sent.elapsed() >= STREAM_BATCH_INTERVAL
+        {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Sends whatever text is being held
+    pub async fn flush(&mut self) -> BufferOpChannelResult<()> {
+        self.last_sent = Instant::now();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let text = std::mem::take(&mut self.pending);
+        let edit = TextEdit {
+            range: Range::new(self.end, self.end),
+            new_text: text,
+        };
+
+        self.end = match edit.new_text.rsplit_once('\n') {
+            Some((before, last_line)) => Position {
+                line: self.end.line + before.matches('\n').count() as u32 + 1,
+                character: self.encoding.str_len(last_line) as u32,
+            },
+            None => Position {
+                line: self.end.line,
+                character: self.end.character + self.encoding.str_len(&edit.new_text) as u32,
+            },
+        };
+
+        self.sender
+            .send_operation(BufferOperation::text_edits(self.uri.clone(), vec![edit]))
+            .await
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+    #[allow(unused)]
+    use crate::handle::buffer_operations::{BufferOpChannelHandler, BufferOpChannelStatus};
+    #[allow(unused)]
+    use crate::state::document::offset_at_position;
+    #[allow(unused)]
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn streamed_insert_tracks_its_range() {
+        let mut handler = BufferOpChannelHandler::new();
+        let uri = Uri::from_str("file:///streamed.rs").unwrap();
+        let mut text = "// 😀 prompt\n}".to_owned();
+        let start = Position::new(0, 12);
+        let mut insert = handler
+            .sender
+            .streamed_insert(uri, start, PositionEncoding::Utf16);
+
+        let pieces = ["\n", "a 😀", &"b".repeat(STREAM_BATCH_BYTES), "\nc", "d"];
+        for piece in pieces {
+            insert.push(piece).await.unwrap();
+        }
+        insert.flush().await.unwrap();
+        handler.sender.send_finish().await.unwrap();
+
+        let mut edits = 0;
+        while let Ok(BufferOpChannelStatus::Working(BufferOperation::WorkspaceEdit(params))) =
+            handler.receiver.recv().await.unwrap()
+        {
+            for edit in params.edit.changes.unwrap().into_values().flatten() {
+                let offset = offset_at_position(&text, &edit.range.start, PositionEncoding::Utf16);
+                text.insert_str(offset, &edit.new_text);
+                edits += 1;
+            }
+        }
+
+        assert!(edits < pieces.len());
+        let expected = format!(
+            "// 😀 prompt\na 😀{}\ncd\n}}",
+            "b".repeat(STREAM_BATCH_BYTES)
+        );
+        assert_eq!(text, expected);
+        assert_eq!(insert.range(), Range::new(start, Position::new(2, 2)));
+    }
+}
@@ -1,6 +1,9 @@
 pub mod channel;
 mod error;
+mod insert;
 mod operations;
 
-pub use self::{channel::*, error::BufferOpError, operations::BufferOperation};
+pub use self::{
+    channel::*, error::BufferOpError, insert::StreamedInsert, operations::BufferOperation,
+};
 pub(super) use error::{BufferOpChannelError, BufferOpChannelResult};
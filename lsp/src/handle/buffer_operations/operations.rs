@@ -1,19 +1,28 @@
 use super::BufferOpChannelResult;
-use crate::handle::diagnostics::LspDiagnostic;
+use crate::handle::{diagnostics::LspDiagnostic, outgoing::OutgoingRequests};
 use crossbeam_channel::Sender;
-use lsp_server::{Message, Notification, RequestId, Response};
+use lsp_server::{Message, Notification, Request, RequestId, Response};
 use lsp_types::{
-    ApplyWorkspaceEditParams, GotoDefinitionResponse, HoverContents, ProgressParams,
-    ProgressParamsValue, ProgressToken, PublishDiagnosticsParams, ShowMessageParams,
-    WorkDoneProgress,
+    request::{ApplyWorkspaceEdit, WorkDoneProgressCreate},
+    ApplyWorkspaceEditParams, GotoDefinitionResponse, HoverContents, LogMessageParams,
+    ProgressParams, ProgressParamsValue, ProgressToken, PublishDiagnosticsParams,
+    ShowMessageParams, TextEdit, Uri, WorkDoneProgress, WorkDoneProgressCreateParams,
+    WorkspaceEdit,
 };
-use tracing::{debug, error};
+use std::collections::HashMap;
+use tracing::{debug, error, warn};
 
 #[derive(Debug, Clone)]
 pub enum BufferOperation {
     Diagnostics(LspDiagnostic),
-    WorkDone(WorkDoneProgress),
+    WorkDone {
+        token: ProgressToken,
+        /// Whether the token has to be created before its progress begins
+        create: bool,
+        work: WorkDoneProgress,
+    },
     ShowMessage(ShowMessageParams),
+    LogMessage(LogMessageParams),
     WorkspaceEdit(ApplyWorkspaceEditParams),
     GotoFile {
         id: RequestId,
@@ -23,12 +32,10 @@ pub enum BufferOperation {
         id: RequestId,
         contents: HoverContents,
     },
-}
-
-impl From<WorkDoneProgress> for BufferOperation {
-    fn from(value: WorkDoneProgress) -> Self {
-        Self::WorkDone(value)
-    }
+    /// A request to the client, whose response is waited on by whoever created it
+    ClientRequest(Request),
+    /// Responds to a request with its result, or the error it failed with
+    Response(Response),
 }
 
 impl From<LspDiagnostic> for BufferOperation {
@@ -43,6 +50,12 @@ impl From<ShowMessageParams> for BufferOperation {
     }
 }
 
+impl From<LogMessageParams> for BufferOperation {
+    fn from(value: LogMessageParams) -> Self {
+        Self::LogMessage(value)
+    }
+}
+
 impl From<ApplyWorkspaceEditParams> for BufferOperation {
     fn from(value: ApplyWorkspaceEditParams) -> Self {
         Self::WorkspaceEdit(value)
@@ -50,31 +63,90 @@ impl From<ApplyWorkspaceEditParams> for BufferOperation {
 }
 
 impl BufferOperation {
+    /// Whether the operation is the response to a request from the client
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            Self::GotoFile { .. } | Self::HoverResponse { .. } | Self::Response(_)
+        )
+    }
+
+    /// A workspace edit making the edits to a single document
+    pub fn text_edits(uri: Uri, edits: Vec<TextEdit>) -> Self {
+        Self::WorkspaceEdit(Self::text_edit_params(uri, edits))
+    }
+
+    pub fn text_edit_params(uri: Uri, edits: Vec<TextEdit>) -> ApplyWorkspaceEditParams {
+        let mut changes = HashMap::new();
+        changes.insert(uri, edits);
+        ApplyWorkspaceEditParams {
+            label: None,
+            edit: WorkspaceEdit {
+                changes: Some(changes),
+                ..Default::default()
+            },
+        }
+    }
+
     pub async fn do_operation(
         self,
         sender: Sender<Message>,
+        outgoing: &OutgoingRequests,
     ) -> BufferOpChannelResult<Sender<Message>> {
         match self {
-            BufferOperation::WorkDone(work) => {
-                let method = match work {
-                    WorkDoneProgress::Begin(_) => "window/workDoneProgress/create",
-                    WorkDoneProgress::Report(_) | WorkDoneProgress::End(_) => "$/progress",
-                };
+            BufferOperation::WorkDone {
+                token,
+                create,
+                work,
+            } => {
+                // progress can only be reported once the client has created its token
+                if let (WorkDoneProgress::Begin(_), true) = (&work, create) {
+                    let (request, pending) = outgoing.request::<WorkDoneProgressCreate>(
+                        WorkDoneProgressCreateParams {
+                            token: token.clone(),
+                        },
+                    )?;
+                    sender.send(Message::Request(request))?;
+                    if let Err(err) = pending.response().await {
+                        warn!("client did not create progress token: {}", err);
+                    }
+                }
 
                 sender.send(Message::Notification(Notification {
-                    method: method.to_string(),
+                    method: "$/progress".to_string(),
                     params: serde_json::to_value(ProgressParams {
-                        token: ProgressToken::Number(0),
+                        token,
                         value: ProgressParamsValue::WorkDone(work),
                     })?,
                 }))?;
             }
             BufferOperation::WorkspaceEdit(edit) => {
+                let (request, pending) = outgoing.request::<ApplyWorkspaceEdit>(edit)?;
+                sender.send(Message::Request(request))?;
+                // nothing waits on these edits, so a rejection can only be logged
+                tokio::spawn(async move {
+                    match pending.response().await {
+                        Ok(response) if !response.applied => warn!(
+                            "client rejected workspace edit: {}",
+                            response.failure_reason.unwrap_or_default()
+                        ),
+                        Ok(_) => {}
+                        Err(err) => warn!("workspace edit failed: {}", err),
+                    }
+                });
+            }
+            BufferOperation::LogMessage(message_params) => {
                 sender.send(Message::Notification(Notification {
-                    method: "workspace/applyEdit".to_string(),
-                    params: serde_json::to_value(edit)?,
+                    method: "window/logMessage".to_string(),
+                    params: serde_json::to_value(message_params)?,
                 }))?;
             }
+            BufferOperation::ClientRequest(request) => {
+                sender.send(Message::Request(request))?;
+            }
+            BufferOperation::Response(response) => {
+                sender.send(Message::Response(response))?;
+            }
             BufferOperation::ShowMessage(message_params) => {
                 sender.send(Message::Notification(Notification {
                     method: "window/showMessage".to_string(),
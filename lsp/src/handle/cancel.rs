@@ -0,0 +1,221 @@
+use lsp_server::RequestId;
+use lsp_types::Uri;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Requests which are still being handled, along with the tokens that cancel them.
+/// Kept outside of the state's lock, so that a request can be cancelled while it holds the lock
+#[derive(Debug, Clone, Default)]
+pub struct InFlightRequests(Arc<Mutex<HashMap<RequestId, CancellationToken>>>);
+
+/// Stops tracking its request when dropped
+#[derive(Debug)]
+pub struct TrackedRequest {
+    id: RequestId,
+    in_flight: InFlightRequests,
+}
+
+impl InFlightRequests {
+    /// Tracks the request until the returned [`TrackedRequest`] is dropped
+    pub fn track(&self, id: RequestId) -> TrackedRequest {
+        self.lock().insert(id.clone(), CancellationToken::new());
+        TrackedRequest {
+            id,
+            in_flight: self.clone(),
+        }
+    }
+
+    /// Token that is cancelled along with the request. A request which isn't tracked gets a token
+    /// that is never cancelled
+    pub fn token(&self, id: &RequestId) -> CancellationToken {
+        self.lock().get(id).cloned().unwrap_or_default()
+    }
+
+    /// Returns false if the request was not in flight
+    pub fn cancel(&self, id: &RequestId) -> bool {
+        match self.lock().get(id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns how many requests were cancelled
+    pub fn cancel_all(&self) -> usize {
+        let map = self.lock();
+        map.values().for_each(CancellationToken::cancel);
+        map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<RequestId, CancellationToken>> {
+        // the map is never left half updated, so a poisoned lock is still usable
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// The latest debounced request of each document, a newer request cancels the one before it
+#[derive(Debug, Clone, Default)]
+pub struct Debouncer(Arc<Mutex<HashMap<Uri, CancellationToken>>>);
+
+/// A debounced request, which the debouncer forgets once it is dropped
+#[derive(Debug)]
+pub struct DebouncedRequest {
+    uri: Uri,
+    token: CancellationToken,
+    debouncer: Debouncer,
+}
+
+impl Debouncer {
+    /// Waits for the delay to pass without another request for the document coming in. The
+    /// returned request's token is cancelled along with `cancel`, or when a newer request comes
+    /// in. None if either happened while waiting
+    pub async fn debounce(
+        &self,
+        uri: &Uri,
+        cancel: &CancellationToken,
+        delay: Duration,
+    ) -> Option<DebouncedRequest> {
+        let request = DebouncedRequest {
+            uri: uri.clone(),
+            token: cancel.child_token(),
+            debouncer: self.clone(),
+        };
+        if let Some(previous) = self.lock().insert(uri.clone(), request.token.clone()) {
+            previous.cancel();
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => Some(request),
+            _ = request.token.cancelled() => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uri, CancellationToken>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl DebouncedRequest {
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for DebouncedRequest {
+    fn drop(&mut self) {
+        // the request is done, so it doesn't matter if it is cancelled. The document's entry is
+        // only removed once its request is done or cancelled, a newer one may have replaced it
+        self.token.cancel();
+        let mut map = self.debouncer.lock();
+        if map
+            .get(&self.uri)
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            map.remove(&self.uri);
+        }
+    }
+}
+
+impl Drop for TrackedRequest {
+    fn drop(&mut self) {
+        if self.in_flight.lock().remove(&self.id).is_none() {
+            warn!("request {} was not being tracked", self.id);
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+
+    #[test]
+    fn tracks_and_cancels_requests() {
+        let in_flight = InFlightRequests::default();
+        let first = in_flight.track(RequestId::from(1));
+        let second = in_flight.track(RequestId::from("second".to_owned()));
+
+        assert!(in_flight.cancel(&RequestId::from(1)));
+        assert!(in_flight.token(&RequestId::from(1)).is_cancelled());
+        assert!(!in_flight
+            .token(&RequestId::from("second".to_owned()))
+            .is_cancelled());
+        assert!(!in_flight.cancel(&RequestId::from(3)));
+        assert!(!in_flight.token(&RequestId::from(3)).is_cancelled());
+
+        drop(first);
+        assert!(!in_flight.cancel(&RequestId::from(1)));
+        assert_eq!(in_flight.cancel_all(), 1);
+        drop(second);
+        assert!(in_flight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn newer_requests_supersede_debounced_ones() {
+        let debouncer = Debouncer::default();
+        let uri: Uri = "file:///debounced.rs".parse().unwrap();
+        let delay = Duration::from_millis(50);
+
+        let first = tokio::spawn({
+            let debouncer = debouncer.clone();
+            let uri = uri.clone();
+            async move {
+                debouncer
+                    .debounce(&uri, &CancellationToken::new(), delay)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second = debouncer
+            .debounce(&uri, &CancellationToken::new(), delay)
+            .await;
+        assert!(first.await.unwrap().is_none());
+        let second = second.unwrap();
+        assert!(!second.token().is_cancelled());
+
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        assert!(debouncer.debounce(&uri, &cancelled, delay).await.is_none());
+        assert!(second.token().is_cancelled());
+        assert!(debouncer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn finished_requests_are_forgotten() {
+        let debouncer = Debouncer::default();
+        let uri: Uri = "file:///debounced.rs".parse().unwrap();
+        let delay = Duration::from_millis(10);
+
+        let first = debouncer
+            .debounce(&uri, &CancellationToken::new(), delay)
+            .await
+            .unwrap();
+        let second = debouncer
+            .debounce(&uri, &CancellationToken::new(), delay)
+            .await
+            .unwrap();
+        // the first is superseded, dropping it leaves the second be
+        drop(first);
+        assert!(!second.token().is_cancelled());
+        assert!(!debouncer.is_empty());
+        drop(second);
+        assert!(debouncer.is_empty());
+    }
+}
@@ -0,0 +1,674 @@
+use super::{
+    buffer_operations::{BufferOpChannelSender, BufferOperation},
+    error::HandleResult,
+    requests::run_interact,
+};
+use crate::{
+    database::models::block::DBBlock,
+    error::StateError,
+    interact::{
+        id::{InteractInteger, ScopeId, PROMPT_ID, PUSH_ID, RAG_PUSH_ID},
+        lexer::ParsedComment,
+        registry::InteractRegistry,
+        response::answer_above_edit,
+        InteractError,
+    },
+    state::{document::offset_at_position, LspState, SharedState},
+};
+use anyhow::anyhow;
+use espionox::{
+    agents::memory::OtherRoleTo,
+    prelude::{io_completion, Agent, ListenerTrigger, Message, MessageRole},
+};
+use lsp_server::{ErrorCode, Request, Response};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, Command, ExecuteCommandParams,
+    MessageActionItem, MessageType, Position, Range, ShowMessageParams, ShowMessageRequestParams,
+    TextEdit, Uri,
+};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+pub const RUN_PROMPT: &str = "espx.runPrompt";
+pub const RUN_PROMPT_IN_SCOPE: &str = "espx.runPromptInScope";
+pub const CLEAR_SCOPE: &str = "espx.clearScope";
+pub const DUMP_SCOPE: &str = "espx.dumpScope";
+pub const CONVERT_TO_RAG_PUSH: &str = "espx.convertToRagPush";
+pub const ASK_ABOUT_SELECTION: &str = "espx.askAboutSelection";
+pub const PROMPT: &str = "espx.prompt";
+pub const PUSH: &str = "espx.push";
+pub const SAVE_STATE: &str = "espx.saveState";
+pub const REINDEX_WORKSPACE: &str = "espx.reindexWorkspace";
+
+/// Every command the server can execute
+pub const COMMANDS: [&str; 10] = [
+    RUN_PROMPT,
+    RUN_PROMPT_IN_SCOPE,
+    CLEAR_SCOPE,
+    DUMP_SCOPE,
+    CONVERT_TO_RAG_PUSH,
+    ASK_ABOUT_SELECTION,
+    PROMPT,
+    PUSH,
+    SAVE_STATE,
+    REINDEX_WORKSPACE,
+];
+
+/// Offered when asking about a selection without a question
+const SELECTION_QUESTIONS: [&str; 3] = [
+    "What does this do?",
+    "Are there any bugs in this?",
+    "How could this be improved?",
+];
+
+/// Argument of commands which act on the interact comment at a position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InteractCommandArgs {
+    pub uri: Uri,
+    pub position: Position,
+    /// Token of the scope to run in instead of the comment's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+/// Argument of commands which act on a scope, the document scope being the one of `uri`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScopeCommandArgs {
+    pub uri: Uri,
+    pub scope: String,
+}
+
+/// Argument of the command asking about a selection, whatever is not given is asked for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SelectionCommandArgs {
+    pub uri: Uri,
+    pub range: Range,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub question: Option<String>,
+    /// Token of the scope to ask in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+/// Argument of the command prompting a scope without an interact comment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptCommandArgs {
+    pub uri: Uri,
+    pub scope: String,
+    pub text: String,
+}
+
+/// Argument of the command pushing a range of a document into a scope
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PushCommandArgs {
+    pub uri: Uri,
+    pub scope: String,
+    pub range: Range,
+}
+
+/// Actions on the interact comment at `position`, none if the comment has no interact
+pub fn interact_code_actions(
+    comment: &ParsedComment,
+    uri: &Uri,
+    position: Position,
+    registry: &InteractRegistry,
+) -> Vec<CodeActionOrCommand> {
+    let Ok(integer) = comment.try_get_interact_integer() else {
+        return vec![];
+    };
+    let Some(scope) = registry.get_scope_token(integer.scope) else {
+        return vec![];
+    };
+    let on_comment = serde_json::json!(InteractCommandArgs {
+        uri: uri.clone(),
+        position,
+        scope: None,
+    });
+    let on_scope = serde_json::json!(ScopeCommandArgs {
+        uri: uri.clone(),
+        scope: scope.to_owned(),
+    });
+
+    let mut actions = vec![];
+    match integer.command {
+        PROMPT_ID => {
+            actions.push(command_action("Run prompt", RUN_PROMPT, &on_comment, None));
+            actions.push(command_action(
+                "Run prompt in scope…",
+                RUN_PROMPT_IN_SCOPE,
+                &on_comment,
+                None,
+            ));
+        }
+        PUSH_ID => actions.push(command_action(
+            "Convert push to RAG push",
+            CONVERT_TO_RAG_PUSH,
+            &on_comment,
+            Some(CodeActionKind::REFACTOR_REWRITE),
+        )),
+        _ => {}
+    }
+    actions.push(command_action("Clear scope", CLEAR_SCOPE, &on_scope, None));
+    actions.push(command_action(
+        "Show scope history",
+        DUMP_SCOPE,
+        &on_scope,
+        None,
+    ));
+    actions
+}
+
+/// Offered on any selection which is not empty
+pub fn selection_code_action(uri: &Uri, range: Range) -> CodeActionOrCommand {
+    let on_selection = serde_json::json!(SelectionCommandArgs {
+        uri: uri.clone(),
+        range,
+        question: None,
+        scope: None,
+    });
+    command_action(
+        "Ask about selection",
+        ASK_ABOUT_SELECTION,
+        &on_selection,
+        None,
+    )
+}
+
+fn command_action(
+    title: &str,
+    command: &str,
+    argument: &serde_json::Value,
+    kind: Option<CodeActionKind>,
+) -> CodeActionOrCommand {
+    CodeActionOrCommand::CodeAction(CodeAction {
+        title: title.to_owned(),
+        kind,
+        command: Some(Command {
+            title: title.to_owned(),
+            command: command.to_owned(),
+            arguments: Some(vec![argument.clone()]),
+        }),
+        ..Default::default()
+    })
+}
+
+#[tracing::instrument(name = "execute command", skip_all)]
+pub async fn handle_execute_command(
+    req: Request,
+    state: SharedState,
+    mut sender: BufferOpChannelSender,
+) -> HandleResult<()> {
+    let cancel = state.in_flight.token(&req.id);
+    let params = serde_json::from_value::<ExecuteCommandParams>(req.params)?;
+    sender.use_work_done_token(params.work_done_progress_params.work_done_token.clone());
+    let argument = params
+        .arguments
+        .into_iter()
+        .next()
+        .unwrap_or(serde_json::Value::Null);
+
+    match params.command.as_str() {
+        RUN_PROMPT => {
+            let args = serde_json::from_value(argument)?;
+            run_prompt(args, false, &state, &mut sender, &cancel).await
+        }
+        RUN_PROMPT_IN_SCOPE => {
+            let args = serde_json::from_value(argument)?;
+            run_prompt(args, true, &state, &mut sender, &cancel).await
+        }
+        CLEAR_SCOPE => clear_scope(serde_json::from_value(argument)?, &state, &mut sender).await,
+        DUMP_SCOPE => {
+            let history =
+                dump_scope(serde_json::from_value(argument)?, &state, &mut sender).await?;
+            sender
+                .send_operation(BufferOperation::Response(Response::new_ok(req.id, history)))
+                .await?;
+            Ok(())
+        }
+        CONVERT_TO_RAG_PUSH => {
+            convert_to_rag_push(serde_json::from_value(argument)?, &state, &mut sender).await
+        }
+        ASK_ABOUT_SELECTION => {
+            let args = serde_json::from_value(argument)?;
+            ask_about_selection(args, &state, &mut sender, &cancel).await
+        }
+        PROMPT => {
+            let args = serde_json::from_value(argument)?;
+            if let Some(answer) = prompt(args, &state, &mut sender, &cancel).await? {
+                sender
+                    .send_operation(BufferOperation::Response(Response::new_ok(req.id, answer)))
+                    .await?;
+            }
+            Ok(())
+        }
+        PUSH => push(serde_json::from_value(argument)?, &state, &mut sender).await,
+        SAVE_STATE => save_state(&state, &mut sender).await,
+        REINDEX_WORKSPACE => reindex_workspace(&state, &mut sender).await,
+        command => {
+            warn!("unknown command: {command}");
+            let response = Response::new_err(
+                req.id,
+                ErrorCode::InvalidParams as i32,
+                format!("unknown command: {command}"),
+            );
+            sender
+                .send_operation(BufferOperation::Response(response))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+/// The text in the range of the document, along with the document's extension. Errors if there
+/// is only whitespace in the range
+fn selected_text(state: &LspState, uri: &Uri, range: Range) -> HandleResult<(String, String)> {
+    let doc = state
+        .documents
+        .get(uri)
+        .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+    let start = offset_at_position(&doc.text, &range.start, state.position_encoding);
+    let end = offset_at_position(&doc.text, &range.end, state.position_encoding).max(start);
+    let selected = doc.text[start..end].to_owned();
+    if selected.trim().is_empty() {
+        return Err(anyhow!("nothing selected at {range:?}").into());
+    }
+    Ok((selected, doc.ext().to_owned()))
+}
+
+/// Completes the prompt with the scope's messages before it, adding both the prompt and its
+/// answer to the scope. Returns none if the request was cancelled before the answer came
+async fn complete_in_scope(
+    prompt: Message,
+    scope: ScopeId,
+    uri: &Uri,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+    cancel: &CancellationToken,
+) -> HandleResult<Option<String>> {
+    let r = state.get_read().await;
+    let model = r
+        .agents
+        .as_ref()
+        .ok_or(StateError::AgentsNotPresent)?
+        .completion_model_with_flags(&[])?;
+    let mut completing_agent = Agent::new(None, model);
+    completing_agent.cache = r.agent_ref_from_scope(scope, uri)?.cache.clone();
+    completing_agent.cache.push(prompt.clone());
+    drop(r);
+    sender.release_order();
+
+    sender
+        .start_work_done(Some("Waiting on completion"))
+        .await?;
+    let answer = tokio::select! {
+        answer = completing_agent.do_action(io_completion, (), Option::<ListenerTrigger>::None) => answer?,
+        _ = cancel.cancelled() => {
+            sender.send_work_done_end(Some("Cancelled")).await?;
+            return Ok(None);
+        }
+    };
+    sender.send_work_done_end(Some("Finished")).await?;
+
+    let mut w = state.get_write().await;
+    let agent = w.agent_mut_from_interact_integer(InteractInteger::new(PROMPT_ID, scope), uri)?;
+    agent.cache.push(prompt);
+    agent.cache.push(Message::new_assistant(&answer));
+    Ok(Some(answer))
+}
+
+/// Asks the user to pick one of the options, none if they dismissed the request
+async fn pick(
+    message: &str,
+    options: Vec<String>,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+) -> HandleResult<Option<String>> {
+    let params = ShowMessageRequestParams {
+        typ: MessageType::INFO,
+        message: message.to_owned(),
+        actions: Some(
+            options
+                .into_iter()
+                .map(|title| MessageActionItem {
+                    title,
+                    properties: Default::default(),
+                })
+                .collect(),
+        ),
+    };
+    let picked = sender.show_message_request(&state.outgoing, params).await?;
+    Ok(picked.map(|action| action.title))
+}
+
+fn scope_id(registry: &InteractRegistry, token: &str) -> HandleResult<ScopeId> {
+    Ok(registry
+        .get_scope_id(token)
+        .ok_or(InteractError::InvalidScopeToken(token.to_owned()))?)
+}
+
+fn comment_at(state: &LspState, uri: &Uri, position: &Position) -> HandleResult<ParsedComment> {
+    let doc = state
+        .documents
+        .get(uri)
+        .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+    match doc.tokens.comment_in_position(position) {
+        Some((comment, _)) => Ok(comment.clone()),
+        None => Err(anyhow!("no comment at {position:?}").into()),
+    }
+}
+
+/// Runs the prompt at the position in the given scope, or the one it was written with. When
+/// `pick_scope` is set and no scope was given, the user is asked for one
+async fn run_prompt(
+    args: InteractCommandArgs,
+    pick_scope: bool,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+    cancel: &CancellationToken,
+) -> HandleResult<()> {
+    let r = state.get_read().await;
+    let comment = comment_at(&r, &args.uri, &args.position)?;
+    let mut integer = comment.try_get_interact_integer()?;
+    if integer.command != PROMPT_ID {
+        return Err(anyhow!("no prompt at {:?}", args.position).into());
+    }
+    let scopes: Vec<String> = r
+        .registry
+        .scope_tokens()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+    drop(r);
+
+    let scope = match (args.scope, pick_scope) {
+        (Some(scope), _) => Some(scope),
+        (None, false) => None,
+        (None, true) => match pick(
+            "Which scope should the prompt run in?",
+            scopes,
+            state,
+            sender,
+        )
+        .await?
+        {
+            Some(scope) => Some(scope),
+            // the user dismissed the request
+            None => return Ok(()),
+        },
+    };
+    if let Some(scope) = scope {
+        let r = state.get_read().await;
+        integer = InteractInteger::new(PROMPT_ID, scope_id(&r.registry, &scope)?);
+    }
+
+    run_interact(comment, integer, args.uri, state, sender, cancel).await
+}
+
+async fn clear_scope(
+    args: ScopeCommandArgs,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+) -> HandleResult<()> {
+    let mut w = state.get_write().await;
+    let scope = scope_id(&w.registry, &args.scope)?;
+    let cleared = w.clear_scope(scope, &args.uri)?;
+    drop(w);
+
+    let message = ShowMessageParams {
+        typ: MessageType::INFO,
+        message: format!("Cleared {cleared} messages from scope {}", args.scope),
+    };
+    sender.send_operation(message.into()).await?;
+    Ok(())
+}
+
+/// Shows every message in the scope, oldest first, and returns them
+async fn dump_scope(
+    args: ScopeCommandArgs,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+) -> HandleResult<serde_json::Value> {
+    let r = state.get_read().await;
+    let scope = scope_id(&r.registry, &args.scope)?;
+    let cache = r.agent_ref_from_scope(scope, &args.uri)?.cache.clone();
+    drop(r);
+
+    let history = cache
+        .as_ref()
+        .iter()
+        .map(|message| {
+            // pushed messages are labeled with where they were pushed from
+            let role = match &message.role {
+                MessageRole::Other { alias, .. } => alias.clone(),
+                role => role.to_string(),
+            };
+            format!("## {role}\n{}", message.content.trim())
+        })
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    let message = ShowMessageParams {
+        typ: MessageType::INFO,
+        message: format!("Scope {}:\n\n{history}", args.scope),
+    };
+    sender.send_operation(message.into()).await?;
+    Ok(serde_json::to_value(cache)?)
+}
+
+/// Asks the model about the selected text in a scope, writing the answer as a comment above the
+/// selection
+async fn ask_about_selection(
+    args: SelectionCommandArgs,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+    cancel: &CancellationToken,
+) -> HandleResult<()> {
+    let r = state.get_read().await;
+    if r.agents.is_none() {
+        warn!("no agents");
+        return Ok(());
+    }
+    let scopes: Vec<String> = r
+        .registry
+        .scope_tokens()
+        .into_iter()
+        .map(str::to_owned)
+        .collect();
+    drop(r);
+
+    // the document can change while the user picks, so it is only read once they have
+    let questions = SELECTION_QUESTIONS.map(str::to_owned).to_vec();
+    let question = match args.question {
+        Some(question) => question,
+        None => match pick("What do you want to know?", questions, state, sender).await? {
+            Some(question) => question,
+            None => return Ok(()),
+        },
+    };
+    let scope = match args.scope {
+        Some(scope) => scope,
+        None => match pick("Which scope should it be asked in?", scopes, state, sender).await? {
+            Some(scope) => scope,
+            None => return Ok(()),
+        },
+    };
+
+    let r = state.get_read().await;
+    let scope = scope_id(&r.registry, &scope)?;
+    let (selected, ext) = selected_text(&r, &args.uri, args.range)?;
+    drop(r);
+    let prompt = Message::new_user(&format!("{question}\n```{ext}\n{selected}\n```"));
+    let Some(answer) = complete_in_scope(prompt, scope, &args.uri, state, sender, cancel).await?
+    else {
+        return Ok(());
+    };
+
+    let w = state.get_read().await;
+    let doc = w
+        .documents
+        .get(&args.uri)
+        .ok_or(StateError::DocumentNotPresent(args.uri.clone()))?;
+    let edit = answer_above_edit(&doc.text, doc.ext(), args.range, &answer)?;
+    drop(w);
+
+    let edit = BufferOperation::text_edit_params(args.uri, vec![edit]);
+    let applied = match sender.apply_edit(&state.outgoing, edit).await {
+        Ok(response) => response.applied,
+        Err(err) => {
+            warn!("failed to write answer into the buffer: {}", err);
+            false
+        }
+    };
+    // an answer the client would not write into the buffer is shown instead
+    if !applied {
+        let message = ShowMessageParams {
+            typ: MessageType::INFO,
+            message: answer,
+        };
+        sender.send_operation(message.into()).await?;
+    }
+    Ok(())
+}
+
+/// Shows the answer to the prompt and returns it, none if the request was cancelled first
+async fn prompt(
+    args: PromptCommandArgs,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+    cancel: &CancellationToken,
+) -> HandleResult<Option<String>> {
+    if args.text.trim().is_empty() {
+        return Err(anyhow!("nothing to prompt").into());
+    }
+    let scope = scope_id(&state.get_read().await.registry, &args.scope)?;
+    let prompt = Message::new_user(&args.text);
+    let answer = complete_in_scope(prompt, scope, &args.uri, state, sender, cancel).await?;
+    if let Some(answer) = &answer {
+        let message = ShowMessageParams {
+            typ: MessageType::INFO,
+            message: answer.clone(),
+        };
+        sender.send_operation(message.into()).await?;
+    }
+    Ok(answer)
+}
+
+/// Pushes the range of the document into the scope, replacing what was pushed from the same
+/// range before
+async fn push(
+    args: PushCommandArgs,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+) -> HandleResult<()> {
+    let mut w = state.get_write().await;
+    let (selected, _) = selected_text(&w, &args.uri, args.range)?;
+    let scope = scope_id(&w.registry, &args.scope)?;
+    // labeled with the lines it came from, so pushes from the document's comments, which are
+    // labeled with only the document, never replace it
+    let source = format!(
+        "{} (lines {}-{})",
+        args.uri.as_str(),
+        args.range.start.line + 1,
+        args.range.end.line + 1
+    );
+    let role = MessageRole::Other {
+        alias: source.clone(),
+        coerce_to: OtherRoleTo::User,
+    };
+    let integer = InteractInteger::new(PUSH_ID, scope);
+    let agent = w.agent_mut_from_interact_integer(integer, &args.uri)?;
+    agent.cache.mut_filter_by(&role, false);
+    agent.cache.push(Message {
+        role,
+        content: selected,
+    });
+    drop(w);
+
+    let message = ShowMessageParams {
+        typ: MessageType::INFO,
+        message: format!("Pushed {source} into scope {}", args.scope),
+    };
+    sender.send_operation(message.into()).await?;
+    Ok(())
+}
+
+/// Saves every document and the messages of every scope to the database
+async fn save_state(state: &SharedState, sender: &mut BufferOpChannelSender) -> HandleResult<()> {
+    let r = state.get_read().await;
+    r.save_docs_to_database().await?;
+    r.save_agent_memories_to_database().await?;
+    drop(r);
+
+    let message = ShowMessageParams {
+        typ: MessageType::INFO,
+        message: "Saved state to the database".to_owned(),
+    };
+    sender.send_operation(message.into()).await?;
+    Ok(())
+}
+
+/// Saves the blocks of every document to the database again, embedding them right away rather
+/// than on the next rag push
+async fn reindex_workspace(
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+) -> HandleResult<()> {
+    // embedding takes a while, so nothing is kept locked while it happens
+    let r = state.get_read().await;
+    let db = r.database.clone().ok_or(StateError::DatabaseNotPresent)?;
+    let blocks = r.docs_block_params();
+    let documents = r.documents.len();
+    drop(r);
+
+    sender.start_work_done(Some("Reindexing workspace")).await?;
+    // saving a block drops its embedding
+    DBBlock::save_all(&db, blocks)
+        .await
+        .map_err(StateError::from)?;
+    sender
+        .send_work_done_report(Some("Embedding blocks"), Some(50))
+        .await?;
+    let embedded = DBBlock::embed_missing(&db)
+        .await
+        .map_err(StateError::from)?;
+
+    sender
+        .send_work_done_end(Some(&format!(
+            "Embedded {embedded} blocks from {documents} documents"
+        )))
+        .await?;
+    Ok(())
+}
+
+/// Swaps the push command of the comment for the rag push one, its block is left as it is
+async fn convert_to_rag_push(
+    args: InteractCommandArgs,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+) -> HandleResult<()> {
+    let r = state.get_read().await;
+    let comment = comment_at(&r, &args.uri, &args.position)?;
+    if !comment.is_push() {
+        return Err(anyhow!("no push at {:?}", args.position).into());
+    }
+    let (Some(push), Some(rag_push)) = (
+        r.registry.get_command_token(PUSH_ID),
+        r.registry.get_command_token(RAG_PUSH_ID),
+    ) else {
+        return Err(anyhow!("push commands are not registered").into());
+    };
+    let whitespace_len = comment.content.len() - comment.content.trim_start().len();
+    let edit = TextEdit {
+        range: comment.span_range(
+            whitespace_len..whitespace_len + push.len(),
+            r.position_encoding,
+        ),
+        new_text: rag_push.to_owned(),
+    };
+    drop(r);
+
+    sender
+        .send_operation(BufferOperation::text_edits(args.uri, vec![edit]))
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use super::{
+    buffer_operations::{BufferOpChannelSender, BufferOperation},
+    error::HandleResult,
+};
+use crate::{
+    agents::Agents,
+    config::espx::InlineCompletionConfig,
+    error::StateError,
+    interact::{
+        id::{CommandId, DOCUMENT_ID, GLOBAL_ID, PROMPT_ID, PUSH_ID, RAG_PUSH_ID},
+        registry::InteractRegistry,
+    },
+    state::{document::offset_at_position, LspState, SharedState},
+};
+use anyhow::anyhow;
+use espionox::prelude::{io_completion, ListenerTrigger};
+use lsp_server::{Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionParams, CompletionResponse, CompletionTextEdit,
+    Documentation, InlineCompletionItem, InlineCompletionParams, InlineCompletionResponse,
+    MarkupContent, MarkupKind, Position, Range, TextEdit, Uri,
+};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Most bytes of the document before the cursor that an inline completion is given
+const PREFIX_LIMIT: usize = 4000;
+/// Most bytes of the document after the cursor that an inline completion is given
+const SUFFIX_LIMIT: usize = 1000;
+
+/// Every command, in the order they are offered
+const COMMAND_IDS: [CommandId; 3] = [PROMPT_ID, PUSH_ID, RAG_PUSH_ID];
+
+/// Name and description of the command
+fn command_info(command: CommandId) -> (&'static str, &'static str) {
+    match command {
+        PROMPT_ID => (
+            "Prompt",
+            "Prompts the model, with the scope's messages before the prompt",
+        ),
+        PUSH_ID => (
+            "Push",
+            "Pushes the block of code under the comment into the scope",
+        ),
+        RAG_PUSH_ID => (
+            "RAG Push",
+            "Pushes the blocks most relevant to the query into the scope",
+        ),
+        _ => ("Unknown", "Unknown command"),
+    }
+}
+
+/// Inside a comment, commands are completed at its start and scopes right after a command.
+/// Outside of comments the model completes the code at the cursor, if inline completion is enabled
+/// and the client can't get it through inline completion
+#[tracing::instrument(name = "completion", skip_all)]
+pub async fn handle_completion(
+    req: Request,
+    state: SharedState,
+    mut sender: BufferOpChannelSender,
+) -> HandleResult<()> {
+    let params = serde_json::from_value::<CompletionParams>(req.params)?;
+    let uri = params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+    let cancel = state.in_flight.token(&req.id);
+
+    let r = state.get_read().await;
+    let interact_items = interact_completions(&r, &uri, position)?;
+    let delay = inline_completion_config(&r)
+        .filter(|_| !r.inline_completion_client)
+        .map(|config| Duration::from_millis(config.debounce_ms));
+    drop(r);
+
+    let items = match (interact_items, delay) {
+        (Some(items), _) => items,
+        (None, Some(delay)) => {
+            match fill_in_the_middle(&uri, position, delay, &state, &cancel).await? {
+                Some(completion) => vec![model_completion_item(completion, position)],
+                None if cancel.is_cancelled() => return Ok(()),
+                None => vec![],
+            }
+        }
+        (None, None) => vec![],
+    };
+
+    let response = CompletionResponse::Array(items);
+    sender
+        .send_operation(BufferOperation::Response(Response::new_ok(
+            req.id, response,
+        )))
+        .await?;
+    Ok(())
+}
+
+/// The model completes the code at the cursor
+#[tracing::instrument(name = "inline completion", skip_all)]
+pub async fn handle_inline_completion(
+    req: Request,
+    state: SharedState,
+    mut sender: BufferOpChannelSender,
+) -> HandleResult<()> {
+    let params = serde_json::from_value::<InlineCompletionParams>(req.params)?;
+    let uri = params.text_document_position.text_document.uri;
+    let position = params.text_document_position.position;
+    let cancel = state.in_flight.token(&req.id);
+
+    let r = state.get_read().await;
+    let delay = inline_completion_config(&r)
+        .map(|config| Duration::from_millis(config.debounce_ms))
+        .ok_or(anyhow!("inline completion is not enabled"))?;
+    drop(r);
+
+    let items = match fill_in_the_middle(&uri, position, delay, &state, &cancel).await? {
+        Some(completion) => vec![InlineCompletionItem {
+            insert_text: completion,
+            filter_text: None,
+            range: Some(Range::new(position, position)),
+            command: None,
+            insert_text_format: None,
+        }],
+        None if cancel.is_cancelled() => return Ok(()),
+        None => vec![],
+    };
+
+    let response = InlineCompletionResponse::Array(items);
+    sender
+        .send_operation(BufferOperation::Response(Response::new_ok(
+            req.id, response,
+        )))
+        .await?;
+    Ok(())
+}
+
+/// Only when a model is configured with inline completion enabled
+fn inline_completion_config(state: &LspState) -> Option<&InlineCompletionConfig> {
+    state
+        .agents
+        .as_ref()
+        .map(|agents| &agents.config.inline_completion)
+        .filter(|config| config.enabled)
+}
+
+/// Asks the model for the code at the cursor, once typing has paused for the delay. None if the
+/// request was cancelled or superseded by a newer one for the document, or the model had nothing
+/// to add
+async fn fill_in_the_middle(
+    uri: &Uri,
+    position: Position,
+    delay: Duration,
+    state: &SharedState,
+    cancel: &CancellationToken,
+) -> HandleResult<Option<String>> {
+    let Some(debounced) = state.debouncer.debounce(uri, cancel, delay).await else {
+        return Ok(None);
+    };
+    let cancel = debounced.token();
+
+    let r = state.get_read().await;
+    let doc = r
+        .documents
+        .get(uri)
+        .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+    let offset = offset_at_position(&doc.text, &position, r.position_encoding);
+    let (prefix, suffix) = around_cursor(&doc.text, offset);
+    let mut agent = r
+        .agents
+        .as_ref()
+        .ok_or(StateError::AgentsNotPresent)?
+        .inline_completion_agent(uri, prefix, suffix)?;
+    drop(r);
+
+    let completion = tokio::select! {
+        completion = agent.do_action(io_completion, (), Option::<ListenerTrigger>::None) => completion?,
+        _ = cancel.cancelled() => return Ok(None),
+    };
+    let completion = strip_code_fence(&completion);
+    Ok((!completion.trim().is_empty()).then(|| completion.to_owned()))
+}
+
+/// The text before and after the offset, each cut down to its limit on a char boundary
+fn around_cursor(text: &str, offset: usize) -> (&str, &str) {
+    let mut start = offset.saturating_sub(PREFIX_LIMIT);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    let mut end = (offset + SUFFIX_LIMIT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&text[start..offset], &text[offset..end])
+}
+
+/// Models wrap code in a code block even when they are told not to
+fn strip_code_fence(completion: &str) -> &str {
+    let trimmed = completion.trim();
+    if !trimmed.starts_with("```") {
+        return completion;
+    }
+    let body = trimmed.split_once('\n').map_or("", |(_, body)| body);
+    body.strip_suffix("```").unwrap_or(body).trim_end()
+}
+
+/// Labelled with the first non-blank line of the completion, the whole of it is inserted at the
+/// cursor
+fn model_completion_item(completion: String, position: Position) -> CompletionItem {
+    CompletionItem {
+        label: completion
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .to_owned(),
+        kind: Some(CompletionItemKind::TEXT),
+        detail: Some("Model completion".to_owned()),
+        text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+            range: Range::new(position, position),
+            new_text: completion,
+        })),
+        ..Default::default()
+    }
+}
+
+/// None outside of comments
+fn interact_completions(
+    state: &LspState,
+    uri: &Uri,
+    position: Position,
+) -> HandleResult<Option<Vec<CompletionItem>>> {
+    let doc = state
+        .documents
+        .get(uri)
+        .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+    let Some((comment, _)) = doc.tokens.comment_in_position(&position) else {
+        return Ok(None);
+    };
+    let encoding = state.position_encoding;
+    let start = offset_at_position(&doc.text, &comment.range.start, encoding);
+    let end = offset_at_position(&doc.text, &position, encoding).max(start);
+    let before = doc.text[start..end].trim_start();
+
+    if before.is_empty() {
+        return Ok(Some(command_items(&state.registry)));
+    }
+
+    let Some(token) = COMMAND_IDS
+        .iter()
+        .filter_map(|command| state.registry.get_command_token(*command))
+        .find(|token| before.starts_with(token))
+    else {
+        return Ok(Some(vec![]));
+    };
+    let typed = &before[token.len()..];
+    // the scope ends at the first whitespace, after it there is nothing left to complete
+    if typed.contains(char::is_whitespace) {
+        return Ok(Some(vec![]));
+    }
+    let replaced = Range::new(
+        Position::new(
+            position.line,
+            position.character - encoding.str_len(typed) as u32,
+        ),
+        position,
+    );
+    Ok(Some(scope_items(state, uri, replaced)))
+}
+
+fn command_items(registry: &InteractRegistry) -> Vec<CompletionItem> {
+    COMMAND_IDS
+        .iter()
+        .filter_map(|command| {
+            let token = registry.get_command_token(*command)?;
+            let (name, description) = command_info(*command);
+            Some(CompletionItem {
+                label: token.to_owned(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: Some(name.to_owned()),
+                documentation: Some(Documentation::String(description.to_owned())),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Every registered scope, documented with its system prompt and how many messages it holds
+fn scope_items(state: &LspState, uri: &Uri, replaced: Range) -> Vec<CompletionItem> {
+    state
+        .registry
+        .scope_tokens()
+        .into_iter()
+        .filter_map(|token| {
+            let scope = state.registry.get_scope_id(token)?;
+            let name = match scope {
+                GLOBAL_ID => "Global",
+                DOCUMENT_ID => "Document",
+                _ => "Custom",
+            };
+            let documentation = match state.agent_ref_from_scope(scope, uri) {
+                Ok(agent) => {
+                    let system_prompt = Agents::system_prompt(agent).unwrap_or("none");
+                    format!(
+                        "**System prompt:** {system_prompt}\n\n**Messages:** {}",
+                        agent.cache.len()
+                    )
+                }
+                Err(StateError::AgentsNotPresent) => "No model is configured".to_owned(),
+                Err(_) => "No messages yet".to_owned(),
+            };
+            Some(CompletionItem {
+                label: token.to_owned(),
+                kind: Some(CompletionItemKind::MODULE),
+                detail: Some(name.to_owned()),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: documentation,
+                })),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range: replaced,
+                    new_text: token.to_owned(),
+                })),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+
+    #[test]
+    fn cursor_context_is_cut_on_char_boundaries() {
+        // the limit falls inside of the é
+        let text = format!(
+            "é{}{}",
+            "a".repeat(PREFIX_LIMIT - 1),
+            "b".repeat(SUFFIX_LIMIT)
+        );
+        let offset = text.find('b').unwrap();
+        let (prefix, suffix) = around_cursor(&text, offset);
+        assert_eq!(prefix, "a".repeat(PREFIX_LIMIT - 1));
+        assert_eq!(suffix, "b".repeat(SUFFIX_LIMIT));
+
+        let (prefix, suffix) = around_cursor("fn main() {}", 3);
+        assert_eq!((prefix, suffix), ("fn ", "main() {}"));
+    }
+
+    #[test]
+    fn code_fences_are_stripped_from_completions() {
+        assert_eq!(
+            strip_code_fence("```rust\nlet raw = String::new();\n```\n"),
+            "let raw = String::new();"
+        );
+        assert_eq!(strip_code_fence("```\n```"), "");
+        assert_eq!(strip_code_fence(" raw.trim()"), " raw.trim()");
+    }
+
+    #[test]
+    fn model_completions_are_labelled_with_their_first_non_blank_line() {
+        let item = model_completion_item("\n  \n    raw.trim()\n}".to_owned(), Position::new(1, 4));
+        assert_eq!(item.label, "raw.trim()");
+    }
+}
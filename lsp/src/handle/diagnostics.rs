@@ -1,12 +1,28 @@
 use crate::{
     interact::{
-        id::{human_readable_int, InteractID},
-        lexer::Token,
+        id::{human_readable_int, DOCUMENT_ID, PROMPT_ID, PUSH_ID},
+        lexer::{ParsedComment, Token},
     },
     state::LspState,
 };
-use anyhow::Ok;
-use lsp_types::{Diagnostic, DiagnosticSeverity, PublishDiagnosticsParams, Uri};
+use anyhow::{anyhow, Ok};
+use lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DocumentDiagnosticReport,
+    FullDocumentDiagnosticReport, Location, NumberOrString, PreviousResultId,
+    PublishDiagnosticsParams, Range, RelatedFullDocumentDiagnosticReport,
+    RelatedUnchangedDocumentDiagnosticReport, UnchangedDocumentDiagnosticReport, Uri,
+    WorkspaceDiagnosticReport, WorkspaceDocumentDiagnosticReport,
+    WorkspaceFullDocumentDiagnosticReport, WorkspaceUnchangedDocumentDiagnosticReport,
+};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+pub const INTERACT_CODE: &str = "interact";
+pub const INVALID_FLAG_CODE: &str = "invalid-flag";
+pub const UNKNOWN_SCOPE_CODE: &str = "unknown-scope";
+pub const EMPTY_PROMPT_CODE: &str = "empty-prompt";
+pub const PUSH_WITHOUT_BLOCK_CODE: &str = "push-without-block";
+pub const REDUNDANT_PUSH_CODE: &str = "redundant-push";
+pub const NO_MODEL_CODE: &str = "no-model";
 
 #[derive(Debug, Clone)]
 pub enum LspDiagnostic {
@@ -17,8 +33,7 @@ pub enum LspDiagnostic {
 impl LspDiagnostic {
     #[tracing::instrument(name = "diagnosing document", skip_all)]
     pub fn diagnose_document(uri: Uri, store: &mut LspState) -> anyhow::Result<LspDiagnostic> {
-        let mut all_diagnostics = vec![];
-        let tokens = store.documents.get(&uri).unwrap();
+        let all_diagnostics = Self::document_diagnostics(&uri, store)?;
         // if let Some(burns) = store.burns.read_burns_on_doc(&uri) {
         //     debug!("got burns on doc: {:?}", burns);
         //     for burn in burns {
@@ -39,20 +54,6 @@ impl LspDiagnostic {
         //
         // }
 
-        let severity = Some(DiagnosticSeverity::HINT);
-        for token in tokens.as_ref() {
-            if let Token::Comment(comment) = token {
-                if let Some(int) = comment.try_get_interact_integer().ok() {
-                    all_diagnostics.push(Diagnostic {
-                        range: comment.range,
-                        severity,
-                        message: format!("{}", human_readable_int(int)),
-                        ..Default::default()
-                    });
-                }
-            }
-        }
-
         let lsp_diagnostic = match all_diagnostics.is_empty() {
             true => LspDiagnostic::ClearDiagnostics(uri),
             false => {
@@ -119,4 +120,228 @@ impl LspDiagnostic {
 
         // Ok(all_diagnostics)
     }
+
+    /// Every diagnostic of the document, in the order they appear in it
+    pub fn document_diagnostics(uri: &Uri, store: &LspState) -> anyhow::Result<Vec<Diagnostic>> {
+        let mut all_diagnostics = vec![];
+        let tokens = &store
+            .documents
+            .get(uri)
+            .ok_or(anyhow!("document not present"))?
+            .tokens;
+
+        let encoding = tokens.encoding();
+        for (idx, token) in tokens.as_ref().iter().enumerate() {
+            let Token::Comment(comment) = token else {
+                continue;
+            };
+            let int = match comment.try_get_interact_integer() {
+                Result::Ok(int) => int,
+                Err(_) => {
+                    if let Some((_, span)) = store.registry.try_get_unknown_scope(&comment.content)
+                    {
+                        let scope = &comment.content[span.clone()];
+                        all_diagnostics.push(validation_diagnostic(
+                            uri,
+                            comment,
+                            comment.span_range(span.clone(), encoding),
+                            DiagnosticSeverity::ERROR,
+                            UNKNOWN_SCOPE_CODE,
+                            format!("Unknown scope `{scope}`"),
+                            format!(
+                                "Registered scopes are: {}",
+                                store.registry.scope_tokens().join(", ")
+                            ),
+                        ));
+                    }
+                    continue;
+                }
+            };
+
+            all_diagnostics.push(validation_diagnostic(
+                uri,
+                comment,
+                comment.range,
+                DiagnosticSeverity::HINT,
+                INTERACT_CODE,
+                human_readable_int(int, &store.registry),
+                "Hover the interact to see what is in its scope".to_owned(),
+            ));
+
+            for arg in comment.args.iter() {
+                // flags that can't be used with the configured provider are errors too
+                let message = match arg.parse_flag() {
+                    Err(err) => err.to_string(),
+                    Result::Ok(flag) => match store.agents.as_ref() {
+                        Some(agents) => match agents.validate_flag(&flag) {
+                            Err(err) => err.to_string(),
+                            Result::Ok(_) => continue,
+                        },
+                        None => continue,
+                    },
+                };
+                all_diagnostics.push(validation_diagnostic(
+                    uri,
+                    comment,
+                    comment.arg_range(arg, encoding),
+                    DiagnosticSeverity::ERROR,
+                    INVALID_FLAG_CODE,
+                    message,
+                    "Flags are --model, --temperature and --max-tokens".to_owned(),
+                ));
+            }
+
+            if store.agents.is_none() {
+                all_diagnostics.push(validation_diagnostic(
+                    uri,
+                    comment,
+                    comment.range,
+                    DiagnosticSeverity::ERROR,
+                    NO_MODEL_CODE,
+                    "No model is configured, so this interact does nothing".to_owned(),
+                    "Add a [model] section to espx-ls.toml".to_owned(),
+                ));
+            }
+
+            match int.command {
+                PROMPT_ID => {
+                    let empty = comment
+                        .text_for_interact(encoding)
+                        .is_none_or(|(_, text)| text.trim().is_empty());
+                    if empty {
+                        all_diagnostics.push(validation_diagnostic(
+                            uri,
+                            comment,
+                            comment.range,
+                            DiagnosticSeverity::WARNING,
+                            EMPTY_PROMPT_CODE,
+                            "Prompt is empty".to_owned(),
+                            "Write the prompt after the interact".to_owned(),
+                        ));
+                    }
+                }
+                PUSH_ID if int.scope == DOCUMENT_ID => {
+                    all_diagnostics.push(validation_diagnostic(
+                        uri,
+                        comment,
+                        comment.range,
+                        DiagnosticSeverity::ERROR,
+                        REDUNDANT_PUSH_CODE,
+                        "Pushing into the document scope is redundant".to_owned(),
+                        "The document's agent already has the whole document".to_owned(),
+                    ));
+                }
+                PUSH_ID if !matches!(tokens.get(idx + 1), Some(Token::Block(_))) => {
+                    all_diagnostics.push(validation_diagnostic(
+                        uri,
+                        comment,
+                        comment.range,
+                        DiagnosticSeverity::WARNING,
+                        PUSH_WITHOUT_BLOCK_CODE,
+                        "Nothing follows this push".to_owned(),
+                        "Pushes push the block of code right after them".to_owned(),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(all_diagnostics)
+    }
+
+    /// Report of a document pulled by the client, which is unchanged if its diagnostics are the
+    /// same as those in the client's previous report
+    pub fn document_report(
+        uri: &Uri,
+        previous_result_id: Option<&str>,
+        store: &LspState,
+    ) -> anyhow::Result<DocumentDiagnosticReport> {
+        let diagnostics = Self::document_diagnostics(uri, store)?;
+        let id = result_id(&diagnostics);
+        let report = match previous_result_id == Some(id.as_str()) {
+            true => DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                    result_id: id,
+                },
+            }),
+            false => DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: Some(id),
+                    items: diagnostics,
+                },
+            }),
+        };
+        Ok(report)
+    }
+
+    /// Reports of every document the server knows of
+    pub fn workspace_report(
+        previous_result_ids: &[PreviousResultId],
+        store: &LspState,
+    ) -> anyhow::Result<WorkspaceDiagnosticReport> {
+        let mut uris: Vec<&Uri> = store.documents.keys().collect();
+        uris.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        let mut items = vec![];
+        for uri in uris {
+            let previous = previous_result_ids
+                .iter()
+                .find(|previous| previous.uri == *uri)
+                .map(|previous| previous.value.as_str());
+            let item = match Self::document_report(uri, previous, store)? {
+                DocumentDiagnosticReport::Full(report) => {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri: uri.clone(),
+                        version: None,
+                        full_document_diagnostic_report: report.full_document_diagnostic_report,
+                    })
+                }
+                DocumentDiagnosticReport::Unchanged(report) => {
+                    WorkspaceDocumentDiagnosticReport::Unchanged(
+                        WorkspaceUnchangedDocumentDiagnosticReport {
+                            uri: uri.clone(),
+                            version: None,
+                            unchanged_document_diagnostic_report: report
+                                .unchanged_document_diagnostic_report,
+                        },
+                    )
+                }
+            };
+            items.push(item);
+        }
+        Ok(WorkspaceDiagnosticReport { items })
+    }
+}
+
+/// Diagnostic with a code, whose related information points back at the comment it is about
+fn validation_diagnostic(
+    uri: &Uri,
+    comment: &ParsedComment,
+    range: Range,
+    severity: DiagnosticSeverity,
+    code: &str,
+    message: String,
+    related: String,
+) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        code: Some(NumberOrString::String(code.to_owned())),
+        message,
+        related_information: Some(vec![DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), comment.range),
+            message: related,
+        }]),
+        ..Default::default()
+    }
+}
+
+/// Identifies a set of diagnostics, reports with the same diagnostics get the same id
+fn result_id(diagnostics: &[Diagnostic]) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(diagnostics)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("{:x}", hasher.finish())
 }
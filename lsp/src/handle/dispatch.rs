@@ -0,0 +1,194 @@
+use super::{
+    buffer_operations::{BufferOpChannelError, BufferOpChannelStatus},
+    error::HandleResult,
+    notifications, requests,
+};
+use crate::state::SharedState;
+use crossbeam_channel::Sender;
+use lsp_server::{Message, Notification};
+use lsp_types::{MessageType, ShowMessageParams, Uri};
+use std::{collections::HashMap, str::FromStr};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// What a message has to stay ordered with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DispatchKey {
+    Document(Uri),
+    /// Scopes are pushed into, prompted and read from every document
+    Scopes,
+}
+
+/// Every key of the message, messages without any are never made to wait
+pub fn dispatch_keys(msg: &Message) -> Vec<DispatchKey> {
+    let (method, params) = match msg {
+        Message::Request(req) => (req.method.as_str(), &req.params),
+        Message::Notification(noti) => (noti.method.as_str(), &noti.params),
+        Message::Response(_) => return vec![],
+    };
+
+    // commands are given the document they act on as their argument
+    let uri_pointer = match method {
+        "workspace/executeCommand" => "/arguments/0/uri",
+        _ => "/textDocument/uri",
+    };
+    let mut keys: Vec<DispatchKey> = params
+        .pointer(uri_pointer)
+        .and_then(|uri| uri.as_str())
+        .and_then(|uri| Uri::from_str(uri).ok())
+        .map(DispatchKey::Document)
+        .into_iter()
+        .collect();
+
+    // syncing a document updates what it pushed into scopes, and hover summarizes them
+    if matches!(
+        method,
+        "textDocument/definition"
+            | "workspace/executeCommand"
+            | "textDocument/didOpen"
+            | "textDocument/didChange"
+            | "textDocument/didSave"
+            | "textDocument/hover"
+    ) {
+        keys.push(DispatchKey::Scopes);
+    }
+    keys
+}
+
+/// Messages which wait on the ones before them, but are never waited on. Completions can take as
+/// long as the model does and never change the state, so edits coming after them don't wait
+pub fn holds_back_later_messages(msg: &Message) -> bool {
+    !matches!(
+        msg,
+        Message::Request(req)
+            if matches!(req.method.as_str(), "textDocument/completion" | "textDocument/inlineCompletion")
+    )
+}
+
+/// Handles messages concurrently, writing the buffer operations of each one to the connection as
+/// they come. A message only waits on the messages before it that share one of its keys, and only
+/// until they are done changing the state
+pub struct Dispatcher {
+    state: SharedState,
+    sender: Sender<Message>,
+    /// Cancelled once the latest message with the key is done changing the state
+    latest: HashMap<DispatchKey, CancellationToken>,
+    tasks: JoinSet<()>,
+}
+
+impl Dispatcher {
+    pub fn new(state: SharedState, sender: Sender<Message>) -> Self {
+        Self {
+            state,
+            sender,
+            latest: HashMap::new(),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    pub fn dispatch(&mut self, msg: Message) {
+        self.latest.retain(|_, done| !done.is_cancelled());
+        while self.tasks.try_join_next().is_some() {}
+
+        // cancellations don't wait their turn. Requests are tracked from here on, so one that is
+        // still waiting on the messages before it can be cancelled as well
+        if let Message::Notification(noti) = &msg {
+            match notifications::handle_cancel(noti, &self.state) {
+                Ok(false) => {}
+                Ok(true) => return,
+                Err(err) => {
+                    warn!("failed to cancel: {}", err);
+                    return;
+                }
+            }
+        }
+        let tracked = match &msg {
+            Message::Request(req) => Some(self.state.in_flight.track(req.id.clone())),
+            _ => None,
+        };
+
+        let done = CancellationToken::new();
+        let holds_back = holds_back_later_messages(&msg);
+        let previous: Vec<CancellationToken> = dispatch_keys(&msg)
+            .into_iter()
+            .filter_map(|key| match holds_back {
+                true => self.latest.insert(key, done.clone()),
+                false => self.latest.get(&key).cloned(),
+            })
+            .collect();
+
+        let state = self.state.clone();
+        let sender = self.sender.clone();
+        self.tasks.spawn(async move {
+            let _tracked = tracked;
+            let _done = done.clone().drop_guard();
+            for previous in previous {
+                previous.cancelled().await;
+            }
+            if let Err(err) = handle_message(msg, state, sender, &done).await {
+                warn!("error handling message: {}", err);
+            }
+        });
+    }
+
+    /// Waits for every message dispatched so far to be handled
+    pub async fn wait_idle(&mut self) {
+        while self.tasks.join_next().await.is_some() {}
+    }
+}
+
+/// `done` is cancelled as soon as the handler releases its order, the messages after it don't
+/// wait on it streaming from the model or waiting on the client
+async fn handle_message(
+    msg: Message,
+    state: SharedState,
+    mut sender: Sender<Message>,
+    done: &CancellationToken,
+) -> HandleResult<()> {
+    let outgoing = state.outgoing.clone();
+    match match msg {
+        Message::Response(response) => {
+            outgoing.respond(response);
+            return Ok(());
+        }
+        Message::Notification(noti) => notifications::handle_notification(noti, state).await,
+        Message::Request(req) => requests::handle_request(req, state).await,
+    } {
+        Ok(mut buffer_op_channel_handler) => {
+            let released = buffer_op_channel_handler.sender.order_released();
+            loop {
+                let status = tokio::select! {
+                    status = buffer_op_channel_handler.receiver.recv() => status,
+                    _ = released.cancelled(), if !done.is_cancelled() => {
+                        done.cancel();
+                        continue;
+                    }
+                };
+                let Some(status) = status else {
+                    break;
+                };
+                match status? {
+                    BufferOpChannelStatus::Finished => break,
+                    BufferOpChannelStatus::Working(buffer_op) => {
+                        sender = buffer_op.do_operation(sender, &outgoing).await?;
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            warn!("error in handler: {}", err);
+            sender
+                .send(Message::Notification(Notification {
+                    method: "window/showMessage".to_string(),
+                    params: serde_json::to_value(ShowMessageParams {
+                        typ: MessageType::ERROR,
+                        message: format!("Handler encounted an error: {}", err),
+                    })?,
+                }))
+                .map_err(BufferOpChannelError::from)?;
+        }
+    }
+    debug!("finished processing message");
+    Ok(())
+}
@@ -1,9 +1,11 @@
-use lsp_types::{MessageType, ShowMessageParams};
+use lsp_server::{ErrorCode, RequestId, Response, ResponseError};
+use lsp_types::{LogMessageParams, MessageType, ShowMessageParams};
 
 use super::buffer_operations::{
     BufferOpChannelError, BufferOpChannelSender, BufferOpError, BufferOperation,
 };
 use crate::{
+    agents::error::AgentsError,
     error::{error_chain_fmt, StateError},
     interact::InteractError,
 };
@@ -18,6 +20,7 @@ pub enum HandleError {
     BufferOp(#[from] BufferOpError),
     EspxAgent(#[from] espionox::agents::error::AgentError),
     Interact(#[from] InteractError),
+    Agents(#[from] AgentsError),
     State(#[from] StateError),
 }
 
@@ -36,6 +39,7 @@ impl Display for HandleError {
             Self::Json(err) => err.to_string(),
             Self::State(err) => err.to_string(),
             Self::Interact(err) => err.to_string(),
+            Self::Agents(err) => err.to_string(),
         };
         write!(f, "{}", display)
     }
@@ -48,6 +52,22 @@ impl From<BufferOpChannelError> for HandleError {
 }
 
 impl HandleError {
+    /// How the failure of a request is responded to
+    pub fn response_error(&self) -> ResponseError {
+        let code = match self {
+            Self::Json(_) => ErrorCode::InvalidParams,
+            Self::Interact(_) | Self::State(_) | Self::Agents(_) | Self::EspxAgent(_) => {
+                ErrorCode::RequestFailed
+            }
+            Self::Undefined(_) | Self::BufferOp(_) => ErrorCode::InternalError,
+        };
+        ResponseError {
+            code: code as i32,
+            message: self.to_string(),
+            data: None,
+        }
+    }
+
     pub async fn notification_err(
         self,
         task_sender: &mut BufferOpChannelSender,
@@ -60,13 +80,68 @@ impl HandleError {
             .await
             .map_err(|err| err.into())
     }
-    pub async fn request_err(self, task_sender: &mut BufferOpChannelSender) -> HandleResult<()> {
+
+    /// Responds to the request with the error, logging the whole chain of it. Errors after the
+    /// handler already responded are only logged
+    pub async fn request_err(
+        self,
+        id: RequestId,
+        task_sender: &mut BufferOpChannelSender,
+    ) -> HandleResult<()> {
         task_sender
-            .send_operation(BufferOperation::ShowMessage(ShowMessageParams {
+            .send_operation(BufferOperation::LogMessage(LogMessageParams {
                 typ: MessageType::ERROR,
                 message: format!("An error occured in request handler: {self:?}"),
             }))
+            .await?;
+        if task_sender.has_responded() {
+            return Ok(());
+        }
+        task_sender
+            .send_operation(BufferOperation::Response(Response {
+                id,
+                result: None,
+                error: Some(self.response_error()),
+            }))
             .await
             .map_err(|err| err.into())
     }
 }
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+    #[allow(unused)]
+    use crate::handle::buffer_operations::{BufferOpChannelHandler, BufferOpChannelStatus};
+
+    #[tokio::test]
+    async fn errors_after_responding_are_only_logged() {
+        let mut handler = BufferOpChannelHandler::new();
+        let mut sender = handler.sender.clone();
+        let id = RequestId::from(1);
+
+        sender
+            .send_operation(BufferOperation::Response(Response::new_ok(
+                id.clone(),
+                serde_json::Value::Null,
+            )))
+            .await
+            .unwrap();
+        HandleError::from(anyhow::anyhow!("failed after responding"))
+            .request_err(id, &mut sender)
+            .await
+            .unwrap();
+
+        let mut responses = 0;
+        let mut logs = 0;
+        while let Ok(Ok(BufferOpChannelStatus::Working(op))) = handler.receiver.try_recv() {
+            match op {
+                BufferOperation::Response(_) => responses += 1,
+                BufferOperation::LogMessage(_) => logs += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(responses, 1);
+        assert_eq!(logs, 1);
+    }
+}
@@ -2,9 +2,14 @@ use crate::handle::{buffer_operations::BufferOpChannelHandler, error::HandleResu
 use lsp_server::Message as LSPMessage;
 use tracing::warn;
 pub mod buffer_operations;
+pub mod cancel;
+pub mod commands;
+pub mod completion;
 pub mod diagnostics;
+pub mod dispatch;
 pub mod error;
 pub mod notifications;
+pub mod outgoing;
 pub mod requests;
 
 pub fn handle_other(msg: LSPMessage) -> HandleResult<BufferOpChannelHandler> {
@@ -8,8 +8,11 @@ use crate::{
     state::SharedState,
 };
 use anyhow::anyhow;
-use lsp_server::Notification;
-use lsp_types::{DidChangeTextDocumentParams, DidSaveTextDocumentParams, TextDocumentItem};
+use lsp_server::{Notification, RequestId};
+use lsp_types::{
+    CancelParams, DidChangeTextDocumentParams, DidSaveTextDocumentParams, NumberOrString,
+    TextDocumentItem,
+};
 use tracing::{debug, warn};
 
 #[derive(serde::Deserialize, Debug)]
@@ -18,6 +21,12 @@ struct TextDocumentOpen {
     text_document: TextDocumentItem,
 }
 
+/// Params of `espx/cancel`, which cancels every request in flight when no id is given
+#[derive(serde::Deserialize, Debug, Default)]
+struct EspxCancelParams {
+    id: Option<NumberOrString>,
+}
+
 #[tracing::instrument(name = "handle notification", skip_all)]
 pub async fn handle_notification(
     noti: Notification,
@@ -32,6 +41,7 @@ pub async fn handle_notification(
             "textDocument/didChange" => handle_didChange(noti, state, task_sender.clone()).await,
             "textDocument/didSave" => handle_didSave(noti, state, task_sender.clone()).await,
             "textDocument/didOpen" => handle_didOpen(noti, state, task_sender.clone()).await,
+            "$/cancelRequest" | "espx/cancel" => handle_cancel(&noti, &state).map(|_| ()),
             s => {
                 debug!("unhandled notification: {:?}", s);
                 Ok(())
@@ -53,27 +63,53 @@ pub async fn handle_notification(
     return Ok(handle);
 }
 
+/// Cancels requests for `$/cancelRequest` and `espx/cancel`, returning false for any other
+/// notification. Never touches the state's lock, so it can be done as soon as the notification
+/// is read
+pub fn handle_cancel(noti: &Notification, state: &SharedState) -> HandleResult<bool> {
+    let request_id = |id: NumberOrString| match id {
+        NumberOrString::Number(int) => RequestId::from(int),
+        NumberOrString::String(str) => RequestId::from(str),
+    };
+    let id = match noti.method.as_str() {
+        "$/cancelRequest" => Some(serde_json::from_value::<CancelParams>(noti.params.clone())?.id),
+        "espx/cancel" => serde_json::from_value::<Option<EspxCancelParams>>(noti.params.clone())?
+            .and_then(|params| params.id),
+        _ => return Ok(false),
+    };
+
+    match id.map(request_id) {
+        Some(id) => {
+            if !state.in_flight.cancel(&id) {
+                debug!("request {id} was not in flight, nothing to cancel");
+            }
+        }
+        None => {
+            let amt = state.in_flight.cancel_all();
+            debug!("cancelled {amt} requests");
+        }
+    }
+    Ok(true)
+}
+
 #[allow(non_snake_case)]
 #[tracing::instrument(name = "didChange", skip_all)]
-async fn handle_didChange(
+pub async fn handle_didChange(
     noti: Notification,
-    mut state: SharedState,
+    state: SharedState,
     mut sender: BufferOpChannelSender,
 ) -> HandleResult<()> {
     let text_document_changes: DidChangeTextDocumentParams = serde_json::from_value(noti.params)?;
     let uri = text_document_changes.text_document.uri;
-    //BAD!
-    let ext = uri.clone().as_str().to_string();
-    let ext = ext.rsplit_once('.').unwrap().1;
 
-    let mut w = state.get_write()?;
-    if text_document_changes.content_changes.len() > 1 {
-        warn!("more than a single change recieved in notification");
-    }
+    let mut w = state.get_write().await;
+    w.update_doc_and_agents_from_changes(uri.clone(), &text_document_changes.content_changes)?;
 
-    // sender
-    //     .send_operation(LspDiagnostic::diagnose_document(uri, &mut w.store)?.into())
-    //     .await?;
+    if !w.pull_diagnostics {
+        sender
+            .send_operation(LspDiagnostic::diagnose_document(uri, &mut w)?.into())
+            .await?;
+    }
     Ok(())
 }
 
@@ -81,7 +117,7 @@ async fn handle_didChange(
 #[tracing::instrument(name = "didSave", skip_all)]
 pub async fn handle_didSave(
     noti: Notification,
-    mut state: SharedState,
+    state: SharedState,
     mut sender: BufferOpChannelSender,
 ) -> HandleResult<()> {
     let saved_text_doc: DidSaveTextDocumentParams =
@@ -91,7 +127,11 @@ pub async fn handle_didSave(
         .ok_or(HandleError::Undefined(anyhow!("No text on didSave noti")))?;
     let uri = saved_text_doc.text_document.uri;
 
-    let mut w = state.get_write()?;
+    sender
+        .start_work_done(Some("Updating Document Tokens"))
+        .await?;
+
+    let mut w = state.get_write().await;
     warn!("updating");
     w.update_doc_and_agents_from_text(uri.clone(), text)?;
     warn!("done updating");
@@ -108,44 +148,35 @@ pub async fn handle_didSave(
     // agent.cache.mut_filter_by(&role, false);
 
     sender
-        .send_work_done_report(Some("Updated Document Tokens"), None)
+        .send_work_done_end(Some("Updated Document Tokens"))
         .await?;
 
-    sender
-        .send_operation(LspDiagnostic::diagnose_document(uri, &mut w)?.into())
-        .await?;
+    if !w.pull_diagnostics {
+        sender
+            .send_operation(LspDiagnostic::diagnose_document(uri, &mut w)?.into())
+            .await?;
+    }
     Ok(())
 }
 
 #[allow(non_snake_case)]
 #[tracing::instrument(name = "didOpen", skip_all)]
-async fn handle_didOpen(
+pub async fn handle_didOpen(
     noti: Notification,
-    mut state: SharedState,
+    state: SharedState,
     mut sender: BufferOpChannelSender,
 ) -> HandleResult<()> {
     let text_doc_item = serde_json::from_value::<TextDocumentOpen>(noti.params)?;
     let text = text_doc_item.text_document.text;
     let uri = text_doc_item.text_document.uri;
 
-    let r = state.get_read()?;
-
-    // Only update from didOpen noti when docs have free capacity.
-    // Otherwise updates are done on save
-    // let docs_already_full = r.store.docs_at_capacity().clone();
-    drop(r);
+    let mut w = state.get_write().await;
+    w.update_doc_and_agents_from_text(uri.clone(), text)?;
 
-    let mut w = state.get_write()?;
-    if let Some(agents) = w.agents.as_mut() {
-        agents.update_or_create_doc_agent(&uri, &text);
+    if !w.pull_diagnostics {
+        sender
+            .send_operation(LspDiagnostic::diagnose_document(uri, &mut w)?.into())
+            .await?;
     }
-    // if !docs_already_full {
-    //     w.store.update_doc(&text, uri.clone());
-    // }
-    // w.store.update_burns_on_doc(&uri)?;
-
-    // sender
-    //     .send_operation(LspDiagnostic::diagnose_document(uri.clone(), &mut w.store)?.into())
-    //     .await?;
     Ok(())
 }
@@ -0,0 +1,165 @@
+use super::buffer_operations::{BufferOpChannelError, BufferOpChannelResult};
+use lsp_server::{Request, RequestId, Response};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+use tracing::warn;
+
+/// Requests the server has sent to the client. Each one gets an id of its own, which the
+/// client's response is routed back to the waiting handler by
+#[derive(Debug, Clone, Default)]
+pub struct OutgoingRequests(Arc<Mutex<Outgoing>>);
+
+#[derive(Debug, Default)]
+struct Outgoing {
+    next_id: i32,
+    waiting: HashMap<RequestId, oneshot::Sender<Response>>,
+}
+
+/// The client's response to an outgoing request, it stops being waited on when dropped
+#[derive(Debug)]
+pub struct PendingResponse<R> {
+    id: RequestId,
+    receiver: oneshot::Receiver<Response>,
+    outgoing: OutgoingRequests,
+    request: PhantomData<R>,
+}
+
+impl OutgoingRequests {
+    /// Creates a request to send to the client, along with its pending response
+    pub fn request<R: lsp_types::request::Request>(
+        &self,
+        params: R::Params,
+    ) -> BufferOpChannelResult<(Request, PendingResponse<R>)> {
+        let params = serde_json::to_value(params)?;
+        let (sender, receiver) = oneshot::channel();
+        let id = {
+            let mut outgoing = self.lock();
+            let id = RequestId::from(outgoing.next_id);
+            outgoing.next_id = outgoing.next_id.wrapping_add(1);
+            outgoing.waiting.insert(id.clone(), sender);
+            id
+        };
+
+        let request = Request {
+            id: id.clone(),
+            method: R::METHOD.to_owned(),
+            params,
+        };
+        let pending = PendingResponse {
+            id,
+            receiver,
+            outgoing: self.clone(),
+            request: PhantomData,
+        };
+        Ok((request, pending))
+    }
+
+    /// Routes a response from the client to the handler waiting on it. Returns false if none was
+    pub fn respond(&self, response: Response) -> bool {
+        let Some(sender) = self.lock().waiting.remove(&response.id) else {
+            warn!("got a response to {}, which was not waited on", response.id);
+            return false;
+        };
+        sender.send(response).is_ok()
+    }
+
+    /// Stops waiting on every outgoing request, their handlers get
+    /// [`BufferOpChannelError::NoResponse`]. Returns how many were waiting
+    pub fn abandon_all(&self) -> usize {
+        let mut outgoing = self.lock();
+        let abandoned = outgoing.waiting.len();
+        outgoing.waiting.clear();
+        abandoned
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().waiting.is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Outgoing> {
+        // the map is never left half updated, so a poisoned lock is still usable
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<R: lsp_types::request::Request> PendingResponse<R> {
+    /// Waits for the client to respond
+    pub async fn response(mut self) -> BufferOpChannelResult<R::Result> {
+        let response = (&mut self.receiver)
+            .await
+            .map_err(|_| BufferOpChannelError::NoResponse)?;
+        if let Some(err) = response.error {
+            return Err(BufferOpChannelError::Client(err));
+        }
+        let result = response.result.unwrap_or(serde_json::Value::Null);
+        Ok(serde_json::from_value(result)?)
+    }
+}
+
+impl<R> Drop for PendingResponse<R> {
+    fn drop(&mut self) {
+        self.outgoing.lock().waiting.remove(&self.id);
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+    #[allow(unused)]
+    use lsp_types::{
+        request::{ApplyWorkspaceEdit, WorkDoneProgressCreate},
+        ApplyWorkspaceEditParams, ApplyWorkspaceEditResponse, NumberOrString,
+        WorkDoneProgressCreateParams,
+    };
+
+    #[tokio::test]
+    async fn routes_responses_to_their_requests() {
+        let outgoing = OutgoingRequests::default();
+        let create = WorkDoneProgressCreateParams {
+            token: NumberOrString::Number(0),
+        };
+        let (first, first_pending) = outgoing.request::<WorkDoneProgressCreate>(create).unwrap();
+        let (second, second_pending) = outgoing
+            .request::<ApplyWorkspaceEdit>(ApplyWorkspaceEditParams {
+                label: None,
+                edit: Default::default(),
+            })
+            .unwrap();
+        assert_ne!(first.id, second.id);
+        assert_eq!(second.method, "workspace/applyEdit");
+
+        let rejected = ApplyWorkspaceEditResponse {
+            applied: false,
+            failure_reason: Some("no".to_owned()),
+            failed_change: None,
+        };
+        assert!(outgoing.respond(Response::new_ok(second.id.clone(), rejected)));
+        assert!(!outgoing.respond(Response::new_ok(second.id, ())));
+        let response = second_pending.response().await.unwrap();
+        assert!(!response.applied);
+
+        let (third, third_pending) = outgoing
+            .request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: NumberOrString::Number(1),
+            })
+            .unwrap();
+        assert!(outgoing.respond(Response::new_err(third.id, 1, "failed".to_owned())));
+        assert!(matches!(
+            third_pending.response().await,
+            Err(BufferOpChannelError::Client(_))
+        ));
+
+        assert_eq!(outgoing.abandon_all(), 1);
+        assert!(matches!(
+            first_pending.response().await,
+            Err(BufferOpChannelError::NoResponse)
+        ));
+        assert!(outgoing.is_empty());
+    }
+}
@@ -1,27 +1,53 @@
 use super::{
     buffer_operations::{BufferOpChannelHandler, BufferOpChannelSender, BufferOperation},
+    commands::{handle_execute_command, interact_code_actions, selection_code_action},
+    completion::{handle_completion, handle_inline_completion},
+    diagnostics::LspDiagnostic,
     error::{HandleError, HandleResult},
 };
 use crate::{
-    agents::{message_stack_into_marked_string, Agents},
+    agents::{doc_control_role, Agents},
+    config::prompt::ResponseMode,
+    database::models::block::DBBlock,
     embeddings,
+    error::StateError,
     handle::BufferOpChannelJoinHandle,
-    interact::id::{human_readable_int, DOCUMENT_ID, GLOBAL_ID, PROMPT_ID, PUSH_ID, RAG_PUSH_ID},
-    state::SharedState,
+    interact::{
+        args::{InteractArg, InteractFlag},
+        id::{
+            human_readable_int, InteractInteger, ScopeId, DOCUMENT_ID, GLOBAL_ID, PROMPT_ID,
+            PUSH_ID, RAG_PUSH_ID,
+        },
+        lexer::ParsedComment,
+        response::{begin_response_stream, response_edit},
+        InteractError, InteractResult,
+    },
+    state::{LspState, SharedState},
 };
 use anyhow::anyhow;
 use espionox::{
+    agents::{memory::OtherRoleTo, Agent},
     language_models::completions::streaming::CompletionStreamStatus,
-    prelude::{stream_completion, ListenerTrigger, Message},
+    prelude::{stream_completion, ListenerTrigger, Message, MessageRole},
 };
-use lsp_server::Request;
+use lsp_server::{ErrorCode, Request, Response};
 use lsp_types::{
-    ApplyWorkspaceEditParams, DocumentDiagnosticParams, GotoDefinitionParams, HoverContents,
-    HoverParams, MessageType, ShowMessageParams, TextEdit, WorkspaceEdit,
+    ApplyWorkspaceEditParams, CodeActionParams, CodeActionResponse, DocumentDiagnosticParams,
+    DocumentDiagnosticReportResult, GotoDefinitionParams, HoverContents, HoverParams,
+    LogMessageParams, MarkupContent, MarkupKind, MessageType, ShowMessageParams, TextEdit, Uri,
+    WorkspaceDiagnosticParams, WorkspaceDiagnosticReportResult, WorkspaceEdit,
 };
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, warn};
 
+/// How similar a block has to be to a rag push query to be pushed
+const RAG_PUSH_THRESHOLD: f32 = 0.5;
+/// Most blocks a single rag push can push
+const RAG_PUSH_LIMIT: usize = 5;
+/// Ends the part of a response that was received before its request was cancelled
+const CANCELLED_RESPONSE_MARKER: &str = "\n\n[cancelled, response truncated]";
+
 #[tracing::instrument(name = "handle request", skip_all)]
 pub async fn handle_request(
     req: Request,
@@ -30,31 +56,66 @@ pub async fn handle_request(
     let handle = BufferOpChannelHandler::new();
     let mut task_sender = handle.sender.clone();
     let _: BufferOpChannelJoinHandle = tokio::spawn(async move {
+        let id = req.id.clone();
         let method = req.method.clone();
-        match match method.as_str() {
+        let cancel = state.in_flight.token(&id);
+        let result = match method.as_str() {
+            // cancelled while it was waiting its turn
+            _ if cancel.is_cancelled() => Ok(()),
             "textDocument/definition" => {
                 handle_goto_definition(req, state, task_sender.clone()).await
             }
             "textDocument/hover" => handle_hover(req, state, task_sender.clone()).await,
+            "textDocument/completion" => handle_completion(req, state, task_sender.clone()).await,
+            "textDocument/inlineCompletion" => {
+                handle_inline_completion(req, state, task_sender.clone()).await
+            }
+            "textDocument/codeAction" => handle_code_action(req, state, task_sender.clone()).await,
+            "workspace/executeCommand" => {
+                handle_execute_command(req, state, task_sender.clone()).await
+            }
             "textDocument/diagnostic" => handle_diagnostics(req, state, task_sender.clone()).await,
+            "workspace/diagnostic" => {
+                handle_workspace_diagnostics(req, state, task_sender.clone()).await
+            }
             "shutdown" => handle_shutdown(state, task_sender.clone()).await,
             _ => {
-                warn!("unhandled request method: {}", req.method);
-                Ok(())
-            }
-        } {
-            Ok(_) => {
+                warn!("unhandled request method: {}", method);
+                let response = Response::new_err(
+                    id.clone(),
+                    ErrorCode::MethodNotFound as i32,
+                    format!("unhandled request method: {method}"),
+                );
                 task_sender
-                    .send_finish()
+                    .send_operation(BufferOperation::Response(response))
                     .await
-                    .map_err(|err| HandleError::from(err))?;
-                Ok(())
+                    .map_err(HandleError::from)
             }
-            Err(err) => {
-                err.request_err(&mut task_sender).await?;
-                Ok(())
+        };
+
+        match result {
+            Err(err) => err.request_err(id, &mut task_sender).await?,
+            // every request gets a response, even when its handler has nothing to respond with
+            Ok(_) if !task_sender.has_responded() => {
+                let response = match cancel.is_cancelled() {
+                    true => Response::new_err(
+                        id,
+                        ErrorCode::RequestCanceled as i32,
+                        "request cancelled".to_owned(),
+                    ),
+                    false => Response::new_ok(id, serde_json::Value::Null),
+                };
+                task_sender
+                    .send_operation(BufferOperation::Response(response))
+                    .await?;
             }
+            Ok(_) => {}
         }
+        task_sender
+            .send_finish()
+            .await
+            .map_err(|err| HandleError::from(err))?;
+        Ok(())
     });
     return Ok(handle);
 }
@@ -62,31 +123,33 @@ pub async fn handle_request(
 #[tracing::instrument(name = "goto def", skip_all)]
 pub async fn handle_goto_definition(
     req: Request,
-    mut state: SharedState,
+    state: SharedState,
     mut sender: BufferOpChannelSender,
 ) -> HandleResult<()> {
+    let cancel = state.in_flight.token(&req.id);
     let params = serde_json::from_value::<GotoDefinitionParams>(req.params)?;
+    sender.use_work_done_token(params.work_done_progress_params.work_done_token.clone());
 
     let uri = params.text_document_position_params.text_document.uri;
-    let mut position = params.text_document_position_params.position;
+    let position = params.text_document_position_params.position;
 
     warn!("Gotodef Position: {position:?}");
 
-    let message = ShowMessageParams {
+    let message = LogMessageParams {
         typ: MessageType::INFO,
         message: format!("Triggered GotoDef at position {position:?}",),
     };
 
     sender.send_operation(message.into()).await?;
-    let mut w = state.get_write()?;
-
-    let doc_tokens = w
+    let r = state.get_read().await;
+    let doc_tokens = &r
         .documents
         .get(&uri)
-        .ok_or(anyhow!("document not present"))?;
+        .ok_or(anyhow!("document not present"))?
+        .tokens;
 
-    let (comment, idx) = match doc_tokens.comment_in_position(&position) {
-        Some((com, i)) => (com.clone(), i),
+    let comment = match doc_tokens.comment_in_position(&position) {
+        Some((com, _)) => com.clone(),
         None => {
             return Err(anyhow!("no comment at gotodef position").into());
         }
@@ -97,33 +160,49 @@ pub async fn handle_goto_definition(
     }
 
     let integer = comment.try_get_interact_integer()?;
-    let (command, scope) = w.registry.interract_tuple(integer)?;
 
-    let message = ShowMessageParams {
+    let message = LogMessageParams {
         typ: MessageType::INFO,
-        message: format!("Triggered GotoDef with {}", human_readable_int(integer)),
+        message: format!(
+            "Triggered GotoDef with {}",
+            human_readable_int(integer, &r.registry)
+        ),
     };
+    drop(r);
 
     sender.send_operation(message.into()).await?;
+    run_interact(comment, integer, uri, &state, &mut sender, &cancel).await
+}
 
-    let agent = match w.agents.as_mut() {
-        Some(agents) => match scope {
-            _ if scope == GLOBAL_ID => agents.global_agent_mut(),
-            _ if scope == DOCUMENT_ID => agents.doc_agent_mut(&uri).expect("No doc agent loaded?"),
-            _ => unreachable!(),
-        },
+/// Runs the command of an interact comment in the scope of `integer`, which can be another scope
+/// than the one the comment was written with
+pub(super) async fn run_interact(
+    comment: ParsedComment,
+    integer: InteractInteger,
+    uri: Uri,
+    state: &SharedState,
+    sender: &mut BufferOpChannelSender,
+    cancel: &CancellationToken,
+) -> HandleResult<()> {
+    let mut guard = state.get_write().await;
+    // borrowing through the guard would lock all of the state along with the agent
+    let w = &mut *guard;
+    let encoding = w.position_encoding;
+
+    let flags = comment
+        .args
+        .iter()
+        .map(InteractArg::parse_flag)
+        .collect::<InteractResult<Vec<InteractFlag>>>()?;
+    let model = match w.agents.as_ref() {
+        Some(agents) => agents.completion_model_with_flags(&flags)?,
         None => {
             warn!("no agents");
             return Ok(());
         }
     };
 
-    // let role = MessageRole::Other {
-    //     alias: uri.to_string(),
-    //     coerce_to: OtherRoleTo::User,
-    // };
-
-    match command {
+    match integer.command {
         PUSH_ID => {
             let message = ShowMessageParams {
                 typ: MessageType::INFO,
@@ -134,44 +213,118 @@ pub async fn handle_goto_definition(
         }
 
         RAG_PUSH_ID => {
-            let (_range, text_for_interact) = comment.text_for_interact().unwrap();
+            let (_range, text_for_interact) = comment.text_for_interact(encoding).unwrap();
             if text_for_interact.trim().is_empty() {
                 return Ok(());
             }
 
-            let embedded = embeddings::get_passage_embeddings(vec![&text_for_interact])?;
+            // embedding and querying take a while, the state is only locked to push the blocks
+            let db = w.database.clone().ok_or(StateError::DatabaseNotPresent)?;
+            drop(guard);
+
+            let embedded = embeddings::get_passage_embeddings(vec![&text_for_interact])?
+                .into_iter()
+                .next()
+                .ok_or(anyhow!("no embedding returned for rag push query"))?;
+
+            let blocks = DBBlock::get_relavent(&db, embedded, RAG_PUSH_THRESHOLD, RAG_PUSH_LIMIT)
+                .await
+                .map_err(StateError::from)?;
+
+            let mut guard = state.get_write().await;
+            let w = &mut *guard;
+            let agent = w.agent_mut_from_interact_integer(integer, &uri)?;
+            let mut pushed = vec![];
+            for block in blocks {
+                let source = match block.lines {
+                    Some(lines) => format!("{} ({lines})", block.uri.as_str()),
+                    None => block.uri.as_str().to_owned(),
+                };
+                let role = MessageRole::Other {
+                    alias: source.clone(),
+                    coerce_to: OtherRoleTo::User,
+                };
+                agent.cache.mut_filter_by(&role, false);
+                agent.cache.push(Message {
+                    role,
+                    content: block.content,
+                });
+                pushed.push(source);
+            }
+
+            let message = ShowMessageParams {
+                typ: MessageType::INFO,
+                message: match pushed.is_empty() {
+                    true => format!("No blocks relevant to: {}", text_for_interact.trim()),
+                    false => format!(
+                        "Pushed {} blocks into {}:\n{}",
+                        pushed.len(),
+                        human_readable_int(integer, &w.registry),
+                        pushed.join("\n")
+                    ),
+                },
+            };
+
+            sender.send_operation(message.into()).await?;
         }
 
         PROMPT_ID => {
-            let (range_of_text, text_for_interact) = comment.text_for_interact().unwrap();
+            let (range_of_text, text_for_interact) = comment.text_for_interact(encoding).unwrap();
             if text_for_interact.trim().is_empty() {
                 return Ok(());
             }
 
-            let mut changes = HashMap::new();
-
-            changes.insert(
-                uri.clone(),
-                vec![TextEdit {
-                    range: range_of_text,
-                    new_text: String::new(),
-                }],
-            );
-
-            let edit_params = ApplyWorkspaceEditParams {
-                label: None,
-                edit: WorkspaceEdit {
-                    changes: Some(changes),
-                    ..Default::default()
-                },
-            };
-
-            sender.send_operation(edit_params.into()).await?;
+            let response_mode = w.prompt_config.response_mode;
+            // in the buffer the prompt stays, so that running it again replaces its answer
+            if response_mode == ResponseMode::Popup {
+                let mut changes = HashMap::new();
+
+                changes.insert(
+                    uri.clone(),
+                    vec![TextEdit {
+                        range: range_of_text,
+                        new_text: String::new(),
+                    }],
+                );
+
+                let edit_params = ApplyWorkspaceEditParams {
+                    label: None,
+                    edit: WorkspaceEdit {
+                        changes: Some(changes),
+                        ..Default::default()
+                    },
+                };
+
+                sender.send_operation(edit_params.into()).await?;
+            }
 
-            let message = Message::new_user(&text_for_interact);
-            agent.cache.push(message);
+            // the completion is done by an agent with a copy of the scope's cache, so the state
+            // is not locked while it streams. Flags only apply to this one completion
+            let prompt = Message::new_user(&text_for_interact);
+            let mut completing_agent = Agent::new(None, model);
+            completing_agent.cache = w.agent_ref_from_scope(integer.scope, &uri)?.cache.clone();
+            completing_agent.cache.push(prompt.clone());
+
+            let mut streamed_response = match response_mode {
+                ResponseMode::Stream => {
+                    let doc = w
+                        .documents
+                        .get(&uri)
+                        .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+                    let (edit, response) =
+                        begin_response_stream(&doc.text, doc.ext(), comment.range, encoding)?;
+                    sender
+                        .send_operation(BufferOperation::text_edits(uri.clone(), vec![edit]))
+                        .await?;
+                    let insert = sender.streamed_insert(uri.clone(), response.start(), encoding);
+                    Some((insert, response))
+                }
+                _ => None,
+            };
+            drop(guard);
+            sender.release_order();
 
-            let mut stream_handler = agent
+            let mut stream_handler = completing_agent
                 .do_action(stream_completion, (), Option::<ListenerTrigger>::None)
                 .await?;
 
@@ -180,14 +333,30 @@ pub async fn handle_goto_definition(
                 .await?;
 
             let mut whole_message = String::new();
+            let mut cancelled = false;
             warn!("starting inference response loop");
-            while let Ok(Some(status)) = stream_handler.receive(agent).await {
+            loop {
+                let status = tokio::select! {
+                    status = stream_handler.receive(&mut completing_agent) => status,
+                    _ = cancel.cancelled() => {
+                        cancelled = true;
+                        break;
+                    }
+                };
+                let Ok(Some(status)) = status else {
+                    break;
+                };
                 warn!("STATUS: {status:?}");
                 match status {
                     CompletionStreamStatus::Working(token) => {
                         warn!("got completion token: {}", token);
                         whole_message.push_str(&token);
-                        sender.send_work_done_report(Some(&token), None).await?;
+                        match streamed_response.as_mut() {
+                            Some((insert, response)) => {
+                                insert.push(&response.comment_piece(&token)).await?
+                            }
+                            None => sender.send_work_done_report(Some(&token), None).await?,
+                        }
                     }
                     CompletionStreamStatus::Finished => {
                         warn!("finished");
@@ -197,16 +366,77 @@ pub async fn handle_goto_definition(
                 }
             }
 
+            let answered = !whole_message.trim().is_empty();
+            if cancelled {
+                warn!("completion cancelled");
+                whole_message.push_str(CANCELLED_RESPONSE_MARKER);
+                if let Some((insert, response)) = streamed_response.as_mut() {
+                    insert
+                        .push(&response.comment_piece(CANCELLED_RESPONSE_MARKER))
+                        .await?;
+                }
+                sender.send_work_done_end(Some("Cancelled")).await?;
+            }
+
             warn!("whole message: {whole_message}");
+            if let Some((mut insert, mut response)) = streamed_response {
+                insert.push(&response.finish()).await?;
+                insert.flush().await?;
+            }
 
-            let message = ShowMessageParams {
-                typ: MessageType::INFO,
-                message: whole_message.clone(),
-            };
+            let mut guard = state.get_write().await;
+            let w = &mut *guard;
+            // the scope's cache is never left with a prompt that has no answer
+            if answered {
+                let agent = w.agent_mut_from_interact_integer(integer, &uri)?;
+                agent.cache.push(prompt);
+                agent.cache.push(Message::new_assistant(&whole_message));
+            }
 
-            sender.send_operation(message.into()).await?;
+            match response_mode {
+                ResponseMode::Popup => {
+                    let message = ShowMessageParams {
+                        typ: MessageType::INFO,
+                        message: whole_message.clone(),
+                    };
+
+                    sender.send_operation(message.into()).await?;
+                }
+                ResponseMode::Buffer => {
+                    let doc = w
+                        .documents
+                        .get(&uri)
+                        .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+                    let edit = response_edit(
+                        &doc.text,
+                        doc.ext(),
+                        comment.range,
+                        &whole_message,
+                        encoding,
+                    )?;
+                    drop(guard);
+
+                    let edit = BufferOperation::text_edit_params(uri.clone(), vec![edit]);
+                    let applied = match sender.apply_edit(&state.outgoing, edit).await {
+                        Ok(response) => response.applied,
+                        Err(err) => {
+                            warn!("failed to write response into the buffer: {}", err);
+                            false
+                        }
+                    };
+                    // a response the client would not write into the buffer is shown instead
+                    if !applied {
+                        let message = ShowMessageParams {
+                            typ: MessageType::INFO,
+                            message: whole_message,
+                        };
+                        sender.send_operation(message.into()).await?;
+                    }
+                }
+                ResponseMode::Stream => {}
+            }
         }
-        _ => unreachable!(),
+        _ => return Err(InteractError::InvalidInteractId(integer).into()),
     }
 
     Ok(())
@@ -228,80 +458,195 @@ pub async fn handle_hover(
         position
     );
 
-    let r = state.get_read()?;
+    let r = state.get_read().await;
 
-    let doc_tokens = r
+    let doc_tokens = &r
         .documents
         .get(&uri)
-        .ok_or(anyhow!("document not present"))?;
-
-    if let Some((comment, _)) = doc_tokens.comment_in_position(&position) {
-        if let Some(integer) = comment.try_get_interact_integer().ok() {
-            let (_command, scope) = r.registry.interract_tuple(integer)?;
-            let agent = match r.agents.as_ref() {
-                Some(agents) => match scope {
-                    GLOBAL_ID => agents.global_agent_ref(),
-                    DOCUMENT_ID => agents.doc_agent_ref(&uri).expect("No doc agent loaded?"),
-                    _ => unreachable!(),
-                },
-                None => {
-                    warn!("no agents");
-                    return Ok(());
-                }
-            };
-            let stack = Agents::get_last_n_messages(agent, 5);
-            let contents = HoverContents::Scalar(message_stack_into_marked_string(stack));
+        .ok_or(anyhow!("document not present"))?
+        .tokens;
+
+    let Some(integer) = doc_tokens
+        .comment_in_position(&position)
+        .and_then(|(comment, _)| comment.try_get_interact_integer().ok())
+    else {
+        return Ok(());
+    };
+    if r.agents.is_none() {
+        warn!("no agents");
+        return Ok(());
+    }
+    let contents = HoverContents::Markup(MarkupContent {
+        kind: MarkupKind::Markdown,
+        value: scope_summary(&r, integer.scope, &uri)?,
+    });
+    drop(r);
 
-            sender
-                .send_operation(BufferOperation::HoverResponse {
-                    id: req.id,
-                    contents,
-                })
-                .await?;
+    sender
+        .send_operation(BufferOperation::HoverResponse {
+            id: req.id,
+            contents,
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// The scope's system prompt, its size, what was pushed into it and the last exchange had in it
+fn scope_summary(state: &LspState, scope: ScopeId, uri: &Uri) -> HandleResult<String> {
+    let agent = state.agent_ref_from_scope(scope, uri)?;
+    let token = state
+        .registry
+        .get_scope_token(scope)
+        .ok_or(InteractError::InvalidScopeId(scope))?;
+    let name = match scope {
+        GLOBAL_ID => "Global",
+        DOCUMENT_ID => "Document",
+        _ => "Custom",
+    };
+
+    let mut summary = format!(
+        "### {name} scope `{token}`\n**System prompt:** {}\n\n**Messages:** {} (~{} tokens)\n",
+        Agents::system_prompt(agent).unwrap_or("none").trim(),
+        agent.cache.len(),
+        Agents::estimated_tokens(agent),
+    );
+
+    // pushes from comments are labeled with only their document, they are in the same order as
+    // the pushes into this scope in that document
+    let mut comment_pushes: HashMap<&str, std::vec::IntoIter<(u32, u32)>> = HashMap::new();
+    let doc_role = doc_control_role();
+    let mut pushed = vec![];
+    for message in agent.cache.as_ref() {
+        let MessageRole::Other { alias, .. } = &message.role else {
+            continue;
+        };
+        if message.role == doc_role {
+            continue;
         }
+        let lines = comment_pushes
+            .entry(alias.as_str())
+            .or_insert_with(|| {
+                let lines: Vec<(u32, u32)> = Uri::from_str(alias)
+                    .ok()
+                    .and_then(|source| state.documents.get(&source))
+                    .map(|doc| {
+                        doc.tokens
+                            .pushed_lines()
+                            .filter(|(comment, _)| {
+                                comment
+                                    .try_get_interact_integer()
+                                    .is_ok_and(|integer| integer.scope == scope)
+                            })
+                            .map(|(_, lines)| lines)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                lines.into_iter()
+            })
+            .next();
+        pushed.push(match lines {
+            Some((start, end)) => format!("- `{alias}` (lines {}-{})", start + 1, end + 1),
+            None => format!("- `{alias}`"),
+        });
+    }
+    if !pushed.is_empty() {
+        summary.push_str(&format!("\n#### Pushed\n{}\n", pushed.join("\n")));
     }
-    // if let Some(burns_on_doc) = r.store.burns.read_burns_on_doc(&uri) {
-    //     if let Some(burn) = burns_on_doc
-    //         .iter()
-    //         .find(|b| b.activation.is_in_position(&position))
-    //     {
-    //         if let Some(contents) = &burn.hover_contents {
-    //             sender
-    //                 .send_operation(BufferOperation::HoverResponse {
-    //                     contents: contents.clone(),
-    //                     id: req.id,
-    //                 })
-    //                 .await?;
-    //         }
-    //     }
-    // }
 
+    match Agents::last_exchange(agent) {
+        Some((prompt, answer)) => {
+            summary.push_str(&format!(
+                "\n#### Last exchange\n**User:** {}\n\n**Assistant:** {}\n",
+                prompt.content.trim(),
+                answer.map_or("no answer yet", |answer| answer.content.trim())
+            ));
+        }
+        None => summary.push_str("\nNo exchanges yet\n"),
+    }
+    Ok(summary)
+}
+
+#[tracing::instrument(name = "code action", skip_all)]
+pub async fn handle_code_action(
+    req: Request,
+    state: SharedState,
+    mut sender: BufferOpChannelSender,
+) -> HandleResult<()> {
+    let params = serde_json::from_value::<CodeActionParams>(req.params)?;
+    let uri = params.text_document.uri;
+    let position = params.range.start;
+
+    let r = state.get_read().await;
+    let doc_tokens = &r
+        .documents
+        .get(&uri)
+        .ok_or(StateError::DocumentNotPresent(uri.clone()))?
+        .tokens;
+    let mut actions: CodeActionResponse = match doc_tokens.comment_in_position(&position) {
+        Some((comment, _)) => interact_code_actions(comment, &uri, position, &r.registry),
+        None => vec![],
+    };
+    drop(r);
+    if params.range.start != params.range.end {
+        actions.push(selection_code_action(&uri, params.range));
+    }
+
+    sender
+        .send_operation(BufferOperation::Response(Response::new_ok(req.id, actions)))
+        .await?;
     Ok(())
 }
 
+#[tracing::instrument(name = "diagnostics", skip_all)]
 async fn handle_diagnostics(
     req: Request,
-    mut state: SharedState,
+    state: SharedState,
     mut sender: BufferOpChannelSender,
 ) -> HandleResult<()> {
     let params: DocumentDiagnosticParams =
         serde_json::from_value::<DocumentDiagnosticParams>(req.params)?;
-    let mut w = state.get_write()?;
-    // sender
-    //     .send_operation(
-    //         LspDiagnostic::diagnose_document(params.text_document.uri, &mut w.store)?.into(),
-    //     )
-    // .await?;
+    let r = state.get_read().await;
+    let report = LspDiagnostic::document_report(
+        &params.text_document.uri,
+        params.previous_result_id.as_deref(),
+        &r,
+    )?;
+    drop(r);
+
+    let result = DocumentDiagnosticReportResult::Report(report);
+    sender
+        .send_operation(BufferOperation::Response(Response::new_ok(req.id, result)))
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "workspace diagnostics", skip_all)]
+async fn handle_workspace_diagnostics(
+    req: Request,
+    state: SharedState,
+    mut sender: BufferOpChannelSender,
+) -> HandleResult<()> {
+    let params: WorkspaceDiagnosticParams =
+        serde_json::from_value::<WorkspaceDiagnosticParams>(req.params)?;
+    let r = state.get_read().await;
+    let report = LspDiagnostic::workspace_report(&params.previous_result_ids, &r)?;
+    drop(r);
+
+    let result = WorkspaceDiagnosticReportResult::Report(report);
+    sender
+        .send_operation(BufferOperation::Response(Response::new_ok(req.id, result)))
+        .await?;
     Ok(())
 }
 
 async fn handle_shutdown(
-    mut state: SharedState,
+    state: SharedState,
     mut sender: BufferOpChannelSender,
 ) -> HandleResult<()> {
     warn!("shutting down server");
     // sender.start_work_done(Some("Shutting down server")).await?;
-    // let mut w = state.get_write()?;
+    // let mut w = state.get_write().await;
     // if let Some(_db) = w.database.take() {
     //     sender
     //         .send_work_done_report(Some("Database present, Saving state..."), None)
@@ -0,0 +1,197 @@
+use super::error::{InteractError, InteractResult};
+use espionox::language_models::completions::{
+    anthropic::builder::AnthropicCompletionModel, openai::builder::OpenAiCompletionModel,
+    CompletionProvider,
+};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+const FLAG_PREFIX: &str = "--";
+
+/// A `--flag value` pair written between an interact code and the text of the comment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InteractArg {
+    pub flag: String,
+    pub value: Option<String>,
+    /// Bytes of the comment's content the flag and its value take up
+    pub span: Range<usize>,
+}
+
+/// A flag that overrides the model parameters of a single completion
+#[derive(Debug, Clone, PartialEq)]
+pub enum InteractFlag {
+    Model(CompletionProvider),
+    /// Between 0 and 2
+    Temperature(f32),
+    MaxTokens(u32),
+}
+
+/// Parses every flag at the start of `str`, stopping at the first word that isn't one.
+/// `offset` is added to each span, so that they point into the whole comment
+pub fn parse_args(str: &str, offset: usize) -> Vec<InteractArg> {
+    let mut args = vec![];
+    let mut words = Words { str, position: 0 };
+    while let Some(flag) = words.peek().filter(|(w, _)| w.starts_with(FLAG_PREFIX)) {
+        words.next();
+        let value = words
+            .peek()
+            .filter(|(w, _)| !w.starts_with(FLAG_PREFIX))
+            .inspect(|_| words.next());
+        let end = value.as_ref().map_or(flag.1.end, |(_, span)| span.end);
+        args.push(InteractArg {
+            flag: flag.0.trim_start_matches(FLAG_PREFIX).to_owned(),
+            value: value.map(|(w, _)| w.to_owned()),
+            span: flag.1.start + offset..end + offset,
+        });
+    }
+    args
+}
+
+/// Whitespace separated words along with their byte spans
+struct Words<'s> {
+    str: &'s str,
+    position: usize,
+}
+
+impl<'s> Words<'s> {
+    fn peek(&self) -> Option<(&'s str, Range<usize>)> {
+        let rest = &self.str[self.position..];
+        let start = self.position + rest.find(|c: char| !c.is_whitespace())?;
+        let len = self.str[start..]
+            .find(char::is_whitespace)
+            .unwrap_or(self.str.len() - start);
+        Some((&self.str[start..start + len], start..start + len))
+    }
+
+    fn next(&mut self) {
+        if let Some((_, span)) = self.peek() {
+            self.position = span.end;
+        }
+    }
+}
+
+impl InteractArg {
+    pub fn parse_flag(&self) -> InteractResult<InteractFlag> {
+        let value = || {
+            self.value
+                .as_ref()
+                .ok_or(InteractError::MissingFlagValue(self.flag.clone()))
+        };
+        let invalid =
+            |value: &String| InteractError::InvalidFlagValue(self.flag.clone(), value.to_owned());
+        match self.flag.as_str() {
+            "model" => match value()?.to_lowercase().as_str() {
+                "opus" => Ok(InteractFlag::Model(AnthropicCompletionModel::Opus.into())),
+                "sonnet" => Ok(InteractFlag::Model(AnthropicCompletionModel::Sonnet.into())),
+                "haiku" => Ok(InteractFlag::Model(AnthropicCompletionModel::Haiku.into())),
+                "gpt3" => Ok(InteractFlag::Model(OpenAiCompletionModel::Gpt3.into())),
+                "gpt4" => Ok(InteractFlag::Model(OpenAiCompletionModel::Gpt4.into())),
+                _ => Err(invalid(value()?)),
+            },
+            "temperature" => {
+                let value = value()?;
+                value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|t| (0.0..=2.0).contains(t))
+                    .map(InteractFlag::Temperature)
+                    .ok_or_else(|| invalid(value))
+            }
+            "max-tokens" => {
+                let value = value()?;
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|t| *t > 0)
+                    .map(InteractFlag::MaxTokens)
+                    .ok_or_else(|| invalid(value))
+            }
+            _ => Err(InteractError::UnknownFlag(self.flag.clone())),
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+
+    #[test]
+    fn parses_flags_until_first_word() {
+        let str = " --model haiku  --temperature 0.2 --max-tokens 500 explain --this";
+        let args = parse_args(str, 3);
+        assert_eq!(
+            args,
+            vec![
+                InteractArg {
+                    flag: "model".to_owned(),
+                    value: Some("haiku".to_owned()),
+                    span: 4..17,
+                },
+                InteractArg {
+                    flag: "temperature".to_owned(),
+                    value: Some("0.2".to_owned()),
+                    span: 19..36,
+                },
+                InteractArg {
+                    flag: "max-tokens".to_owned(),
+                    value: Some("500".to_owned()),
+                    span: 37..53,
+                },
+            ]
+        );
+        assert_eq!(
+            &str[args[0].span.start - 3..args[0].span.end - 3],
+            "--model haiku"
+        );
+        assert_eq!(&str[args[2].span.end - 3..], " explain --this");
+
+        assert!(parse_args(" explain --model haiku", 0).is_empty());
+        let args = parse_args(" --verbose --model", 0);
+        assert_eq!(args.len(), 2);
+        assert!(args.iter().all(|a| a.value.is_none()));
+    }
+
+    #[test]
+    fn validates_flag_values() {
+        let arg = |flag: &str, value: Option<&str>| InteractArg {
+            flag: flag.to_owned(),
+            value: value.map(str::to_owned),
+            span: 0..0,
+        };
+        assert_eq!(
+            arg("model", Some("Haiku")).parse_flag().unwrap(),
+            InteractFlag::Model(AnthropicCompletionModel::Haiku.into())
+        );
+        assert_eq!(
+            arg("temperature", Some("0.2")).parse_flag().unwrap(),
+            InteractFlag::Temperature(0.2)
+        );
+        assert_eq!(
+            arg("max-tokens", Some("500")).parse_flag().unwrap(),
+            InteractFlag::MaxTokens(500)
+        );
+
+        for value in [Some("yes"), None] {
+            assert!(matches!(
+                arg("verbose", value).parse_flag(),
+                Err(InteractError::UnknownFlag(_))
+            ));
+        }
+        assert!(matches!(
+            arg("model", None).parse_flag(),
+            Err(InteractError::MissingFlagValue(_))
+        ));
+        for (flag, value) in [
+            ("model", "gpt5"),
+            ("temperature", "3"),
+            ("temperature", "warm"),
+            ("max-tokens", "-1"),
+            ("max-tokens", "0"),
+        ] {
+            assert!(matches!(
+                arg(flag, Some(value)).parse_flag(),
+                Err(InteractError::InvalidFlagValue(_, _))
+            ));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use lsp_types::PositionEncodingKind;
+
+/// The unit the `character` of a position is counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionEncoding {
+    Utf8,
+    /// The only encoding every client has to support
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the client's most preferred encoding, falling back to UTF-16 when the client
+    /// doesn't list any
+    pub fn negotiate(client_encodings: Option<&[PositionEncodingKind]>) -> Self {
+        client_encodings
+            .unwrap_or_default()
+            .iter()
+            .find_map(|kind| Self::try_from(kind).ok())
+            .unwrap_or_default()
+    }
+
+    /// How many units the char takes up in this encoding
+    pub fn char_len(&self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    pub fn str_len(&self, str: &str) -> usize {
+        match self {
+            Self::Utf8 => str.len(),
+            Self::Utf16 => str.encode_utf16().count(),
+            Self::Utf32 => str.chars().count(),
+        }
+    }
+}
+
+impl TryFrom<&PositionEncodingKind> for PositionEncoding {
+    type Error = ();
+    fn try_from(kind: &PositionEncodingKind) -> Result<Self, Self::Error> {
+        match kind {
+            _ if *kind == PositionEncodingKind::UTF8 => Ok(Self::Utf8),
+            _ if *kind == PositionEncodingKind::UTF16 => Ok(Self::Utf16),
+            _ if *kind == PositionEncodingKind::UTF32 => Ok(Self::Utf32),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<PositionEncoding> for PositionEncodingKind {
+    fn from(encoding: PositionEncoding) -> Self {
+        match encoding {
+            PositionEncoding::Utf8 => Self::UTF8,
+            PositionEncoding::Utf16 => Self::UTF16,
+            PositionEncoding::Utf32 => Self::UTF32,
+        }
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+
+    #[test]
+    fn negotiates_first_supported_encoding() {
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&[])),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&[
+                PositionEncodingKind::new("utf-7"),
+                PositionEncodingKind::UTF8,
+                PositionEncodingKind::UTF16,
+            ])),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&[PositionEncodingKind::UTF32])),
+            PositionEncoding::Utf32
+        );
+    }
+
+    #[test]
+    fn counts_units_per_encoding() {
+        let str = "a€🦀";
+        assert_eq!(PositionEncoding::Utf8.str_len(str), 1 + 3 + 4);
+        assert_eq!(PositionEncoding::Utf16.str_len(str), 1 + 1 + 2);
+        assert_eq!(PositionEncoding::Utf32.str_len(str), 3);
+        for c in str.chars() {
+            for encoding in [
+                PositionEncoding::Utf8,
+                PositionEncoding::Utf16,
+                PositionEncoding::Utf32,
+            ] {
+                assert_eq!(encoding.char_len(c), encoding.str_len(&c.to_string()));
+            }
+        }
+    }
+}
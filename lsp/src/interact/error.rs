@@ -1,3 +1,4 @@
+use super::id::{InteractInteger, ScopeId};
 use crate::error::error_chain_fmt;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 
@@ -11,8 +12,12 @@ pub enum InteractError {
     UnhandledLanguageExtension(String),
     NoCommentToken,
     NoInteractInComment,
-    InvalidInteractId(u8),
-    // InvalidScopeId(u8),
+    InvalidInteractId(InteractInteger),
+    InvalidScopeToken(String),
+    UnknownFlag(String),
+    MissingFlagValue(String),
+    InvalidFlagValue(String, String),
+    InvalidScopeId(ScopeId),
     // InvaliCommandId(u8),
     AllWhitespace,
     NoScopeCharacter,
@@ -31,9 +36,15 @@ impl Display for InteractError {
             Self::RegistryFull => "Registry Full".to_owned(),
             Self::UnhandledLanguageExtension(ext) => format!("Unhandled Languge Extension: {ext}"),
             Self::NoInteractInComment => "No Interact In Comment".to_string(),
-            Self::InvalidInteractId(id) => format!("{id} is not a valid interact id"),
+            Self::InvalidInteractId(id) => format!("{id:?} is not a valid interact id"),
+            Self::InvalidScopeToken(token) => format!("{token:?} is not a valid scope token"),
+            Self::UnknownFlag(flag) => format!("Unknown flag: --{flag}"),
+            Self::MissingFlagValue(flag) => format!("No value given for flag: --{flag}"),
+            Self::InvalidFlagValue(flag, value) => {
+                format!("{value:?} is not a valid value for flag: --{flag}")
+            }
             // Self::InvaliCommandId(id) => format!("No Command with id: {id}"),
-            // Self::InvalidScopeId(id) => format!("No Scope with id: {id}"),
+            Self::InvalidScopeId(id) => format!("No Scope with id: {}", id.0),
             Self::AllWhitespace => "All Whitespace".to_owned(),
             Self::NoCommentToken => "No Comment Token".to_owned(),
             Self::NoScopeCharacter => "No Scope Character".to_owned(),
@@ -1,18 +1,37 @@
+use super::registry::InteractRegistry;
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, Clone, Hash, Ord, PartialOrd, PartialEq, Eq, Copy)]
 pub enum InteractID<ID> {
     Scope(ID),
     Command(ID),
 }
 
-pub const COMMAND_MASK: u8 = 0b0000_1111;
-pub const SCOPE_MASK: u8 = 0b1111_0000;
+#[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandId(pub u16);
+
+#[derive(Debug, Clone, Copy, Hash, Ord, PartialOrd, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScopeId(pub u16);
+
+/// The command and scope an interact comment was written with
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InteractInteger {
+    pub command: CommandId,
+    pub scope: ScopeId,
+}
+
+impl InteractInteger {
+    pub fn new(command: CommandId, scope: ScopeId) -> Self {
+        Self { command, scope }
+    }
+}
 
-pub const PROMPT_ID: InteractID<u8> = InteractID::Command(0b0);
-pub const PUSH_ID: InteractID<u8> = InteractID::Command(0b1);
-pub const RAG_PUSH_ID: InteractID<u8> = InteractID::Command(0b10);
+pub const PROMPT_ID: CommandId = CommandId(0);
+pub const PUSH_ID: CommandId = CommandId(1);
+pub const RAG_PUSH_ID: CommandId = CommandId(2);
 
-pub const GLOBAL_ID: InteractID<u8> = InteractID::Scope(0b0);
-pub const DOCUMENT_ID: InteractID<u8> = InteractID::Scope(0b0001_0000);
+pub const GLOBAL_ID: ScopeId = ScopeId(0);
+pub const DOCUMENT_ID: ScopeId = ScopeId(1);
 
 pub const DOCUMENT_CHARACTER: InteractID<char> = InteractID::Scope('^');
 pub const GLOBAL_CHARACTER: InteractID<char> = InteractID::Scope('_');
@@ -30,32 +49,31 @@ impl<ID> AsRef<ID> for InteractID<ID> {
     }
 }
 
-pub fn u8_to_binary_string(num: u8) -> String {
-    let mut result = String::with_capacity(8);
-    for i in (0..8).rev() {
-        if num & (1 << i) != 0 {
-            result.push('1');
-        } else {
-            result.push('0');
+impl From<InteractID<char>> for InteractID<String> {
+    fn from(id: InteractID<char>) -> Self {
+        match id {
+            InteractID::Scope(char) => InteractID::Scope(char.to_string()),
+            InteractID::Command(char) => InteractID::Command(char.to_string()),
         }
     }
-    result
 }
 
-pub fn human_readable_int(int: u8) -> String {
-    let command_masked = int & COMMAND_MASK;
-    let command_str = match command_masked {
-        _ if command_masked == *PUSH_ID.as_ref() => "PUSH",
-        _ if command_masked == *PROMPT_ID.as_ref() => "PROMPT",
-        _ if command_masked == *RAG_PUSH_ID.as_ref() => "RAG_PUSH",
-        other => &format!("UNKNOWN COMMAND ID: {}", u8_to_binary_string(other)),
+/// Custom scopes are named after the token they were registered with
+pub fn human_readable_int(int: InteractInteger, registry: &InteractRegistry) -> String {
+    let command_str = match int.command {
+        PUSH_ID => "PUSH".to_owned(),
+        PROMPT_ID => "PROMPT".to_owned(),
+        RAG_PUSH_ID => "RAG_PUSH".to_owned(),
+        CommandId(other) => format!("UNKNOWN COMMAND ID: {other}"),
     };
 
-    let scope_masked = int & SCOPE_MASK;
-    let scope_str = match scope_masked {
-        _ if scope_masked == *GLOBAL_ID.as_ref() => "GLOBAL",
-        _ if scope_masked == *DOCUMENT_ID.as_ref() => "DOCUMENT",
-        other => &format!("UNKNOWN SCOPE ID: {}", u8_to_binary_string(other)),
+    let scope_str = match int.scope {
+        GLOBAL_ID => "GLOBAL".to_owned(),
+        DOCUMENT_ID => "DOCUMENT".to_owned(),
+        other => match registry.get_scope_token(other) {
+            Some(token) => format!("CUSTOM({token})"),
+            None => format!("UNKNOWN SCOPE ID: {}", other.0),
+        },
     };
 
     format!("{command_str}_{scope_str}")
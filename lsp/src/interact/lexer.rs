@@ -1,4 +1,10 @@
-use super::{registry::InteractRegistry, InteractError, InteractResult};
+use super::{
+    args::{parse_args, InteractArg},
+    encoding::PositionEncoding,
+    id::{InteractInteger, PUSH_ID},
+    registry::InteractRegistry,
+    InteractError, InteractResult,
+};
 use crate::interact::comment_str_map::{get_comment_string_info, CommentStrInfo};
 use lsp_types::{Position, Range};
 use serde::{Deserialize, Serialize};
@@ -6,48 +12,98 @@ use std::{
     cmp::Ordering,
     fmt::{Debug, Display},
 };
-use tracing::warn;
+use tracing::{debug, warn};
 
 #[derive(Debug)]
 pub struct Lexer<'i> {
     input: &'i str,
     comment_str_info: CommentStrInfo<'i>,
     buffer: String,
-    position: usize,      // current position in input (points to current char)
-    read_position: usize, // current reading position in input (after current char)
-    ch: Option<char>,     // NONE if at end of input
+    buffer_start: TokenStart,   // where the contents of the buffer begin
+    encoding: PositionEncoding, // what current_char is counted in
+    position: usize,            // byte offset of the current char in input
+    read_position: usize,       // byte offset of the char after the current char
+    ch: Option<char>,           // NONE if at end of input
     current_line: usize,
     current_char: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ParsedComment {
-    interact: Option<u8>,
+    interact: Option<InteractInteger>,
+    /// Bytes of `content` taken up by the interact code and the whitespace before it
+    interact_len: usize,
+    /// Flags written right after the interact code
+    pub args: Vec<InteractArg>,
     pub content: String,
     pub range: Range,
 }
 
-#[derive(Debug, Clone)]
+/// Where a token begins in the lexed input, both as a byte offset and as the lexer's position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TokenStart {
+    offset: usize,
+    position: Position,
+}
+
+/// Tokens lexed up to the end of input, or up to the point where lexing was stopped
+#[derive(Debug)]
+struct LexedTokens {
+    vec: Vec<Token>,
+    comment_indices: Vec<usize>,
+    starts: Vec<TokenStart>,
+    stopped_at: Option<TokenStart>,
+}
+
+/// How many bytes past the end of a token the lexer may look while lexing it. Comment strings
+/// are at most 4 chars long and the lexer peeks one char past them
+const LEXER_LOOKAHEAD: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct TokenVec {
     vec: Vec<Token>,
     comment_indices: Vec<usize>,
+    starts: Vec<TokenStart>,
+    encoding: PositionEncoding,
+}
+
+/// Interact comments which changed between two lexes of a document
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommentDiff {
+    pub added: Vec<ParsedComment>,
+    pub removed: Vec<ParsedComment>,
+    /// Comments which are unchanged apart from their range, along with their previous range
+    pub moved: Vec<(Range, ParsedComment)>,
 }
 
 impl TokenVec {
-    pub fn new(vec: Vec<Token>, comment_indices: Vec<usize>) -> Self {
+    fn new(
+        vec: Vec<Token>,
+        comment_indices: Vec<usize>,
+        starts: Vec<TokenStart>,
+        encoding: PositionEncoding,
+    ) -> Self {
         for idx in comment_indices.iter() {
             match vec.iter().nth(*idx) {
                 Some(Token::Comment(_)) => {}
                 o => panic!("encountered {o:?} where Comment should be"),
             }
         }
+        assert_eq!(vec.len(), starts.len(), "every token should have a start");
 
         Self {
             vec,
             comment_indices,
+            starts,
+            encoding,
         }
     }
 
+    /// The encoding of every position in these tokens
+    pub fn encoding(&self) -> PositionEncoding {
+        self.encoding
+    }
+
     pub fn comment_indices(&self) -> &Vec<usize> {
         &self.comment_indices
     }
@@ -74,6 +130,241 @@ impl TokenVec {
     pub fn get(&self, idx: usize) -> Option<&Token> {
         self.vec.iter().nth(idx)
     }
+
+    /// Every push comment along with the block it pushes
+    pub fn pushes(&self) -> impl Iterator<Item = (&ParsedComment, &String)> {
+        self.comment_indices.iter().filter_map(|idx| {
+            match (&self.vec[*idx], self.vec.get(idx + 1)) {
+                (Token::Comment(comment), Some(Token::Block(block))) if comment.is_push() => {
+                    Some((comment, block))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Every push comment along with the first and last line of the code it pushes, blank lines
+    /// around the code are not counted
+    pub fn pushed_lines(&self) -> impl Iterator<Item = (&ParsedComment, (u32, u32))> {
+        self.comment_indices.iter().filter_map(|idx| {
+            match (&self.vec[*idx], self.vec.get(idx + 1)) {
+                (Token::Comment(comment), Some(Token::Block(block))) if comment.is_push() => {
+                    let code = block.trim_start();
+                    let leading = block[..block.len() - code.len()].matches('\n').count();
+                    let start = self.starts[idx + 1].position.line + leading as u32;
+                    let lines = block.trim().lines().count().max(1) as u32;
+                    Some((comment, (start, start + lines - 1)))
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Every block along with the position it starts at
+    pub fn blocks(&self) -> impl Iterator<Item = (&String, Position)> {
+        self.vec
+            .iter()
+            .zip(self.starts.iter())
+            .filter_map(|(token, start)| match token {
+                Token::Block(block) => Some((block, start.position)),
+                _ => None,
+            })
+    }
+
+    /// Lexing can only restart at the beginning of a block or of a comment
+    fn is_restartable(&self, idx: usize) -> bool {
+        match self.vec.get(idx) {
+            Some(Token::Block(_)) => true,
+            Some(Token::CommentStr) => matches!(self.vec.get(idx + 1), Some(Token::Comment(_))),
+            _ => false,
+        }
+    }
+
+    /// Index of a token at or after `from` which starts at `offset` and lexing can restart at
+    fn restartable_at(&self, offset: usize, from: usize) -> Option<usize> {
+        let first = from + self.starts[from..].partition_point(|s| s.offset < offset);
+        (first..self.starts.len())
+            .take_while(|idx| self.starts[*idx].offset == offset)
+            .find(|idx| self.is_restartable(*idx))
+    }
+
+    /// Updates tokens after the bytes in `replaced` were replaced with `inserted_len` bytes.
+    /// `text` is the whole text after the edit.
+    /// Lexing restarts at the last token the edit could not have affected and stops as soon as it
+    /// lines back up with a token from before the edit, every token after that is only shifted.
+    #[tracing::instrument(name = "splicing edit into TokenVec", skip(self, text, registry))]
+    pub fn splice(
+        &mut self,
+        text: &str,
+        ext: &str,
+        replaced: std::ops::Range<usize>,
+        inserted_len: usize,
+        registry: &InteractRegistry,
+    ) -> CommentDiff {
+        let edit_end = replaced.start + inserted_len;
+        let delta = inserted_len as isize - replaced.len() as isize;
+
+        let mut restart = self
+            .starts
+            .partition_point(|s| s.offset + LEXER_LOOKAHEAD <= replaced.start)
+            .saturating_sub(1);
+        while restart > 0 && !self.is_restartable(restart) {
+            restart -= 1;
+        }
+
+        let mut resync = None;
+        let lexed = Lexer::resume(text, ext, self.encoding, self.starts[restart]).lex_until(
+            registry,
+            |offset| {
+                if offset < edit_end {
+                    return false;
+                }
+                resync = self.restartable_at((offset as isize - delta) as usize, restart);
+                resync.is_some()
+            },
+        );
+        let tail = resync.unwrap_or(self.vec.len());
+
+        // A push before the restart token may push the first re-lexed block, and a push at the
+        // end of the re-lexed tokens pushes the first token of the tail
+        let window_start = match restart.checked_sub(1).map(|idx| &self.vec[idx]) {
+            Some(Token::Comment(_)) => restart - 1,
+            _ => restart,
+        };
+        let old_window = self.vec[window_start..(tail + 1).min(self.vec.len())].to_vec();
+
+        let mut moved = vec![];
+        if let (Some(tail), Some(new_start)) = (resync, lexed.stopped_at) {
+            let old_start = self.starts[tail];
+            for start in self.starts[tail..].iter_mut() {
+                start.offset = start.offset - old_start.offset + new_start.offset;
+                start.position =
+                    shift_position(start.position, old_start.position, new_start.position);
+            }
+            if old_start.position != new_start.position {
+                for token in self.vec[tail..].iter_mut() {
+                    if let Token::Comment(comment) = token {
+                        let old_range = comment.range;
+                        comment.range.start =
+                            shift_position(old_range.start, old_start.position, new_start.position);
+                        comment.range.end =
+                            shift_position(old_range.end, old_start.position, new_start.position);
+                        if comment.interact.is_some() {
+                            moved.push((old_range, comment.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        let removed_amt = tail - restart;
+        let inserted_amt = lexed.vec.len();
+        let kept_comments = self.comment_indices.partition_point(|idx| *idx < restart);
+        let tail_comments = self.comment_indices.partition_point(|idx| *idx < tail);
+        let shifted_comments: Vec<usize> = self.comment_indices[tail_comments..]
+            .iter()
+            .map(|idx| idx - removed_amt + inserted_amt)
+            .collect();
+        self.comment_indices.truncate(kept_comments);
+        self.comment_indices
+            .extend(lexed.comment_indices.into_iter().map(|idx| idx + restart));
+        self.comment_indices.extend(shifted_comments);
+
+        self.vec.splice(restart..tail, lexed.vec);
+        self.starts.splice(restart..tail, lexed.starts);
+
+        let new_window_end = (restart + inserted_amt + 1).min(self.vec.len());
+        let mut diff = CommentDiff::between(&old_window, &self.vec[window_start..new_window_end]);
+        diff.moved.append(&mut moved);
+        debug!(
+            "re-lexed {inserted_amt} tokens in place of {removed_amt}, {} tokens total",
+            self.vec.len()
+        );
+        diff
+    }
+}
+
+/// Moves a position which comes after `from` by however far `from` moved to get to `to`
+fn shift_position(pos: Position, from: Position, to: Position) -> Position {
+    if pos.line == from.line {
+        Position {
+            line: to.line,
+            character: pos.character - from.character + to.character,
+        }
+    } else {
+        Position {
+            line: pos.line - from.line + to.line,
+            character: pos.character,
+        }
+    }
+}
+
+impl CommentDiff {
+    /// Matches the interact comments of both token slices by their content and, for pushes, by
+    /// the block they push
+    pub fn between(old: &[Token], new: &[Token]) -> Self {
+        let mut unmatched: Vec<_> = interact_comments(old).collect();
+        let mut diff = Self::default();
+        for (comment, pushed) in interact_comments(new) {
+            match unmatched.iter().position(|(old_comment, old_pushed)| {
+                old_comment.interact == comment.interact
+                    && old_comment.content == comment.content
+                    && *old_pushed == pushed
+            }) {
+                Some(idx) => {
+                    let (old_comment, _) = unmatched.remove(idx);
+                    if old_comment.range != comment.range {
+                        diff.moved.push((old_comment.range, comment.clone()));
+                    }
+                }
+                None => diff.added.push(comment.clone()),
+            }
+        }
+        diff.removed = unmatched.into_iter().map(|(c, _)| c.clone()).collect();
+        diff
+    }
+
+    /// Treats every interact comment as removed from `old` and added back in `new`, for when a
+    /// document is replaced wholesale and everything it pushed should be refreshed
+    pub fn replaced(old: Option<&TokenVec>, new: &TokenVec) -> Self {
+        let comments = |tokens: &[Token]| -> Vec<ParsedComment> {
+            interact_comments(tokens).map(|(c, _)| c.clone()).collect()
+        };
+        Self {
+            added: comments(new.as_ref()),
+            removed: old
+                .map(|tokens| comments(tokens.as_ref()))
+                .unwrap_or_default(),
+            moved: vec![],
+        }
+    }
+
+    pub fn append(&mut self, mut other: Self) {
+        self.added.append(&mut other.added);
+        self.removed.append(&mut other.removed);
+        self.moved.append(&mut other.moved);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty()
+    }
+}
+
+/// Interact comments along with the block they push, if they are a push
+fn interact_comments(tokens: &[Token]) -> impl Iterator<Item = (&ParsedComment, Option<&String>)> {
+    tokens
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, token)| match token {
+            Token::Comment(comment) if comment.interact.is_some() => {
+                let pushed = match tokens.get(idx + 1) {
+                    Some(Token::Block(block)) if comment.is_push() => Some(block),
+                    _ => None,
+                };
+                Some((comment, pushed))
+            }
+            _ => None,
+        })
 }
 
 impl AsRef<Vec<Token>> for TokenVec {
@@ -99,7 +390,7 @@ impl IntoIterator for TokenVec {
 }
 
 /// Returns Ordering::Equal if the position is within the range, otherwise denotes which direction
-/// it is out of range
+/// it is out of range. Both have to be in the same position encoding
 pub fn cmp_pos_range(range: &Range, pos: &Position) -> Ordering {
     if pos.line < range.start.line
         || pos.character < range.start.character && pos.line == range.start.line
@@ -117,40 +408,75 @@ pub fn cmp_pos_range(range: &Range, pos: &Position) -> Ordering {
 }
 
 impl ParsedComment {
-    pub fn new(interact: Option<u8>, content: &str, range: Range) -> Self {
+    pub fn new(interact: Option<(InteractInteger, usize)>, content: &str, range: Range) -> Self {
+        let interact_len = interact.map_or(0, |(_, len)| len);
+        let args = match interact {
+            Some(_) => parse_args(&content[interact_len..], interact_len),
+            None => vec![],
+        };
         Self {
-            interact,
+            interact: interact.map(|(int, _)| int),
+            interact_len,
+            args,
             content: content.to_string(),
             range,
         }
     }
-    /// returns range and text of comment without interract
+
+    /// Position of the given byte offset into `content`, in the given encoding
+    fn position_at(&self, offset: usize, encoding: PositionEncoding) -> Position {
+        let before = &self.content[..offset];
+        let mut position = self.range.start;
+        match before.rsplit_once('\n') {
+            Some((lines, after)) => {
+                position.line += lines.matches('\n').count() as u32 + 1;
+                position.character = encoding.str_len(after) as u32;
+            }
+            None => position.character += encoding.str_len(before) as u32,
+        }
+        position
+    }
+
+    /// returns range and text of comment without interract or its arguments, with the range in
+    /// the given encoding
     /// returns none if there is no interact code
-    pub fn text_for_interact(&self) -> Option<(Range, String)> {
+    pub fn text_for_interact(&self, encoding: PositionEncoding) -> Option<(Range, String)> {
         self.interact.and_then(|_| {
-            // for now all interact codes have only 2 chars, no more no less.
-            // This will likely change in the future
-            let chars_amt = 2;
+            let text_start = self
+                .args
+                .last()
+                .map_or(self.interact_len, |arg| arg.span.end);
 
-            let whitespace_amt = self
-                .content
-                .chars()
-                .position(|c| !c.is_whitespace())
-                .unwrap();
+            let range = Range {
+                start: self.position_at(text_start, encoding),
+                end: self.range.end,
+            };
 
-            let mut range = self.range.clone();
+            Some((range, self.content[text_start..].to_owned()))
+        })
+    }
 
-            let skip_amt = chars_amt + whitespace_amt - 1;
-            range.start.character += skip_amt as u32;
-            let ret_str = self.content.chars().skip(skip_amt + 1).collect();
+    /// Range of one of this comment's arguments, in the given encoding
+    pub fn arg_range(&self, arg: &InteractArg, encoding: PositionEncoding) -> Range {
+        self.span_range(arg.span.clone(), encoding)
+    }
 
-            Some((range, ret_str))
-        })
+    /// Range of a byte span of `content`, in the given encoding
+    pub fn span_range(&self, span: std::ops::Range<usize>, encoding: PositionEncoding) -> Range {
+        Range {
+            start: self.position_at(span.start, encoding),
+            end: self.position_at(span.end, encoding),
+        }
     }
 
-    pub fn try_get_interact_integer(&self) -> InteractResult<u8> {
+    pub fn try_get_interact_integer(&self) -> InteractResult<InteractInteger> {
         self.interact.ok_or(InteractError::NoInteractInComment)
     }
+
+    /// Whether this comment pushes the block after it into an agent
+    pub fn is_push(&self) -> bool {
+        self.interact.is_some_and(|int| int.command == PUSH_ID)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -174,21 +500,42 @@ impl Display for Token {
 }
 
 impl<'i> Lexer<'i> {
-    pub fn new(input: &'i String, ext: &'i str) -> Self {
+    /// Positions are counted in UTF-16 unless set with `with_encoding`
+    pub fn new(input: &'i str, ext: &'i str) -> Self {
+        Self::resume(
+            input,
+            ext,
+            PositionEncoding::default(),
+            TokenStart {
+                offset: 0,
+                position: Position::default(),
+            },
+        )
+    }
+
+    pub fn with_encoding(mut self, encoding: PositionEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Creates a lexer which picks up at the start of a token from a previous lex of the input
+    fn resume(input: &'i str, ext: &'i str, encoding: PositionEncoding, start: TokenStart) -> Self {
         let comment_str_info = get_comment_string_info(ext).expect("no comment string");
 
+        let ch = input[start.offset..].chars().next();
         Self {
             input,
             buffer: String::new(),
-            position: 0,
-            read_position: 1,
-            ch: input.to_lowercase().chars().nth(0),
+            buffer_start: start,
+            encoding,
+            position: start.offset,
+            read_position: start.offset + ch.map(|c| c.len_utf8()).unwrap_or(0),
+            ch,
             comment_str_info,
-            current_line: 0,
-            current_char: 0,
+            current_line: start.position.line as usize,
+            current_char: start.position.character as usize,
         }
     }
-
     /// Checks that the current char is indeed the beginning of a slice that will result in the
     /// given &str
     fn at_beginning_of_slice(&self, slice: &str) -> bool {
@@ -233,6 +580,20 @@ impl<'i> Lexer<'i> {
         }
     }
 
+    fn current_start(&self) -> TokenStart {
+        TokenStart {
+            offset: self.position,
+            position: self.current_position(),
+        }
+    }
+
+    fn push_to_buffer(&mut self, c: char) {
+        if self.buffer.is_empty() {
+            self.buffer_start = self.current_start();
+        }
+        self.buffer.push(c);
+    }
+
     fn current_position(&self) -> Position {
         Position {
             line: self.current_line as u32,
@@ -241,31 +602,55 @@ impl<'i> Lexer<'i> {
     }
 
     fn progress_char(&mut self) {
-        if self.ch == Some('\n') {
-            self.current_line += 1;
-            self.current_char = 0;
+        match self.ch {
+            Some('\n') => {
+                self.current_line += 1;
+                self.current_char = 0;
+            }
+            Some(c) => self.current_char += self.encoding.char_len(c),
+            None => {}
         }
-        self.ch = self.input.chars().nth(self.read_position);
+        self.ch = self.input[self.read_position..].chars().next();
         self.position = self.read_position;
-        self.current_char += 1;
-        self.read_position += 1
+        self.read_position += self.ch.map(|c| c.len_utf8()).unwrap_or(0);
     }
 
     fn peek(&self) -> Option<char> {
-        self.input.chars().nth(self.read_position)
+        self.input[self.read_position..].chars().next()
     }
 
+    /// Offsets are only ever as long as a comment string, so walking chars here stays cheap
     fn peek_offset(&self, offset: usize) -> Option<char> {
-        self.input.chars().nth(self.read_position + offset)
+        self.input[self.read_position..].chars().nth(offset)
     }
 
-    #[tracing::instrument(name = "lex input into TokenVec")]
+    #[tracing::instrument(name = "lex input into TokenVec", skip_all)]
     pub fn lex_input(&mut self, registry: &InteractRegistry) -> TokenVec {
+        let lexed = self.lex_until(registry, |_| false);
+        let token_vec = TokenVec::new(
+            lexed.vec,
+            lexed.comment_indices,
+            lexed.starts,
+            self.encoding,
+        );
+        debug!(
+            "returning token vec with {} tokens and {} comments",
+            token_vec.vec.len(),
+            token_vec.comment_indices.len()
+        );
+        token_vec
+    }
+
+    /// Lexes until the end of input, or until `stop` returns true for the byte offset of a char
+    /// a new token would begin at
+    fn lex_until(
+        &mut self,
+        registry: &InteractRegistry,
+        mut stop: impl FnMut(usize) -> bool,
+    ) -> LexedTokens {
         let mut vec = vec![];
         let mut comment_indices = vec![];
-        let mut start_opt = Option::<Position>::None;
-        let mut end_opt = Option::<Position>::None;
-        // let mut buffer = String::new();
+        let mut starts = vec![];
 
         let singleline_comment_str = self.comment_str(None).unwrap().to_owned();
         let singleline_comment_first_char =
@@ -279,6 +664,14 @@ impl<'i> Lexer<'i> {
             .and_then(|str| str.chars().nth(0).and_then(|i| Some(i.to_owned())));
 
         while let Some(c) = self.ch {
+            if self.buffer.is_empty() && stop(self.position) {
+                return LexedTokens {
+                    vec,
+                    comment_indices,
+                    starts,
+                    stopped_at: Some(self.current_start()),
+                };
+            }
             match c {
                 _ if c == singleline_comment_first_char
                     || Some(c) == multiline_comment_start_first_char =>
@@ -293,7 +686,7 @@ impl<'i> Lexer<'i> {
                         .unwrap_or(false);
 
                     if !singleline_start && !multiline_start {
-                        self.buffer.push(c);
+                        self.push_to_buffer(c);
                         self.progress_char();
                         continue;
                     }
@@ -320,21 +713,27 @@ impl<'i> Lexer<'i> {
 
                     if !self.buffer.is_empty() {
                         vec.push(Token::Block(self.buffer.drain(..).collect::<String>()));
+                        starts.push(self.buffer_start);
                     }
 
+                    vec.push(Token::CommentStr);
+                    starts.push(self.current_start());
+
                     for _ in 0..start_slice.len() {
                         self.progress_char();
                     }
 
-                    start_opt = Some(self.current_position());
-                    vec.push(Token::CommentStr);
+                    let comment_start = self.current_start();
+                    let mut end_opt = Option::<Position>::None;
+                    let mut end_str_start = Option::<TokenStart>::None;
 
                     while let Some(peek) = self.peek() {
-                        self.buffer.push(self.ch.unwrap());
+                        self.push_to_buffer(self.ch.unwrap());
                         self.progress_char();
                         if peek == end_slice_first_char {
                             if self.at_beginning_of_slice(&end_slice) {
                                 end_opt = Some(self.current_position());
+                                end_str_start = Some(self.current_start());
                                 for _ in 0..end_slice.len() - 1 {
                                     self.progress_char();
                                 }
@@ -343,9 +742,21 @@ impl<'i> Lexer<'i> {
                         }
                     }
 
+                    // A comment that is never closed runs to the end of input
+                    let end = match end_opt {
+                        Some(end) => end,
+                        None => {
+                            if let Some(c) = self.ch {
+                                self.push_to_buffer(c);
+                                self.progress_char();
+                            }
+                            self.current_position()
+                        }
+                    };
+
                     let range = Range {
-                        start: start_opt.expect("start opt should be some at this point"),
-                        end: end_opt.expect("end opt should be some at this point"),
+                        start: comment_start.position,
+                        end,
                     };
 
                     let content = self.buffer.drain(..).collect::<String>();
@@ -353,30 +764,33 @@ impl<'i> Lexer<'i> {
                     let interact = registry.try_get_interact(&content);
 
                     comment_indices.push(vec.len());
-                    vec.push(Token::Comment(ParsedComment {
-                        interact,
-                        content,
-                        range,
-                    }));
+                    vec.push(Token::Comment(ParsedComment::new(
+                        interact, &content, range,
+                    )));
+                    starts.push(comment_start);
 
                     if multiline_start {
-                        vec.push(Token::CommentStr);
+                        if let Some(end_str_start) = end_str_start {
+                            vec.push(Token::CommentStr);
+                            starts.push(end_str_start);
+                        }
                     };
                 }
 
                 '\n' => {
-                    self.buffer.push(c);
+                    self.push_to_buffer(c);
                     if self.peek() == Some('\n') {
                         while self.peek() == Some('\n') {
                             self.progress_char();
-                            self.buffer.push(self.ch.expect("this should be some"));
+                            self.push_to_buffer(self.ch.expect("this should be some"));
                         }
-                        vec.push(Token::Block(self.buffer.drain(..).collect::<String>()))
+                        vec.push(Token::Block(self.buffer.drain(..).collect::<String>()));
+                        starts.push(self.buffer_start);
                     }
                 }
 
                 _ => {
-                    self.buffer.push(c);
+                    self.push_to_buffer(c);
                 }
             }
             self.progress_char();
@@ -384,13 +798,18 @@ impl<'i> Lexer<'i> {
 
         if !self.buffer.is_empty() {
             vec.push(Token::Block(self.buffer.drain(..).collect()));
+            starts.push(self.buffer_start);
         }
 
         vec.push(Token::End);
+        starts.push(self.current_start());
 
-        let token_vec = TokenVec::new(vec, comment_indices);
-        warn!("returning token vec: {token_vec:#?}");
-        token_vec
+        LexedTokens {
+            vec,
+            comment_indices,
+            starts,
+            stopped_at: None,
+        }
     }
 }
 mod tests {
@@ -1,7 +1,10 @@
+pub mod args;
 mod comment_str_map;
+pub mod encoding;
 mod error;
 pub mod id;
 pub mod lexer;
 pub mod registry;
+pub mod response;
 
 pub use error::{InteractError, InteractResult};
@@ -2,24 +2,27 @@ use super::{
     error::{InteractError, InteractResult},
     id::*,
 };
-use std::{collections::HashMap, fmt::Debug};
+use std::{collections::HashMap, fmt::Debug, ops::Range};
 use tracing::warn;
 
 #[derive(Debug)]
 pub struct InteractRegistry {
-    char_lookup: HashMap<InteractID<char>, InteractID<u8>>,
-    id_lookup: HashMap<InteractID<u8>, InteractID<char>>,
+    token_lookup: HashMap<InteractID<String>, InteractID<u16>>,
+    id_lookup: HashMap<InteractID<u16>, InteractID<String>>,
 }
 
 impl Default for InteractRegistry {
     fn default() -> Self {
         let mut registered = Self::new();
-        registered.insert(GLOBAL_CHARACTER, GLOBAL_ID);
-        registered.insert(DOCUMENT_CHARACTER, DOCUMENT_ID);
-
-        registered.insert(PUSH_CHARACTER, PUSH_ID);
-        registered.insert(RAG_PUSH_CHARACTER, RAG_PUSH_ID);
-        registered.insert(PROMPT_CHARACTER, PROMPT_ID);
+        registered.insert(GLOBAL_CHARACTER.into(), InteractID::Scope(GLOBAL_ID.0));
+        registered.insert(DOCUMENT_CHARACTER.into(), InteractID::Scope(DOCUMENT_ID.0));
+
+        registered.insert(PUSH_CHARACTER.into(), InteractID::Command(PUSH_ID.0));
+        registered.insert(
+            RAG_PUSH_CHARACTER.into(),
+            InteractID::Command(RAG_PUSH_ID.0),
+        );
+        registered.insert(PROMPT_CHARACTER.into(), InteractID::Command(PROMPT_ID.0));
         registered
     }
 }
@@ -27,175 +30,245 @@ impl Default for InteractRegistry {
 impl InteractRegistry {
     fn new() -> Self {
         Self {
-            char_lookup: HashMap::new(),
+            token_lookup: HashMap::new(),
             id_lookup: HashMap::new(),
         }
     }
 
-    fn insert(&mut self, char: InteractID<char>, id: InteractID<u8>) {
-        self.char_lookup.insert(char.clone(), id);
-        self.id_lookup.insert(id, char);
+    fn insert(&mut self, token: InteractID<String>, id: InteractID<u16>) {
+        self.token_lookup.insert(token.clone(), id);
+        self.id_lookup.insert(id, token);
 
-        if self.char_lookup.len() != self.id_lookup.len() {
+        if self.token_lookup.len() != self.id_lookup.len() {
             warn!("Lookup tables out of sync: {self:#?}");
-            for (char, id) in self.char_lookup.iter() {
-                if self.id_lookup.get(&id) != Some(&char) {
-                    warn!("mismatch\nchar lookup: {char:#?}\nid: {id:#?}")
+            for (token, id) in self.token_lookup.iter() {
+                if self.id_lookup.get(id) != Some(token) {
+                    warn!("mismatch\ntoken lookup: {token:#?}\nid: {id:#?}")
                 }
             }
             panic!("lookup tables should be synced")
         }
     }
 
-    /// Interact integer should be pre masked
-    pub fn get_interact_char(&self, interact: InteractID<u8>) -> Option<&InteractID<char>> {
-        self.id_lookup.get(&interact)
+    pub fn get_scope_token(&self, scope: ScopeId) -> Option<&str> {
+        self.id_lookup
+            .get(&InteractID::Scope(scope.0))
+            .map(|token| token.as_ref().as_str())
     }
 
-    pub fn get_interact_integer(&self, interact: InteractID<char>) -> Option<&InteractID<u8>> {
-        self.char_lookup.get(&interact)
+    pub fn get_command_token(&self, command: CommandId) -> Option<&str> {
+        self.id_lookup
+            .get(&InteractID::Command(command.0))
+            .map(|token| token.as_ref().as_str())
     }
 
-    pub fn register_scope(&mut self, char: &char) -> InteractResult<()> {
-        let max = self.max_scope_id();
-        warn!("registering scope for char: {char}\ncurrent max: {max}");
-
-        let id = Self::increment_masked_value(max, SCOPE_MASK)?;
-        warn!("id: {id}");
-
-        if id == SCOPE_MASK {
-            return Err(InteractError::RegistryFull);
-        }
-
-        self.insert(InteractID::Scope(*char), InteractID::Scope(id));
-        Ok(())
+    pub fn get_scope_id(&self, token: &str) -> Option<ScopeId> {
+        self.token_lookup
+            .get(&InteractID::Scope(token.to_owned()))
+            .map(|id| ScopeId(*id.as_ref()))
     }
 
-    fn all_registered_scope_ids(&self) -> Vec<u8> {
-        self.char_lookup
-            .iter()
-            .filter_map(|(char, id)| {
-                if let InteractID::<char>::Scope(_) = char {
-                    Some(*id.as_ref())
-                } else {
-                    None
-                }
-            })
-            .collect()
+    pub fn get_command_id(&self, token: &str) -> Option<CommandId> {
+        self.token_lookup
+            .get(&InteractID::Command(token.to_owned()))
+            .map(|id| CommandId(*id.as_ref()))
     }
 
-    fn all_registered_command_ids(&self) -> Vec<u8> {
-        self.char_lookup
-            .iter()
-            .filter_map(|(char, id)| {
-                if let InteractID::<char>::Command(_) = char {
-                    Some(*id.as_ref())
-                } else {
-                    None
-                }
-            })
-            .collect()
-    }
-
-    fn increment_masked_value(value: u8, mask: u8) -> InteractResult<u8> {
-        if value == mask {
-            return Err(InteractError::RegistryFull);
+    /// Scope tokens can be any number of characters, as long as they contain no whitespace
+    pub fn register_scope(&mut self, token: &str) -> InteractResult<ScopeId> {
+        if token.is_empty() || token.contains(char::is_whitespace) {
+            return Err(InteractError::InvalidScopeToken(token.to_owned()));
         }
-        let masked_value = value & mask;
-
-        let shift_amount = mask.trailing_zeros();
-        let incremented_value = ((masked_value >> shift_amount) + 1) << shift_amount;
-
-        let new_value = (value & !mask) | (incremented_value & mask);
+        let max = self.max_scope_id();
+        warn!("registering scope for token: {token}\ncurrent max: {max:?}");
 
-        if new_value > mask {
-            panic!("overflow when incrementing masked value");
-        }
+        let id = ScopeId(max.0.checked_add(1).ok_or(InteractError::RegistryFull)?);
+        warn!("id: {id:?}");
 
-        Ok(new_value)
+        self.insert(InteractID::Scope(token.to_owned()), InteractID::Scope(id.0));
+        Ok(id)
     }
 
-    fn max_scope_id(&self) -> u8 {
-        *self
-            .id_lookup
+    fn max_scope_id(&self) -> ScopeId {
+        self.id_lookup
             .keys()
-            .collect::<Vec<&InteractID<u8>>>()
-            .iter_mut()
-            .map(|id| {
-                let mut val: u8 = *id.as_ref();
-                if let InteractID::<u8>::Scope(_) = *id {
-                    val <<= 4;
-                }
-                val
+            .filter_map(|id| match id {
+                InteractID::Scope(id) => Some(ScopeId(*id)),
+                InteractID::Command(_) => None,
             })
-            .collect::<Vec<u8>>()
-            .iter()
             .max()
             .expect("no shot this fails")
     }
 
-    pub fn try_get_interact(&self, string: &String) -> Option<u8> {
-        let first_non_whitespace_pos = string.chars().position(|c| !c.is_whitespace())?;
+    /// Finds the longest registered token of the kind that `str` starts with, returning its id
+    /// and byte length
+    fn longest_token_match(
+        &self,
+        str: &str,
+        is_kind: fn(&InteractID<String>) -> bool,
+    ) -> Option<(u16, usize)> {
+        self.token_lookup
+            .iter()
+            .filter(|(token, _)| is_kind(token) && str.starts_with(token.as_ref().as_str()))
+            .map(|(token, id)| (*id.as_ref(), token.as_ref().len()))
+            .max_by_key(|(_, len)| *len)
+    }
 
-        let command_char = string.chars().nth(first_non_whitespace_pos)?;
-        let command_id = *self
-            .char_lookup
-            .keys()
-            .find(|ch| InteractID::<char>::Command(command_char) == **ch)
-            .and_then(|ic| self.char_lookup.get(ic))?;
+    /// Returns the interact at the start of the string, along with how many bytes of the string
+    /// its leading whitespace, command token and scope token take up
+    pub fn try_get_interact(&self, string: &str) -> Option<(InteractInteger, usize)> {
+        let whitespace_len = string.len() - string.trim_start().len();
 
-        let scope_char = string.chars().nth(first_non_whitespace_pos + 1)?;
-        let scope_id = *self
-            .char_lookup
-            .keys()
-            .find(|ch| InteractID::<char>::Scope(scope_char) == **ch)
-            .and_then(|ic| self.char_lookup.get(ic))?;
+        let (command, command_len) = self.longest_token_match(&string[whitespace_len..], |t| {
+            matches!(t, InteractID::Command(_))
+        })?;
 
-        Some(command_id.as_ref() + scope_id.as_ref())
-    }
+        let (scope, scope_len) = self
+            .longest_token_match(&string[whitespace_len + command_len..], |t| {
+                matches!(t, InteractID::Scope(_))
+            })?;
 
-    /// Given a byte, returns the associated command and scope ids.
-    /// Will error if either the command or scope id are not within the registry
-    pub fn interract_tuple(&self, id: u8) -> InteractResult<(InteractID<u8>, InteractID<u8>)> {
-        let command = id & COMMAND_MASK;
-        let scope = id & SCOPE_MASK;
+        Some((
+            InteractInteger::new(CommandId(command), ScopeId(scope)),
+            whitespace_len + command_len + scope_len,
+        ))
+    }
+    /// Every registered scope token, sorted
+    pub fn scope_tokens(&self) -> Vec<&str> {
+        let mut tokens: Vec<&str> = self
+            .token_lookup
+            .keys()
+            .filter_map(|token| match token {
+                InteractID::Scope(token) => Some(token.as_str()),
+                InteractID::Command(_) => None,
+            })
+            .collect();
+        tokens.sort();
+        tokens
+    }
 
-        if !self.all_registered_command_ids().contains(&command)
-            || !self.all_registered_scope_ids().contains(&scope)
-        {
-            return Err(InteractError::InvalidInteractId(id));
+    /// For a string starting with a command followed by a single character which is not a
+    /// registered scope, returns the command and the byte span of that character. Longer words
+    /// and digits after a command are left alone, so comments like `@param` or `+1` are not
+    /// mistaken for interacts
+    pub fn try_get_unknown_scope(&self, string: &str) -> Option<(CommandId, Range<usize>)> {
+        if self.try_get_interact(string).is_some() {
+            return None;
         }
-
-        let command = InteractID::<u8>::Command(command);
-        let scope = InteractID::<u8>::Scope(scope);
-
-        return Ok((command, scope));
+        let whitespace_len = string.len() - string.trim_start().len();
+        let (command, command_len) = self.longest_token_match(&string[whitespace_len..], |t| {
+            matches!(t, InteractID::Command(_))
+        })?;
+
+        let start = whitespace_len + command_len;
+        let mut chars = string[start..].chars();
+        let scope = chars
+            .next()
+            .filter(|c| !c.is_whitespace() && !c.is_ascii_digit())?;
+        if chars.next().is_some_and(|c| !c.is_whitespace()) {
+            return None;
+        }
+        Some((CommandId(command), start..start + scope.len_utf8()))
     }
 }
 
 mod tests {
+    #[allow(unused)]
     use super::InteractRegistry;
-    use crate::interact::id::SCOPE_MASK;
+    #[allow(unused)]
+    use crate::interact::id::{
+        human_readable_int, InteractInteger, ScopeId, DOCUMENT_ID, GLOBAL_ID, PROMPT_ID, PUSH_ID,
+    };
 
     #[test]
-    fn incrementation_works() {
-        let val = 0b0001_0000;
-        let inc = InteractRegistry::increment_masked_value(val, SCOPE_MASK).unwrap();
-        assert_eq!(inc, 0b0010_0000);
+    fn registered_scopes_get_their_own_ids() {
+        let mut registry = InteractRegistry::default();
+        registry.register_scope("c").unwrap();
+        registry.register_scope("b").unwrap();
+
+        let c = registry.get_scope_id("c").unwrap();
+        let b = registry.get_scope_id("b").unwrap();
+        assert_eq!(c, ScopeId(2));
+        assert_eq!(b, ScopeId(3));
+
+        let (pushed_to_c, len) = registry.try_get_interact(" +c").unwrap();
+        assert_eq!(pushed_to_c, InteractInteger::new(PUSH_ID, c));
+        assert_eq!(len, 3);
+        assert_ne!(pushed_to_c.scope, DOCUMENT_ID);
+        assert_eq!(registry.get_scope_token(pushed_to_c.scope), Some("c"));
+    }
+
+    #[test]
+    fn registers_many_scopes() {
+        let mut registry = InteractRegistry::default();
+        let chars: Vec<char> = ('\u{4e00}'..).take(500).collect();
+        for char in chars.iter() {
+            registry.register_scope(&char.to_string()).unwrap();
+        }
 
-        let val = 0b0010_0000;
-        let inc = InteractRegistry::increment_masked_value(val, SCOPE_MASK).unwrap();
-        assert_eq!(inc, 0b0011_0000);
+        for (i, char) in chars.iter().enumerate() {
+            let scope = registry.get_scope_id(&char.to_string()).unwrap();
+            assert_eq!(scope, ScopeId(i as u16 + 2));
+            assert_eq!(
+                registry.get_scope_token(scope),
+                Some(char.to_string().as_str())
+            );
+        }
 
-        let val = 0b0011_0000;
-        let inc = InteractRegistry::increment_masked_value(val, SCOPE_MASK).unwrap();
-        assert_eq!(inc, 0b0100_0000);
+        let last = chars.last().unwrap();
+        let (interact, _) = registry
+            .try_get_interact(&format!(" @{last} what"))
+            .unwrap();
+        assert_eq!(interact.command, PROMPT_ID);
+        assert_eq!(
+            human_readable_int(interact, &registry),
+            format!("PROMPT_CUSTOM({last})")
+        );
+    }
 
-        let val = 0b0110_0000;
-        let inc = InteractRegistry::increment_masked_value(val, SCOPE_MASK).unwrap();
-        assert_eq!(inc, 0b0111_0000);
+    #[test]
+    fn matches_longest_registered_token() {
+        let mut registry = InteractRegistry::default();
+        let review = registry.register_scope("review").unwrap();
+        let rev = registry.register_scope("rev").unwrap();
+
+        let (interact, len) = registry.try_get_interact("  @review this").unwrap();
+        assert_eq!(interact, InteractInteger::new(PROMPT_ID, review));
+        assert_eq!(len, "  @review".len());
+
+        let (interact, len) = registry.try_get_interact("@revi").unwrap();
+        assert_eq!(interact, InteractInteger::new(PROMPT_ID, rev));
+        assert_eq!(len, "@rev".len());
+
+        let (interact, len) = registry.try_get_interact("@_ still works").unwrap();
+        assert_eq!(interact, InteractInteger::new(PROMPT_ID, GLOBAL_ID));
+        assert_eq!(len, "@_".len());
+
+        assert!(registry.try_get_interact("@unregistered").is_none());
+        assert!(registry.try_get_interact("   ").is_none());
+        assert_eq!(
+            human_readable_int(InteractInteger::new(PUSH_ID, review), &registry),
+            "PUSH_CUSTOM(review)"
+        );
+    }
 
-        let inc = InteractRegistry::increment_masked_value(SCOPE_MASK, SCOPE_MASK);
-        assert!(inc.is_err());
+    #[test]
+    fn finds_unknown_scopes_after_commands() {
+        let mut registry = InteractRegistry::default();
+        registry.register_scope("c").unwrap();
+
+        let (command, span) = registry.try_get_unknown_scope("  @x what").unwrap();
+        assert_eq!(command, PROMPT_ID);
+        assert_eq!(span, 3..4);
+        let (command, span) = registry.try_get_unknown_scope(" +*").unwrap();
+        assert_eq!(command, PUSH_ID);
+        assert_eq!(span, 2..3);
+
+        assert!(registry.try_get_unknown_scope(" @c what").is_none());
+        assert!(registry.try_get_unknown_scope(" @param foo").is_none());
+        assert!(registry.try_get_unknown_scope(" +1 to this").is_none());
+        assert!(registry.try_get_unknown_scope(" @ what").is_none());
+        assert!(registry.try_get_unknown_scope(" just a comment").is_none());
+        assert_eq!(registry.scope_tokens(), vec!["^", "_", "c"]);
     }
 }
@@ -0,0 +1,449 @@
+use super::{
+    comment_str_map::{get_comment_string_info, CommentStrInfo},
+    encoding::PositionEncoding,
+    InteractResult,
+};
+use lsp_types::{Position, Range, TextEdit};
+
+/// First words of every response written into a buffer, used to find it again
+const RESPONSE_HEADER: &str = "espx response";
+/// Starts every line of a response written in single line comments, so that no line of the
+/// response can be mistaken for an interact
+const SINGLELINE_RESPONSE_PREFIX: &str = "|";
+
+/// The extension's multiline comment syntax, or single line comments if it has none, indented to
+/// match the prompt
+#[derive(Debug, Clone)]
+struct ResponseSyntax {
+    indent: String,
+    singleline: String,
+    multiline: Option<(String, String)>,
+}
+
+impl ResponseSyntax {
+    fn new(info: &CommentStrInfo, indent: &str) -> Self {
+        Self {
+            indent: indent.to_owned(),
+            singleline: info.singleline().to_owned(),
+            multiline: info
+                .multiline_start()
+                .zip(info.multiline_end())
+                .map(|(start, end)| (start.to_owned(), end.to_owned())),
+        }
+    }
+
+    fn header(&self) -> String {
+        let indent = &self.indent;
+        match &self.multiline {
+            Some((start, _)) => format!("{indent}{start} {RESPONSE_HEADER}"),
+            None => format!("{indent}{} {RESPONSE_HEADER}", self.singleline),
+        }
+    }
+
+    /// Written before every line of the response
+    fn line_prefix(&self) -> String {
+        match self.multiline {
+            Some(_) => self.indent.clone(),
+            None => format!(
+                "{}{} {SINGLELINE_RESPONSE_PREFIX} ",
+                self.indent, self.singleline
+            ),
+        }
+    }
+
+    fn footer(&self) -> Option<String> {
+        let (_, end) = self.multiline.as_ref()?;
+        Some(format!("{}{end}", self.indent))
+    }
+
+    /// The end of the multiline comment, along with what it is replaced with in the response so
+    /// that the response can't close the comment early
+    fn escaped_end(&self) -> Option<(&str, String)> {
+        let (_, end) = self.multiline.as_ref()?;
+        let (first, rest) = end.split_at(end.chars().next().map_or(0, char::len_utf8));
+        Some((end, format!("{first} {rest}")))
+    }
+
+    fn comment(&self, response: &str) -> String {
+        let prefix = self.line_prefix();
+        let mut lines = vec![self.header()];
+        for line in response.trim().lines() {
+            let line = match self.escaped_end() {
+                Some((end, escaped)) => line.replace(end, &escaped),
+                None => line.to_owned(),
+            };
+            lines.push(format!("{prefix}{line}").trim_end().to_owned());
+        }
+        lines.extend(self.footer());
+        lines.join("\n")
+    }
+}
+
+/// First and last line of a response written right after `line`, in either comment syntax
+fn previous_response_lines(
+    lines: &[&str],
+    info: &CommentStrInfo,
+    line: usize,
+) -> Option<(usize, usize)> {
+    let first = line + 1;
+    let header = lines.get(first)?.trim();
+
+    if let (Some(start), Some(end)) = (info.multiline_start(), info.multiline_end()) {
+        if header == format!("{start} {RESPONSE_HEADER}") {
+            return (first..lines.len())
+                .find(|idx| lines[*idx].contains(end))
+                .map(|last| (first, last));
+        }
+    }
+
+    let single = info.singleline();
+    if header == format!("{single} {RESPONSE_HEADER}") {
+        let response_line = format!("{single} {SINGLELINE_RESPONSE_PREFIX}");
+        let last = (first + 1..lines.len())
+            .take_while(|idx| lines[*idx].trim_start().starts_with(&response_line))
+            .last()
+            .unwrap_or(first);
+        return Some((first, last));
+    }
+    None
+}
+
+/// Where a response is written, either over the previous response or on the line after the prompt
+struct ResponsePlacement {
+    range: Range,
+    /// The prompt is on the last line, so the response has to start a new one
+    leading_newline: bool,
+    /// There is more text after the response, which has to stay on its own line
+    trailing_newline: bool,
+    header_line: u32,
+}
+
+impl ResponsePlacement {
+    fn new(
+        text: &str,
+        info: &CommentStrInfo,
+        prompt_range: Range,
+        encoding: PositionEncoding,
+    ) -> Self {
+        let lines: Vec<&str> = text.split('\n').collect();
+        let prompt_line = (prompt_range.end.line as usize).min(lines.len() - 1);
+        let end_of_line = |idx: usize| Position {
+            line: idx as u32,
+            character: encoding.str_len(lines[idx]) as u32,
+        };
+        let line_start = |idx: usize| Position::new(idx as u32, 0);
+
+        match previous_response_lines(&lines, info, prompt_line) {
+            Some((first, last)) => {
+                let trailing_newline = last + 1 < lines.len();
+                let end = match trailing_newline {
+                    true => line_start(last + 1),
+                    false => end_of_line(last),
+                };
+                Self {
+                    range: Range::new(line_start(first), end),
+                    leading_newline: false,
+                    trailing_newline,
+                    header_line: first as u32,
+                }
+            }
+            None if prompt_line + 1 < lines.len() => Self {
+                range: Range::new(line_start(prompt_line + 1), line_start(prompt_line + 1)),
+                leading_newline: false,
+                trailing_newline: true,
+                header_line: prompt_line as u32 + 1,
+            },
+            None => Self {
+                range: Range::new(end_of_line(prompt_line), end_of_line(prompt_line)),
+                leading_newline: true,
+                trailing_newline: false,
+                header_line: prompt_line as u32 + 1,
+            },
+        }
+    }
+
+    fn edit(&self, commented: &str) -> TextEdit {
+        let leading = if self.leading_newline { "\n" } else { "" };
+        let trailing = if self.trailing_newline { "\n" } else { "" };
+        TextEdit {
+            range: self.range,
+            new_text: format!("{leading}{commented}{trailing}"),
+        }
+    }
+}
+
+fn indent_of_prompt(text: &str, prompt_range: Range) -> String {
+    text.split('\n')
+        .nth(prompt_range.start.line as usize)
+        .map(|line| line.chars().take_while(|c| c.is_whitespace()).collect())
+        .unwrap_or_default()
+}
+
+/// Edit that writes the response as a comment under the prompt comment, replacing the response
+/// from the last time the prompt was run if it is still there
+pub fn response_edit(
+    text: &str,
+    ext: &str,
+    prompt_range: Range,
+    response: &str,
+    encoding: PositionEncoding,
+) -> InteractResult<TextEdit> {
+    let info = get_comment_string_info(ext)?;
+    let syntax = ResponseSyntax::new(&info, &indent_of_prompt(text, prompt_range));
+    let placement = ResponsePlacement::new(text, &info, prompt_range, encoding);
+    Ok(placement.edit(&syntax.comment(response)))
+}
+
+/// Edit that writes the answer as a comment on the lines above `range`, indented to match its
+/// first line
+pub fn answer_above_edit(
+    text: &str,
+    ext: &str,
+    range: Range,
+    answer: &str,
+) -> InteractResult<TextEdit> {
+    let info = get_comment_string_info(ext)?;
+    let syntax = ResponseSyntax::new(&info, &indent_of_prompt(text, range));
+    let start = Position::new(range.start.line, 0);
+    Ok(TextEdit {
+        range: Range::new(start, start),
+        new_text: format!("{}\n", syntax.comment(answer)),
+    })
+}
+
+/// Comments a response one piece at a time as it is streamed in, ending up in the same place and
+/// syntax as [`response_edit`]
+#[derive(Debug, Clone)]
+pub struct ResponseStream {
+    syntax: ResponseSyntax,
+    start: Position,
+    /// Trailing whitespace, or what could be the start of the comment's end string, kept until
+    /// the next piece shows what it is
+    held: String,
+    started: bool,
+}
+
+/// Edit that writes an empty response under the prompt comment, along with the stream that fills
+/// it in
+pub fn begin_response_stream(
+    text: &str,
+    ext: &str,
+    prompt_range: Range,
+    encoding: PositionEncoding,
+) -> InteractResult<(TextEdit, ResponseStream)> {
+    let info = get_comment_string_info(ext)?;
+    let syntax = ResponseSyntax::new(&info, &indent_of_prompt(text, prompt_range));
+    let placement = ResponsePlacement::new(text, &info, prompt_range, encoding);
+    let prefix = syntax.line_prefix();
+    let edit = placement.edit(&format!("{}\n{prefix}", syntax.header()));
+    let stream = ResponseStream {
+        start: Position::new(placement.header_line + 1, encoding.str_len(&prefix) as u32),
+        syntax,
+        held: String::new(),
+        started: false,
+    };
+    Ok((edit, stream))
+}
+
+impl ResponseStream {
+    /// Where the first piece of the response is inserted
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    /// The text to insert for the next piece of the response
+    pub fn comment_piece(&mut self, piece: &str) -> String {
+        let mut text = std::mem::take(&mut self.held) + piece;
+        if !self.started {
+            text = text.trim_start().to_owned();
+            if text.is_empty() {
+                return text;
+            }
+            self.started = true;
+        }
+
+        let mut hold_from = text.trim_end().len();
+        if let Some((end, escaped)) = self.syntax.escaped_end() {
+            text = text.replace(end, &escaped);
+            hold_from = text.trim_end().len();
+            if let Some(partial) = (1..end.len())
+                .rev()
+                .filter(|len| end.is_char_boundary(*len))
+                .find(|len| text.ends_with(&end[..*len]))
+            {
+                hold_from = text.len() - partial;
+            }
+        }
+        self.held = text.split_off(hold_from);
+        text.replace('\n', &format!("\n{}", self.syntax.line_prefix()))
+    }
+
+    /// The text to insert once the response is finished, closing the comment
+    pub fn finish(&mut self) -> String {
+        let held = std::mem::take(&mut self.held);
+        let mut text = held.trim_end().to_owned();
+        if let Some(footer) = self.syntax.footer() {
+            text.push('\n');
+            text.push_str(&footer);
+        }
+        text
+    }
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+    #[allow(unused)]
+    use crate::interact::{
+        lexer::{Lexer, ParsedComment, Token},
+        registry::InteractRegistry,
+    };
+
+    #[allow(unused)]
+    fn apply(text: &str, edit: &TextEdit) -> String {
+        let offset = |pos: Position| -> usize {
+            text.split_inclusive('\n')
+                .take(pos.line as usize)
+                .map(str::len)
+                .sum::<usize>()
+                + pos.character as usize
+        };
+        let mut text = text.to_owned();
+        text.replace_range(
+            offset(edit.range.start)..offset(edit.range.end),
+            &edit.new_text,
+        );
+        text
+    }
+
+    #[allow(unused)]
+    fn interacts(text: &str, ext: &str) -> Vec<ParsedComment> {
+        let tokens = Lexer::new(text, ext).lex_input(&InteractRegistry::default());
+        tokens
+            .comment_indices()
+            .iter()
+            .filter_map(|idx| match tokens.get(*idx) {
+                Some(Token::Comment(c)) if c.try_get_interact_integer().is_ok() => Some(c.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[allow(unused)]
+    fn prompt_range(text: &str, ext: &str) -> Range {
+        interacts(text, ext)[0].range
+    }
+
+    #[test]
+    fn writes_and_replaces_multiline_response() {
+        let text = "fn main() {\n    // @_ what is this\n    let x = 1;\n}\n";
+        let range = prompt_range(text, "rs");
+        let edit = response_edit(
+            text,
+            "rs",
+            range,
+            "a variable\n\nset to */ one",
+            PositionEncoding::Utf16,
+        )
+        .unwrap();
+        let answered = apply(text, &edit);
+        assert_eq!(
+            answered,
+            "fn main() {\n    // @_ what is this\n    /* espx response\n    a variable\n\n    set to * / one\n    */\n    let x = 1;\n}\n"
+        );
+
+        let range = prompt_range(&answered, "rs");
+        let edit = response_edit(
+            &answered,
+            "rs",
+            range,
+            "something else",
+            PositionEncoding::Utf16,
+        )
+        .unwrap();
+        assert_eq!(
+            apply(&answered, &edit),
+            "fn main() {\n    // @_ what is this\n    /* espx response\n    something else\n    */\n    let x = 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn writes_and_replaces_singleline_response() {
+        let text = "# @_ what is this";
+        let range = prompt_range(text, "py");
+        let edit = response_edit(
+            text,
+            "py",
+            range,
+            "@_ a prompt?\nno",
+            PositionEncoding::Utf16,
+        )
+        .unwrap();
+        let answered = apply(text, &edit);
+        assert_eq!(
+            answered,
+            "# @_ what is this\n# espx response\n# | @_ a prompt?\n# | no"
+        );
+
+        // only the prompt is an interact, not the response
+        assert_eq!(interacts(&answered, "py").len(), 1);
+
+        let with_code = format!("{answered}\n# unrelated comment\nx = 1\n");
+        let range = prompt_range(&with_code, "py");
+        let edit = response_edit(&with_code, "py", range, "yes", PositionEncoding::Utf16).unwrap();
+        assert_eq!(
+            apply(&with_code, &edit),
+            "# @_ what is this\n# espx response\n# | yes\n# unrelated comment\nx = 1\n"
+        );
+    }
+
+    #[test]
+    fn streamed_response_matches_whole_response() {
+        let pieces = ["\n\n", "a var", "iable\n", "set to *", "/ one", "  \n\n"];
+        for (text, ext) in [
+            (
+                "fn main() {\n    // @_ what is this\n    let x = 1;\n}\n",
+                "rs",
+            ),
+            ("# @_ what is this", "py"),
+        ] {
+            let range = prompt_range(text, ext);
+            let whole =
+                response_edit(text, ext, range, &pieces.concat(), PositionEncoding::Utf16).unwrap();
+
+            let (edit, mut stream) =
+                begin_response_stream(text, ext, range, PositionEncoding::Utf16).unwrap();
+            let begun = apply(text, &edit);
+            let mut streamed: String = pieces.iter().map(|p| stream.comment_piece(p)).collect();
+            streamed.push_str(&stream.finish());
+            let start = stream.start();
+            let finished = apply(
+                &begun,
+                &TextEdit {
+                    range: Range::new(start, start),
+                    new_text: streamed,
+                },
+            );
+
+            assert_eq!(finished, apply(text, &whole));
+        }
+    }
+
+    #[test]
+    fn writes_answer_above_selection() {
+        let text = "fn main() {\n    let x = 1;\n    let y = x;\n}\n";
+        let selection = Range::new(Position::new(1, 8), Position::new(2, 14));
+        let edit = answer_above_edit(text, "rs", selection, "two variables").unwrap();
+        assert_eq!(
+            apply(text, &edit),
+            "fn main() {\n    /* espx response\n    two variables\n    */\n    let x = 1;\n    let y = x;\n}\n"
+        );
+
+        let text = "x = 1\n";
+        let selection = Range::new(Position::new(0, 0), Position::new(0, 5));
+        let edit = answer_above_edit(text, "py", selection, "@_ one").unwrap();
+        let answered = apply(text, &edit);
+        assert_eq!(answered, "# espx response\n# | @_ one\nx = 1\n");
+        assert!(interacts(&answered, "py").is_empty());
+    }
+}
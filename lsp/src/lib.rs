@@ -7,31 +7,39 @@ pub mod handle;
 pub mod interact;
 pub mod state;
 pub mod util;
-use crate::handle::buffer_operations::BufferOpChannelStatus;
 use anyhow::Result;
 use config::Config;
+use crossbeam_channel::Sender;
+use handle::{buffer_operations::new_progress_token, dispatch::Dispatcher};
+use interact::encoding::PositionEncoding;
 use lsp_server::{Connection, Message, Notification};
 use lsp_types::{
-    CodeActionProviderCapability, DiagnosticServerCapabilities, InitializeParams, MessageType,
-    ProgressParams, ProgressToken, ServerCapabilities, ShowMessageParams,
+    request::WorkDoneProgressCreate, CodeActionProviderCapability, DiagnosticServerCapabilities,
+    ExecuteCommandOptions, InitializeParams, InitializeResult, ProgressParams, ServerCapabilities,
     TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
-    TextDocumentSyncSaveOptions, WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressEnd,
-    WorkDoneProgressOptions, WorkDoneProgressReport,
+    TextDocumentSyncSaveOptions, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressOptions,
+    WorkDoneProgressReport,
 };
 use state::SharedState;
-use tracing::{debug, info, warn};
+use tracing::{info, warn};
 
-async fn main_loop(
-    mut connection: Connection,
-    params: serde_json::Value,
-    mut state: SharedState,
-    // config: Config,
-) -> Result<()> {
-    let _params: InitializeParams = serde_json::from_value(params).unwrap();
-    connection.sender.send(Message::Notification(Notification {
-        method: "window/workDoneProgress/create".to_string(),
+/// Reports what was loaded on startup as work done progress, once the client has created its token
+async fn report_initialization(state: SharedState, sender: Sender<Message>) -> Result<()> {
+    let token = new_progress_token();
+    let (request, pending) =
+        state
+            .outgoing
+            .request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                token: token.clone(),
+            })?;
+    sender.send(Message::Request(request))?;
+    pending.response().await?;
+
+    sender.send(Message::Notification(Notification {
+        method: "$/progress".to_string(),
         params: serde_json::to_value(ProgressParams {
-            token: ProgressToken::String("Initializing".to_owned()),
+            token: token.clone(),
             value: lsp_types::ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(
                 WorkDoneProgressBegin {
                     title: "Initializing".to_owned(),
@@ -41,16 +49,16 @@ async fn main_loop(
         })?,
     }))?;
 
-    let mut w = state.get_write().expect("failed to get write");
+    let w = state.get_read().await;
     let model_message = match &w.agents {
         Some(agents) => format!("Model Config Loaded For: {:?}", agents.config.provider),
         None => "No model in your config file, AI will be unusable.".to_owned(),
     };
 
-    connection.sender.send(Message::Notification(Notification {
+    sender.send(Message::Notification(Notification {
         method: "$/progress".to_string(),
         params: serde_json::to_value(ProgressParams {
-            token: ProgressToken::String("Initializing".to_owned()),
+            token: token.clone(),
             value: lsp_types::ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
                 WorkDoneProgressReport {
                     message: Some(model_message),
@@ -75,10 +83,10 @@ async fn main_loop(
 
     drop(w);
 
-    connection.sender.send(Message::Notification(Notification {
+    sender.send(Message::Notification(Notification {
         method: "$/progress".to_string(),
         params: serde_json::to_value(ProgressParams {
-            token: ProgressToken::String("Initializing".to_owned()),
+            token,
             value: lsp_types::ProgressParamsValue::WorkDone(WorkDoneProgress::End(
                 WorkDoneProgressEnd {
                     // message: None,
@@ -87,37 +95,42 @@ async fn main_loop(
             )),
         })?,
     }))?;
+    Ok(())
+}
 
-    for msg in &connection.receiver {
-        match match msg {
-            Message::Notification(not) => {
-                handle::notifications::handle_notification(not, state.clone()).await
-            }
-            Message::Request(req) => handle::requests::handle_request(req, state.clone()).await,
-            _ => handle::handle_other(msg),
-        } {
-            Ok(mut buffer_op_channel_handler) => {
-                while let Some(status) = buffer_op_channel_handler.receiver.recv().await {
-                    match status? {
-                        BufferOpChannelStatus::Finished => break,
-                        BufferOpChannelStatus::Working(buffer_op) => {
-                            connection.sender = buffer_op.do_operation(connection.sender).await?;
-                        }
-                    }
-                }
-            }
-            Err(err) => {
-                warn!("error in handler: {}", err);
-                connection.sender.send(Message::Notification(Notification {
-                    method: "window/showMessage".to_string(),
-                    params: serde_json::to_value(ShowMessageParams {
-                        typ: MessageType::ERROR,
-                        message: format!("Handler encounted an error: {}", err),
-                    })?,
-                }))?;
+async fn main_loop(
+    connection: Connection,
+    params: serde_json::Value,
+    state: SharedState,
+    // config: Config,
+) -> Result<()> {
+    let _params: InitializeParams = serde_json::from_value(params).unwrap();
+
+    // the client's response to creating the progress token comes through the dispatcher
+    let initialization = tokio::spawn(report_initialization(
+        state.clone(),
+        connection.sender.clone(),
+    ));
+
+    let (incoming_sender, mut incoming) = tokio::sync::mpsc::unbounded_channel();
+    let receiver = connection.receiver.clone();
+    std::thread::spawn(move || {
+        for msg in receiver {
+            if incoming_sender.send(msg).is_err() {
+                break;
             }
         }
-        debug!("finished processing message, moving on");
+    });
+
+    let mut dispatcher = Dispatcher::new(state.clone(), connection.sender.clone());
+    while let Some(msg) = incoming.recv().await {
+        dispatcher.dispatch(msg);
+    }
+    // the client is gone, so nothing it was sent will be responded to
+    state.outgoing.abandon_all();
+    dispatcher.wait_idle().await;
+    if let Ok(Err(err)) = initialization.await {
+        warn!("failed to report initialization: {}", err);
     }
 
     Ok(())
@@ -134,6 +147,34 @@ pub async fn start_lsp() -> Result<()> {
     // also be implemented to use sockets or HTTP.
     let (connection, io_threads) = Connection::stdio();
 
+    let (initialize_id, initialization_params) = connection.initialize_start()?;
+    let client_capabilities =
+        serde_json::from_value::<InitializeParams>(initialization_params.clone())?.capabilities;
+    let position_encoding = PositionEncoding::negotiate(
+        client_capabilities
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_deref()),
+    );
+    info!("negotiated position encoding: {position_encoding:?}");
+    let pull_diagnostics = client_capabilities
+        .text_document
+        .as_ref()
+        .is_some_and(|text_document| text_document.diagnostic.is_some());
+    let inline_completion_client = client_capabilities
+        .text_document
+        .as_ref()
+        .is_some_and(|text_document| text_document.inline_completion.is_some());
+    let mut w = state.get_write().await;
+    w.position_encoding = position_encoding;
+    w.pull_diagnostics = pull_diagnostics;
+    w.inline_completion_client = inline_completion_client;
+    let inline_completion = w
+        .agents
+        .as_ref()
+        .is_some_and(|agents| agents.config.inline_completion.enabled);
+    drop(w);
+
     let text_document_sync = Some(TextDocumentSyncCapability::Options(
         TextDocumentSyncOptions {
             open_close: Some(true),
@@ -147,28 +188,53 @@ pub async fn start_lsp() -> Result<()> {
             ..Default::default()
         },
     ));
-    let server_capabilities = serde_json::to_value(ServerCapabilities {
+    let server_capabilities = ServerCapabilities {
+        position_encoding: Some(position_encoding.into()),
         text_document_sync,
         completion_provider: Some(lsp_types::CompletionOptions {
             resolve_provider: Some(false),
-            trigger_characters: Some(vec!["?".to_string(), "\"".to_string(), " ".to_string()]),
+            trigger_characters: Some(
+                ["?", "\"", " ", "@", "+", "$"]
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            ),
             work_done_progress_options: WorkDoneProgressOptions {
                 work_done_progress: None,
             },
             all_commit_characters: None,
             completion_item: None,
         }),
+        inline_completion_provider: inline_completion.then_some(lsp_types::OneOf::Left(true)),
         code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: handle::commands::COMMANDS
+                .iter()
+                .map(|command| command.to_string())
+                .collect(),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
         hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
         diagnostic_provider: Some(DiagnosticServerCapabilities::Options(
-            lsp_types::DiagnosticOptions::default(),
+            lsp_types::DiagnosticOptions {
+                identifier: Some("espx-ls".to_owned()),
+                inter_file_dependencies: false,
+                workspace_diagnostics: true,
+                ..Default::default()
+            },
         )),
         definition_provider: Some(lsp_types::OneOf::Left(true)),
         ..Default::default()
-    })
-    .unwrap();
+    };
 
-    let initialization_params = connection.initialize(server_capabilities)?;
+    connection.initialize_finish(
+        initialize_id,
+        serde_json::to_value(InitializeResult {
+            capabilities: server_capabilities,
+            server_info: None,
+            offset_encoding: None,
+        })?,
+    )?;
     main_loop(connection, initialization_params, state).await?;
     io_threads.join()?;
     Ok(())
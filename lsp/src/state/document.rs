@@ -0,0 +1,404 @@
+use crate::interact::{
+    encoding::PositionEncoding,
+    lexer::{CommentDiff, Lexer, TokenVec},
+    registry::InteractRegistry,
+};
+use lsp_types::{Position, TextDocumentContentChangeEvent};
+
+/// In memory copy of an open buffer, kept in sync with the client through didChange
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub text: String,
+    pub tokens: TokenVec,
+    ext: String,
+}
+
+impl Document {
+    pub fn new(
+        text: String,
+        ext: &str,
+        encoding: PositionEncoding,
+        registry: &InteractRegistry,
+    ) -> Self {
+        let tokens = Lexer::new(&text, ext)
+            .with_encoding(encoding)
+            .lex_input(registry);
+        Self {
+            text,
+            tokens,
+            ext: ext.to_owned(),
+        }
+    }
+
+    pub fn ext(&self) -> &str {
+        &self.ext
+    }
+
+    /// Applies changes in the order they were received, only re-lexing the regions they touch.
+    /// Returns every interact comment the changes added, removed or moved
+    pub fn apply_changes(
+        &mut self,
+        changes: &[TextDocumentContentChangeEvent],
+        registry: &InteractRegistry,
+    ) -> CommentDiff {
+        let mut diff = CommentDiff::default();
+        let encoding = self.tokens.encoding();
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = offset_at_position(&self.text, &range.start, encoding);
+                    let end = offset_at_position(&self.text, &range.end, encoding).max(start);
+                    self.text.replace_range(start..end, &change.text);
+                    diff.append(self.tokens.splice(
+                        &self.text,
+                        &self.ext,
+                        start..end,
+                        change.text.len(),
+                        registry,
+                    ));
+                }
+                None => {
+                    self.text = change.text.clone();
+                    let tokens = Lexer::new(&self.text, &self.ext)
+                        .with_encoding(encoding)
+                        .lex_input(registry);
+                    diff.append(CommentDiff::between(self.tokens.as_ref(), tokens.as_ref()));
+                    self.tokens = tokens;
+                }
+            }
+        }
+        diff
+    }
+}
+
+/// Returns the byte offset of the given position, with its character counted in `encoding`.
+/// Characters past the end of a line are clamped to the end of that line, lines past the end of
+/// the text are clamped to the end of the text
+pub fn offset_at_position(text: &str, pos: &Position, encoding: PositionEncoding) -> usize {
+    let mut line_start = 0;
+    for _ in 0..pos.line {
+        match text[line_start..].find('\n') {
+            Some(idx) => line_start += idx + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line_end = text[line_start..]
+        .find('\n')
+        .map(|idx| line_start + idx)
+        .unwrap_or(text.len());
+
+    let mut units = 0;
+    for (idx, c) in text[line_start..line_end].char_indices() {
+        if units >= pos.character as usize {
+            return line_start + idx;
+        }
+        units += encoding.char_len(c);
+    }
+    line_end
+}
+
+mod tests {
+    #[allow(unused)]
+    use super::*;
+    #[allow(unused)]
+    use lsp_types::Range;
+
+    #[allow(unused)]
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position {
+                    line: start.0,
+                    character: start.1,
+                },
+                end: Position {
+                    line: end.0,
+                    character: end.1,
+                },
+            }),
+            range_length: None,
+            text: text.to_owned(),
+        }
+    }
+
+    #[allow(unused)]
+    fn assert_matches_full_lex(doc: &Document, expected_text: &str) {
+        let registry = InteractRegistry::default();
+        assert_eq!(doc.text, expected_text);
+        let relexed = Document::new(
+            expected_text.to_owned(),
+            doc.ext(),
+            doc.tokens.encoding(),
+            &registry,
+        );
+        assert_eq!(doc.tokens.as_ref(), relexed.tokens.as_ref());
+        assert_eq!(
+            doc.tokens.comment_indices(),
+            relexed.tokens.comment_indices()
+        );
+        assert_eq!(doc.tokens, relexed.tokens);
+    }
+
+    #[allow(unused)]
+    fn position_at_offset(text: &str, offset: usize, encoding: PositionEncoding) -> Position {
+        let line_start = text[..offset].rfind('\n').map(|idx| idx + 1).unwrap_or(0);
+        Position {
+            line: text[..offset].matches('\n').count() as u32,
+            character: encoding.str_len(&text[line_start..offset]) as u32,
+        }
+    }
+
+    #[test]
+    fn offset_at_position_clamps() {
+        let text = "ab\ncd\n";
+        let pos = |line, character| Position { line, character };
+        let utf16 = PositionEncoding::Utf16;
+        assert_eq!(offset_at_position(text, &pos(0, 0), utf16), 0);
+        assert_eq!(offset_at_position(text, &pos(0, 10), utf16), 2);
+        assert_eq!(offset_at_position(text, &pos(1, 1), utf16), 4);
+        assert_eq!(offset_at_position(text, &pos(2, 0), utf16), 6);
+        assert_eq!(offset_at_position(text, &pos(9, 9), utf16), 6);
+    }
+
+    #[test]
+    fn offset_at_position_counts_encoding_units() {
+        // 'é' is 2 bytes and 1 utf-16 unit, '🦀' is 4 bytes and 2 utf-16 units
+        let text = "é🦀x\n";
+        let pos = |character| Position { line: 0, character };
+        let x_offset = text.find('x').unwrap();
+        assert_eq!(
+            offset_at_position(text, &pos(6), PositionEncoding::Utf8),
+            x_offset
+        );
+        assert_eq!(
+            offset_at_position(text, &pos(3), PositionEncoding::Utf16),
+            x_offset
+        );
+        assert_eq!(
+            offset_at_position(text, &pos(2), PositionEncoding::Utf32),
+            x_offset
+        );
+    }
+
+    #[test]
+    fn multibyte_text_before_comments_keeps_ranges_in_encoding() {
+        let registry = InteractRegistry::default();
+        let text = "let s = \"日本🦀\"; // @_ why\n".to_owned();
+        let comment_char = |encoding: PositionEncoding| {
+            let prefix = "let s = \"日本🦀\"; //";
+            encoding.str_len(prefix) as u32
+        };
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            let mut doc = Document::new(text.clone(), "rs", encoding, &registry);
+            let comment = doc.tokens.clone().into_iter().next().unwrap();
+            assert_eq!(comment.range.start.character, comment_char(encoding));
+            let (range, prompt) = comment.text_for_interact(encoding).unwrap();
+            assert_eq!(prompt, " why");
+            assert_eq!(range.start.character, comment_char(encoding) + 3);
+
+            // edit the text after the emoji using positions in this encoding
+            let after_crab = encoding.str_len("let s = \"日本🦀") as u32;
+            doc.apply_changes(&[change((0, after_crab), (0, after_crab), "!")], &registry);
+            assert_matches_full_lex(&doc, "let s = \"日本🦀!\"; // @_ why\n");
+        }
+    }
+
+    #[test]
+    fn typing_a_prompt_matches_full_lex() {
+        let registry = InteractRegistry::default();
+        let mut doc = Document::new(
+            "fn main() {\n    println!(\"hi\");\n}\n".to_owned(),
+            "rs",
+            PositionEncoding::default(),
+            &registry,
+        );
+
+        doc.apply_changes(&[change((0, 0), (0, 0), "\n")], &registry);
+        for (i, ch) in "// @_ what is this".chars().enumerate() {
+            doc.apply_changes(
+                &[change((0, i as u32), (0, i as u32), &ch.to_string())],
+                &registry,
+            );
+        }
+
+        assert_matches_full_lex(
+            &doc,
+            "// @_ what is this\nfn main() {\n    println!(\"hi\");\n}\n",
+        );
+        assert_eq!(doc.tokens.comment_indices().len(), 1);
+    }
+
+    #[test]
+    fn multiline_edits_match_full_lex() {
+        let registry = InteractRegistry::default();
+        let mut doc = Document::new(
+            "// +_\nstruct Pushed;\n\n/*\nsome\ncomment\n*/\nfn other() {}\n".to_owned(),
+            "rs",
+            PositionEncoding::default(),
+            &registry,
+        );
+
+        doc.apply_changes(
+            &[
+                change((3, 0), (6, 2), ""),
+                change((0, 3), (0, 5), "@^ explain"),
+                change((1, 7), (1, 13), "Renamed {\n    field: u8,\n}"),
+            ],
+            &registry,
+        );
+
+        assert_matches_full_lex(
+            &doc,
+            "// @^ explain\nstruct Renamed {\n    field: u8,\n};\n\n\nfn other() {}\n",
+        );
+
+        doc.apply_changes(
+            &[TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "// +_\nstruct Replaced;\n".to_owned(),
+            }],
+            &registry,
+        );
+
+        assert_matches_full_lex(&doc, "// +_\nstruct Replaced;\n");
+    }
+
+    #[test]
+    fn random_edits_match_full_lex() {
+        let registry = InteractRegistry::default();
+        let snippets = [
+            "// +_ pushed\n",
+            "// @_ what is this\n",
+            "/*",
+            "*/",
+            "/* +^\nmulti\n*/\n",
+            "\n",
+            "\n\n",
+            "/",
+            "x",
+            "é",
+            "🦀",
+            "fn a() {}\n",
+            " ",
+            "+_",
+            "@",
+        ];
+
+        // simple lcg so failures are reproducible
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |max: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % max.max(1)
+        };
+
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            let mut doc = Document::new(
+                "// +_\nstruct Pushed;\n\n/*\nsome\ncomment\n*/\nfn other() {}\n// @_ hi\n"
+                    .repeat(4),
+                "rs",
+                encoding,
+                &registry,
+            );
+
+            for _ in 0..500 {
+                let mut start = next(doc.text.len() + 1);
+                while !doc.text.is_char_boundary(start) {
+                    start -= 1;
+                }
+                let mut end = (start + next(24)).min(doc.text.len());
+                while !doc.text.is_char_boundary(end) {
+                    end += 1;
+                }
+                let inserted = if next(3) == 0 {
+                    ""
+                } else {
+                    snippets[next(snippets.len())]
+                };
+
+                let mut expected = doc.text.clone();
+                expected.replace_range(start..end, inserted);
+                let start = position_at_offset(&doc.text, start, encoding);
+                let end = position_at_offset(&doc.text, end, encoding);
+                doc.apply_changes(
+                    &[change(
+                        (start.line, start.character),
+                        (end.line, end.character),
+                        inserted,
+                    )],
+                    &registry,
+                );
+                assert_matches_full_lex(&doc, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn changes_report_comment_diff() {
+        let registry = InteractRegistry::default();
+        let mut doc = Document::new(
+            "// +_\nstruct Pushed;\n\nfn main() {}\n// @_ what\nfn other() {}\n".to_owned(),
+            "rs",
+            PositionEncoding::default(),
+            &registry,
+        );
+
+        let diff = doc.apply_changes(&[change((3, 0), (3, 0), "// +_\n")], &registry);
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.added[0].is_push());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.moved.len(), 1);
+        assert_eq!(diff.moved[0].0.start.line, 4);
+        assert_eq!(diff.moved[0].1.range.start.line, 5);
+
+        let diff = doc.apply_changes(&[change((1, 7), (1, 13), "Renamed")], &registry);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed.len(), 1);
+        assert!(diff.moved.is_empty());
+        assert_eq!(doc.tokens.pushes().next().unwrap().1, "struct Renamed;\n\n");
+
+        let diff = doc.apply_changes(&[change((1, 0), (1, 0), "    ")], &registry);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed.len(), 1);
+
+        let diff = doc.apply_changes(&[change((6, 10), (6, 10), "!")], &registry);
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.moved.is_empty());
+
+        // the push above the prompt now pushes the block that followed the prompt as well
+        let diff = doc.apply_changes(&[change((5, 0), (6, 0), "")], &registry);
+        assert_eq!(diff.removed.len(), 2);
+        assert!(diff.removed.iter().any(|comment| !comment.is_push()));
+        assert_eq!(diff.added.len(), 1);
+        assert!(diff.added[0].is_push());
+        assert_matches_full_lex(
+            &doc,
+            "// +_\n    struct Renamed;\n\n// +_\nfn main() {}\nfn other()! {}\n",
+        );
+    }
+
+    #[test]
+    fn pushed_lines_cover_only_the_code() {
+        let registry = InteractRegistry::default();
+        let text = "// +_\nstruct A;\n\n// +^\n\nfn main() {\n}\n\n";
+        let doc = Document::new(text.to_owned(), "rs", PositionEncoding::Utf16, &registry);
+        let lines: Vec<(u32, u32)> = doc.tokens.pushed_lines().map(|(_, lines)| lines).collect();
+        assert_eq!(lines, vec![(1, 1), (5, 6)]);
+
+        // lines that are only whitespace, and windows line endings, are blank too
+        let text = "// +_\n  \nstruct A;\r\n// +_\r\n\r\n    fn main() {\r\n    }\r\n";
+        let doc = Document::new(text.to_owned(), "rs", PositionEncoding::Utf16, &registry);
+        let lines: Vec<(u32, u32)> = doc.tokens.pushed_lines().map(|(_, lines)| lines).collect();
+        assert_eq!(lines, vec![(2, 2), (5, 6)]);
+    }
+}
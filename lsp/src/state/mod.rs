@@ -0,0 +1,324 @@
+use crate::{
+    agents::Agents,
+    config::{prompt::PromptConfig, Config},
+    database::{
+        error::DatabaseError,
+        models::{
+            agent_memories::{DBAgentMemory, DBAgentMemoryParams},
+            block::{block_params_from, DBBlock, DBBlockParams},
+            DatabaseStruct, QueryBuilder,
+        },
+        Database,
+    },
+    error::{StateError, StateResult},
+    handle::{
+        cancel::{Debouncer, InFlightRequests},
+        outgoing::OutgoingRequests,
+    },
+    interact::{
+        encoding::PositionEncoding,
+        id::{InteractInteger, ScopeId, DOCUMENT_ID, GLOBAL_ID, PROMPT_ID, PUSH_ID},
+        lexer::CommentDiff,
+        registry::InteractRegistry,
+        InteractError,
+    },
+};
+pub mod document;
+use anyhow::anyhow;
+use document::Document;
+use espionox::{
+    agents::{memory::OtherRoleTo, Agent},
+    prelude::{Message, MessageRole},
+};
+use lsp_types::{TextDocumentContentChangeEvent, Uri};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::warn;
+
+pub struct SharedState {
+    state: Arc<RwLock<LspState>>,
+    pub in_flight: InFlightRequests,
+    pub outgoing: OutgoingRequests,
+    pub debouncer: Debouncer,
+}
+
+#[derive(Debug)]
+pub struct LspState {
+    pub documents: HashMap<Uri, Document>,
+    pub database: Option<Database>,
+    pub registry: InteractRegistry,
+    pub agents: Option<Agents>,
+    /// Negotiated with the client on initialize, every document is lexed with it
+    pub position_encoding: PositionEncoding,
+    /// Negotiated with the client on initialize, clients which pull diagnostics are not also
+    /// pushed them
+    pub pull_diagnostics: bool,
+    /// Negotiated with the client on initialize, clients which support inline completion get
+    /// model completions through it rather than through regular completion
+    pub inline_completion_client: bool,
+    pub prompt_config: PromptConfig,
+}
+
+impl LspState {
+    #[tracing::instrument(name = "initializing state")]
+    async fn new(mut config: Config) -> anyhow::Result<Self> {
+        let database = Database::init(&mut config).await.ok();
+        let mut agents = config.model.take().and_then(|cfg| Some(Agents::from(cfg)));
+        let mut registry = InteractRegistry::default();
+        if let Some(ref scopes_config) = &config.scopes {
+            for (token, scope_settings) in scopes_config.clone().into_iter() {
+                registry.register_scope(&token)?;
+                if let Some(agents) = agents.as_mut() {
+                    agents.create_custom_agent(token, scope_settings.sys_prompt);
+                }
+            }
+        }
+
+        Ok(Self {
+            documents: HashMap::new(),
+            registry,
+            database,
+            agents,
+            position_encoding: PositionEncoding::default(),
+            pull_diagnostics: false,
+            inline_completion_client: false,
+            prompt_config: config.prompt.take().unwrap_or_default(),
+        })
+    }
+
+    pub async fn save_agent_memories_to_database(&self) -> StateResult<()> {
+        let mut all_agent_params = vec![];
+        if let Some(agents) = &self.agents {
+            let global_cache = &agents.global_agent_ref().cache;
+            let global_token = self
+                .registry
+                .get_scope_token(GLOBAL_ID)
+                .expect("no global agent in registry?");
+
+            all_agent_params.push(DBAgentMemoryParams::new(global_token, Some(&global_cache)));
+
+            for (custom_token, custom_agent) in agents.custom_agents_iter() {
+                let cache = &custom_agent.cache;
+                all_agent_params.push(DBAgentMemoryParams::new(
+                    custom_token.as_str(),
+                    Some(&cache),
+                ));
+            }
+
+            for (doc_uri, doc_agent) in agents.doc_agents_iter() {
+                let cache = &doc_agent.cache;
+                all_agent_params.push(DBAgentMemoryParams::new(doc_uri.to_owned(), Some(&cache)));
+            }
+        }
+
+        let db = self
+            .database
+            .as_ref()
+            .ok_or(StateError::DatabaseNotPresent)?;
+
+        let mut q = QueryBuilder::begin();
+
+        for param in all_agent_params {
+            q.push(&DBAgentMemory::upsert(&param)?)
+        }
+
+        db.client
+            .query(q.end())
+            .await
+            .map_err(|err| StateError::from(DatabaseError::from(err)))?;
+
+        Ok(())
+    }
+
+    pub async fn save_docs_to_database(&self) -> StateResult<()> {
+        let db = self
+            .database
+            .as_ref()
+            .ok_or(StateError::DatabaseNotPresent)?;
+        DBBlock::save_all(db, self.docs_block_params())
+            .await
+            .map_err(StateError::from)
+    }
+
+    /// Every block of every document, as they are saved to the database
+    pub fn docs_block_params(&self) -> Vec<DBBlockParams> {
+        let mut all_block_params = vec![];
+        for (uri, doc) in &self.documents {
+            let mut params = block_params_from(&doc.tokens, uri.clone());
+            all_block_params.append(&mut params);
+        }
+        all_block_params
+    }
+
+    pub fn agent_mut_from_interact_integer(
+        &mut self,
+        integer: InteractInteger,
+        current_document_uri: &Uri,
+    ) -> StateResult<&mut Agent> {
+        let agents = self
+            .agents
+            .as_mut()
+            .ok_or(anyhow!("agents not present in state"))?;
+        match integer.scope {
+            DOCUMENT_ID => Ok(agents.doc_agent_mut(current_document_uri)?),
+            GLOBAL_ID => Ok(agents.global_agent_mut()),
+            custom_scope => {
+                let token = self
+                    .registry
+                    .get_scope_token(custom_scope)
+                    .ok_or(InteractError::InvalidInteractId(integer))?;
+                Ok(agents.custom_agent_mut(token)?)
+            }
+        }
+    }
+
+    /// The document scope's agent is the one of the current document
+    pub fn agent_ref_from_scope(
+        &self,
+        scope: ScopeId,
+        current_document_uri: &Uri,
+    ) -> StateResult<&Agent> {
+        let agents = self.agents.as_ref().ok_or(StateError::AgentsNotPresent)?;
+        match scope {
+            DOCUMENT_ID => Ok(agents.doc_agent_ref(current_document_uri)?),
+            GLOBAL_ID => Ok(agents.global_agent_ref()),
+            custom_scope => {
+                let token = self
+                    .registry
+                    .get_scope_token(custom_scope)
+                    .ok_or(InteractError::InvalidScopeId(custom_scope))?;
+                Ok(agents.custom_agent_ref(token)?)
+            }
+        }
+    }
+
+    /// Removes the conversation had in a scope, keeping its system prompt and everything pushed
+    /// into it. Returns how many messages were removed
+    pub fn clear_scope(
+        &mut self,
+        scope: ScopeId,
+        current_document_uri: &Uri,
+    ) -> StateResult<usize> {
+        let integer = InteractInteger::new(PROMPT_ID, scope);
+        let agent = self.agent_mut_from_interact_integer(integer, current_document_uri)?;
+        let before = agent.cache.len();
+        agent.cache.mut_filter_by(&MessageRole::User, false);
+        agent.cache.mut_filter_by(&MessageRole::Assistant, false);
+        Ok(before - agent.cache.len())
+    }
+
+    /// Replaces the stored document, refreshing everything it pushes into agents
+    pub fn update_doc_and_agents_from_text(&mut self, uri: Uri, text: String) -> StateResult<()> {
+        let uri_str = uri.as_str().to_string();
+        let ext = &uri_str
+            .rsplit_once('.')
+            .expect("uri does not have extension")
+            .1;
+        let new_doc = Document::new(text, ext, self.position_encoding, &self.registry);
+        let old_doc = self.documents.remove(&uri);
+        let diff = CommentDiff::replaced(old_doc.as_ref().map(|doc| &doc.tokens), &new_doc.tokens);
+        self.update_doc_and_agents(uri, diff, new_doc)
+    }
+
+    /// Applies incremental changes from a didChange notification to the stored document
+    pub fn update_doc_and_agents_from_changes(
+        &mut self,
+        uri: Uri,
+        changes: &[TextDocumentContentChangeEvent],
+    ) -> StateResult<()> {
+        let mut doc = self
+            .documents
+            .remove(&uri)
+            .ok_or(StateError::DocumentNotPresent(uri.clone()))?;
+        let diff = doc.apply_changes(changes, &self.registry);
+        self.update_doc_and_agents(uri, diff, doc)
+    }
+
+    /// Only the scopes of pushes which were added or removed have their messages from this
+    /// document rebuilt
+    fn update_doc_and_agents(
+        &mut self,
+        uri: Uri,
+        diff: CommentDiff,
+        new_doc: Document,
+    ) -> StateResult<()> {
+        if let Some(agents) = self.agents.as_mut() {
+            agents.update_or_create_doc_agent(&uri, &new_doc.text);
+        }
+
+        let mut changed_scopes: Vec<ScopeId> = diff
+            .added
+            .iter()
+            .chain(diff.removed.iter())
+            .filter(|comment| comment.is_push())
+            .filter_map(|comment| comment.try_get_interact_integer().ok())
+            .map(|integer| integer.scope)
+            .collect();
+        changed_scopes.sort();
+        changed_scopes.dedup();
+
+        let role = MessageRole::Other {
+            alias: uri.to_string(),
+            coerce_to: OtherRoleTo::User,
+        };
+
+        for scope in changed_scopes {
+            let blocks: Vec<String> = new_doc
+                .tokens
+                .pushes()
+                .filter_map(|(comment, block)| {
+                    let integer = comment.try_get_interact_integer().ok()?;
+                    (integer.scope == scope).then(|| block.to_owned())
+                })
+                .collect();
+
+            let integer = InteractInteger::new(PUSH_ID, scope);
+            if let Ok(agent) = self.agent_mut_from_interact_integer(integer, &uri) {
+                warn!("updating {} pushes for scope: {scope:?}", blocks.len());
+                agent.cache.mut_filter_by(&role, false);
+                for block in blocks {
+                    agent.cache.push(Message {
+                        role: role.clone(),
+                        content: block,
+                    });
+                }
+            }
+        }
+
+        self.documents.insert(uri, new_doc);
+
+        Ok(())
+    }
+}
+
+impl Clone for SharedState {
+    fn clone(&self) -> Self {
+        Self {
+            state: Arc::clone(&self.state),
+            in_flight: self.in_flight.clone(),
+            outgoing: self.outgoing.clone(),
+            debouncer: self.debouncer.clone(),
+        }
+    }
+}
+
+impl SharedState {
+    pub async fn init(config: Config) -> anyhow::Result<Self> {
+        Ok(Self {
+            state: Arc::new(RwLock::new(LspState::new(config).await?)),
+            in_flight: InFlightRequests::default(),
+            outgoing: OutgoingRequests::default(),
+            debouncer: Debouncer::default(),
+        })
+    }
+
+    /// Waits for every writer to be done
+    pub async fn get_read(&self) -> RwLockReadGuard<'_, LspState> {
+        self.state.read().await
+    }
+
+    /// Waits for every reader and writer to be done
+    pub async fn get_write(&self) -> RwLockWriteGuard<'_, LspState> {
+        self.state.write().await
+    }
+}
@@ -1,6 +1,7 @@
 use espx_lsp_server::config::{
     database::DatabaseConfig,
-    espx::{ModelConfig, ModelProvider},
+    espx::{InlineCompletionConfig, ModelConfig, ModelName, ModelProvider},
+    prompt::{PromptConfig, ResponseMode},
     scopes::ScopeSettings,
     Config, ConfigFromFile,
 };
@@ -34,6 +35,11 @@ pub fn test_config(database: bool) -> anyhow::Result<Config> {
              [scopes.c]
              [scopes.b]
              sys_prompt = "prompt"
+             [scopes.review]
+             sys_prompt = "review prompt"
+
+            [prompt]
+            response_mode = "popup"
 
         "#
     );
@@ -53,18 +59,25 @@ fn pwd() -> PathBuf {
 #[test]
 fn config_builds_correctly() {
     let mut scopes = HashMap::new();
-    scopes.insert('c', ScopeSettings::default());
+    scopes.insert("c".to_owned(), ScopeSettings::default());
     scopes.insert(
-        'b',
+        "b".to_owned(),
         ScopeSettings {
             sys_prompt: "prompt".to_string(),
         },
     );
+    scopes.insert(
+        "review".to_owned(),
+        ScopeSettings {
+            sys_prompt: "review prompt".to_string(),
+        },
+    );
     let expected = Config {
         pwd: pwd(),
         model: Some(ModelConfig {
             provider: ModelProvider::Anthropic,
             api_key: "invalid".to_owned(),
+            inline_completion: InlineCompletionConfig::default(),
         }),
         scopes: Some(scopes),
         database: Some(DatabaseConfig {
@@ -73,6 +86,9 @@ fn config_builds_correctly() {
             user: "root".to_owned(),
             pass: "root".to_owned(),
         }),
+        prompt: Some(PromptConfig {
+            response_mode: ResponseMode::Popup,
+        }),
     };
 
     let mut cfg = test_config(true).unwrap();
@@ -83,3 +99,27 @@ fn config_builds_correctly() {
 
     assert_eq!(expected, cfg);
 }
+
+#[test]
+fn inline_completion_is_configured_under_model() {
+    let input = r#"
+        [model]
+        provider = "Anthropic"
+        api_key = "invalid"
+
+          [model.inline_completion]
+          enabled = true
+          model = "haiku"
+    "#;
+    let cnfg: ConfigFromFile = toml::from_str(input).unwrap();
+    let cfg = Config::from((cnfg, pwd()));
+
+    assert_eq!(
+        cfg.model.unwrap().inline_completion,
+        InlineCompletionConfig {
+            enabled: true,
+            model: Some(ModelName::Haiku),
+            debounce_ms: 300,
+        }
+    );
+}
@@ -6,18 +6,20 @@ use espionox::prelude::{Message, MessageStack};
 use espx_lsp_server::{
     database::models::{
         agent_memories::{AgentID, DBAgentMemory, DBAgentMemoryParams},
-        block::{block_params_from, DBBlock, DBBlockParams},
+        block::{block_params_from, BlockLines, DBBlock, DBBlockParams},
         DatabaseStruct, FieldQuery, QueryBuilder,
     },
     embeddings,
     interact::lexer::Lexer,
 };
-use std::sync::LazyLock;
+use lsp_types::Uri;
+use std::{str::FromStr, sync::LazyLock};
+use surrealdb::sql::Thing;
 
 #[tokio::test]
 async fn health_test() {
     let state = test_state(true).await;
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
     if let Err(err) = r.database.as_ref().unwrap().client.health().await {
         panic!("unhealthy database: {err:#?}")
     }
@@ -26,7 +28,7 @@ async fn health_test() {
 #[tokio::test]
 async fn get_relavent_blocks() {
     let state = test_state(true).await;
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
     let db = r.database.as_ref().unwrap();
     let (uri, _) = test_doc_1();
 
@@ -58,7 +60,7 @@ async fn get_relavent_blocks() {
         .next()
         .unwrap();
 
-    let relavent = DBBlock::get_relavent(db, embedding, 0.5).await.unwrap();
+    let relavent = DBBlock::get_relavent(db, embedding, 0.5, 10).await.unwrap();
 
     let r_contents = relavent
         .iter()
@@ -80,11 +82,81 @@ async fn get_relavent_blocks() {
     }
 }
 
+#[tokio::test]
+async fn get_relavent_orders_and_limits_blocks() {
+    let state = test_state(true).await;
+    let r = state.get_read().await;
+    let db = r.database.as_ref().unwrap();
+    let uri = Uri::from_str("rag_ranking_test.rs").unwrap();
+
+    // same dimension as the real embeddings, but only ever pointing along the first two
+    let embedding = |x: f32, y: f32| {
+        let mut emb = vec![0.0; 384];
+        emb[0] = x;
+        emb[1] = y;
+        emb
+    };
+    let blocks = [
+        ("most similar", embedding(1.0, 0.0)),
+        ("less similar", embedding(0.8, 0.6)),
+        ("similar enough", embedding(0.6, 0.8)),
+        ("not similar", embedding(0.0, 1.0)),
+    ];
+
+    let mut q = QueryBuilder::begin();
+    for (i, (content, _)) in blocks.iter().enumerate() {
+        let mut params = DBBlockParams::new(uri.clone(), i, Some(content.to_string()));
+        params.lines = Some(BlockLines {
+            start: i as u32 * 10,
+            end: i as u32 * 10 + 9,
+        });
+        q.push(&DBBlock::upsert(&params).unwrap())
+    }
+    db.client.query(q.end()).await.unwrap();
+    for (content, emb) in blocks.iter() {
+        db.client
+            .query("UPDATE blocks SET content_embedding = $emb WHERE content = $content;")
+            .bind(("emb", emb.clone()))
+            .bind(("content", content.to_string()))
+            .await
+            .unwrap();
+    }
+
+    let contents = |blocks: Vec<DBBlock>| -> Vec<String> {
+        blocks
+            .into_iter()
+            .filter(|b| b.uri == uri)
+            .map(|b| b.content)
+            .collect()
+    };
+
+    let relavent = DBBlock::get_relavent(db, embedding(1.0, 0.0), 0.5, 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        relavent
+            .iter()
+            .find(|b| b.content == "less similar")
+            .unwrap()
+            .lines,
+        Some(BlockLines { start: 10, end: 19 })
+    );
+    assert_eq!(
+        contents(relavent),
+        vec!["most similar", "less similar", "similar enough"]
+    );
+
+    let relavent = DBBlock::get_relavent(db, embedding(1.0, 0.0), 0.5, 2)
+        .await
+        .unwrap();
+    assert_eq!(contents(relavent), vec!["most similar", "less similar"]);
+}
+
 #[tokio::test]
 async fn tokens_crud_test() {
     LazyLock::force(&TEST_TRACING);
     let state = test_state(true).await;
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
     let all_test_docs = vec![
         test_doc_1(),
         test_doc_2(),
@@ -173,7 +245,7 @@ async fn tokens_crud_test() {
 async fn memories_crud_test() {
     LazyLock::force(&TEST_TRACING);
     let state = test_state(true).await;
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
 
     let db = r.database.as_ref().unwrap();
     let _: Vec<DBAgentMemory> = db.client.delete(DBAgentMemory::db_id()).await.unwrap();
@@ -194,12 +266,12 @@ async fn memories_crud_test() {
     ]
     .into();
 
-    let agent_char_1 = 'c';
+    let agent_scope_1 = "review";
     let (agent_uri_2, _) = test_doc_1();
 
     let mut all_params = vec![];
 
-    all_params.push(DBAgentMemoryParams::new(&agent_char_1, Some(&test_mems_1)));
+    all_params.push(DBAgentMemoryParams::new(agent_scope_1, Some(&test_mems_1)));
     all_params.push(DBAgentMemoryParams::new(
         agent_uri_2.clone(),
         Some(&test_mems_2),
@@ -228,7 +300,38 @@ async fn memories_crud_test() {
         .unwrap();
 
     assert_eq!(agent_2.messages, test_mems_2);
+    assert_eq!(
+        AgentID::try_from(agent_2.id).unwrap(),
+        AgentID::from(test_doc_1().0)
+    );
+
+    let agent_1: DBAgentMemory = db
+        .client
+        .select((
+            DBAgentMemory::db_id(),
+            AgentID::from(agent_scope_1).to_string(),
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        AgentID::try_from(agent_1.id).unwrap(),
+        AgentID::from(agent_scope_1)
+    );
     // let mut q = QueryBuilder::begin();
 
     // q.push(&DBBlock::delete(&FieldQuery::new("uri", ).unwrap()).unwrap());
 }
+
+#[test]
+fn legacy_memory_ids_are_read_as_scopes() {
+    // scopes used to be a single char, saved without a prefix
+    let legacy = Thing::from((DBAgentMemory::db_id(), "l"));
+    assert_eq!(AgentID::try_from(legacy).unwrap(), AgentID::from("l"));
+    let (uri, _) = test_doc_1();
+    let encoded = Thing::from((
+        DBAgentMemory::db_id(),
+        AgentID::from(uri.clone()).to_string().as_str(),
+    ));
+    assert_eq!(AgentID::try_from(encoded).unwrap(), AgentID::from(uri));
+}
@@ -0,0 +1,373 @@
+use crate::{
+    helpers::{handler_tests_state, TEST_TRACING},
+    notifications::into_lsp_notification,
+    requests::{create_hover_params, execute_command_request, into_lsp_request},
+    test_docs::test_doc_1,
+};
+use crossbeam_channel::Receiver;
+use espx_lsp_server::handle::{
+    buffer_operations::{BufferOpChannelHandler, BufferOpChannelStatus, BufferOperation},
+    commands::ASK_ABOUT_SELECTION,
+    dispatch::{dispatch_keys, holds_back_later_messages, DispatchKey, Dispatcher},
+};
+use lsp_server::{ErrorCode, Message, Request, RequestId, Response};
+use lsp_types::{
+    ApplyWorkspaceEditResponse, CancelParams, DidChangeTextDocumentParams,
+    DidOpenTextDocumentParams, MessageActionItem, NumberOrString, Position, Range,
+    TextDocumentContentChangeEvent, TextDocumentItem, TextEdit, Uri,
+    VersionedTextDocumentIdentifier,
+};
+use std::{str::FromStr, sync::LazyLock, time::Duration};
+
+pub fn insert_at_start(uri: lsp_types::Uri, text: &str) -> Message {
+    let params = DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier { uri, version: 0 },
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(0, 0), Position::new(0, 0))),
+            range_length: None,
+            text: text.to_owned(),
+        }],
+    };
+    Message::Notification(into_lsp_notification(params, "textDocument/didChange"))
+}
+
+#[test]
+fn messages_are_keyed_by_document_and_scope() {
+    let (uri, _) = test_doc_1();
+    let position = serde_json::json!({
+        "textDocument": { "uri": uri.as_str() },
+        "position": { "line": 3, "character": 3 },
+    });
+    let request = |method: &str| {
+        Message::Request(Request {
+            id: RequestId::from(1),
+            method: method.to_owned(),
+            params: position.clone(),
+        })
+    };
+
+    assert_eq!(
+        dispatch_keys(&request("textDocument/definition")),
+        vec![DispatchKey::Document(uri.clone()), DispatchKey::Scopes]
+    );
+    assert_eq!(
+        dispatch_keys(&request("textDocument/hover")),
+        vec![DispatchKey::Document(uri.clone()), DispatchKey::Scopes]
+    );
+    assert_eq!(
+        dispatch_keys(&insert_at_start(uri.clone(), "a")),
+        vec![DispatchKey::Document(uri.clone()), DispatchKey::Scopes]
+    );
+    assert_eq!(
+        dispatch_keys(&request("textDocument/codeAction")),
+        vec![DispatchKey::Document(uri.clone())]
+    );
+    let shutdown = Message::Request(Request {
+        id: RequestId::from(2),
+        method: "shutdown".to_owned(),
+        params: serde_json::Value::Null,
+    });
+    assert!(dispatch_keys(&shutdown).is_empty());
+
+    // completions wait on the changes before them, but nothing waits on completions
+    let completion = request("textDocument/inlineCompletion");
+    assert_eq!(
+        dispatch_keys(&completion),
+        vec![DispatchKey::Document(uri.clone())]
+    );
+    assert!(!holds_back_later_messages(&completion));
+    assert!(!holds_back_later_messages(&request(
+        "textDocument/completion"
+    )));
+    assert!(holds_back_later_messages(&request("textDocument/hover")));
+    assert!(holds_back_later_messages(&insert_at_start(uri, "a")));
+}
+
+#[tokio::test]
+async fn changes_to_a_document_stay_ordered() {
+    LazyLock::force(&TEST_TRACING);
+    let (uri, text) = test_doc_1();
+    let state = handler_tests_state().await;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender);
+
+    let letters = ["a", "b", "c", "d", "e", "f", "g", "h"];
+    for letter in letters {
+        dispatcher.dispatch(insert_at_start(uri.clone(), letter));
+    }
+    dispatcher.wait_idle().await;
+
+    let r = state.get_read().await;
+    let expected: String = letters.iter().rev().copied().collect::<String>() + &text;
+    assert_eq!(r.documents.get(&uri).unwrap().text, expected);
+
+    let published = receiver
+        .try_iter()
+        .filter(|msg| {
+            matches!(msg, Message::Notification(noti) if noti.method == "textDocument/publishDiagnostics")
+        })
+        .count();
+    assert_eq!(published, letters.len());
+}
+
+fn open_document(uri: &Uri, text: &str) -> Message {
+    let params = DidOpenTextDocumentParams {
+        text_document: TextDocumentItem::new(uri.clone(), "rust".to_owned(), 0, text.to_owned()),
+    };
+    Message::Notification(into_lsp_notification(params, "textDocument/didOpen"))
+}
+
+/// The summary the hover with the id was responded to with, if it has been
+fn hover_summary(receiver: &Receiver<Message>, id: i32) -> Option<String> {
+    receiver.try_iter().find_map(|msg| match msg {
+        Message::Response(response) if response.id == RequestId::from(id) => response
+            .result?
+            .pointer("/contents/value")?
+            .as_str()
+            .map(str::to_owned),
+        _ => None,
+    })
+}
+
+#[tokio::test]
+async fn scopes_stay_ordered_across_documents() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender);
+    let pushing = Uri::from_str("file:///tmp/pushes_into_b.rs").unwrap();
+    let hovered = Uri::from_str("file:///tmp/hovers_b.rs").unwrap();
+    dispatcher.dispatch(open_document(&hovered, "// +b\n"));
+    dispatcher.wait_idle().await;
+
+    // nothing is handled until the lock is released, by then the hover of one document has to be
+    // between the push and its removal from the other
+    let lock = state.get_write().await;
+    dispatcher.dispatch(open_document(&pushing, "// +b\nfn pushed() {}\n"));
+    let hover = create_hover_params(Position::new(0, 4), hovered);
+    dispatcher.dispatch(Message::Request(into_lsp_request(
+        hover,
+        1,
+        "textDocument/hover",
+    )));
+    dispatcher.dispatch(open_document(&pushing, "fn pushed() {}\n"));
+    drop(lock);
+    dispatcher.wait_idle().await;
+
+    let summary = hover_summary(&receiver, 1).expect("the hover should be responded to");
+    assert!(
+        summary.contains("- `file:///tmp/pushes_into_b.rs` (lines 2-2)"),
+        "{summary}"
+    );
+    let r = state.get_read().await;
+    let b = r.agents.as_ref().unwrap().custom_agent_ref("b").unwrap();
+    assert!(!b.cache.to_string().contains("pushed"));
+}
+
+#[tokio::test]
+async fn messages_are_handled_while_a_command_waits_on_the_client() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender);
+    let asked = Uri::from_str("file:///tmp/asked_about.rs").unwrap();
+    let hovered = Uri::from_str("file:///tmp/hovers_b.rs").unwrap();
+    dispatcher.dispatch(open_document(&hovered, "// +b\n"));
+    dispatcher.dispatch(open_document(&asked, "fn asked() {}\n"));
+    dispatcher.wait_idle().await;
+
+    // the command waits on the client to pick a question, which it is never sent
+    let ask = serde_json::json!({
+        "uri": asked.as_str(),
+        "range": Range::new(Position::new(0, 0), Position::new(0, 13)),
+    });
+    dispatcher.dispatch(Message::Request(execute_command_request(
+        1,
+        ASK_ABOUT_SELECTION,
+        ask,
+    )));
+    dispatcher.dispatch(insert_at_start(asked.clone(), "// edited\n"));
+    let hover = create_hover_params(Position::new(0, 4), hovered);
+    dispatcher.dispatch(Message::Request(into_lsp_request(
+        hover,
+        2,
+        "textDocument/hover",
+    )));
+
+    let summary = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(summary) = hover_summary(&receiver, 2) {
+                break summary;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the hover should not wait on the command");
+    assert!(summary.contains("Custom scope `b`"), "{summary}");
+    let r = state.get_read().await;
+    assert_eq!(
+        r.documents.get(&asked).unwrap().text,
+        "// edited\nfn asked() {}\n"
+    );
+    assert!(!state.outgoing.is_empty());
+}
+
+/// Waits for the next request sent to the client
+async fn client_request(receiver: &Receiver<Message>) -> Request {
+    loop {
+        match receiver.try_recv() {
+            Ok(Message::Request(request)) => return request,
+            Ok(_) => {}
+            Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn selections_are_read_once_the_user_has_picked() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender);
+    let asked = Uri::from_str("file:///tmp/asked_about.rs").unwrap();
+    dispatcher.dispatch(open_document(&asked, "fn asked() {}\n"));
+    dispatcher.wait_idle().await;
+
+    let selection = Range::new(Position::new(0, 0), Position::new(0, 13));
+    let ask = serde_json::json!({ "uri": asked.as_str(), "range": selection });
+    dispatcher.dispatch(Message::Request(execute_command_request(
+        1,
+        ASK_ABOUT_SELECTION,
+        ask,
+    )));
+    // the selected code is deleted while the user picks a question
+    let question = client_request(&receiver).await;
+    let params = DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier {
+            uri: asked.clone(),
+            version: 1,
+        },
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(selection),
+            range_length: None,
+            text: String::new(),
+        }],
+    };
+    dispatcher.dispatch(Message::Notification(into_lsp_notification(
+        params,
+        "textDocument/didChange",
+    )));
+    let pick = |request: Request, title: &str| {
+        let picked = MessageActionItem {
+            title: title.to_owned(),
+            properties: Default::default(),
+        };
+        Message::Response(Response::new_ok(request.id, picked))
+    };
+    dispatcher.dispatch(pick(question, "why?"));
+    let scope = client_request(&receiver).await;
+    dispatcher.dispatch(pick(scope, "_"));
+    dispatcher.wait_idle().await;
+
+    let response = receiver
+        .try_iter()
+        .find_map(|msg| match msg {
+            Message::Response(response) if response.id == RequestId::from(1) => Some(response),
+            _ => None,
+        })
+        .unwrap();
+    assert!(response.error.unwrap().message.contains("nothing selected"));
+}
+
+#[tokio::test]
+async fn requests_can_be_cancelled_while_waiting_their_turn() {
+    LazyLock::force(&TEST_TRACING);
+    let (uri, _) = test_doc_1();
+    let state = handler_tests_state().await;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender);
+
+    // the change can't be handled until the lock is released, and the hover waits on the change
+    let lock = state.get_write().await;
+    dispatcher.dispatch(insert_at_start(uri.clone(), "a"));
+    let hover = create_hover_params(Position::new(0, 4), uri);
+    dispatcher.dispatch(Message::Request(into_lsp_request(
+        hover,
+        7,
+        "textDocument/hover",
+    )));
+    let cancel = CancelParams {
+        id: NumberOrString::Number(7),
+    };
+    dispatcher.dispatch(Message::Notification(into_lsp_notification(
+        cancel,
+        "$/cancelRequest",
+    )));
+    assert!(state.in_flight.token(&RequestId::from(7)).is_cancelled());
+    drop(lock);
+    dispatcher.wait_idle().await;
+
+    let responses: Vec<Response> = receiver
+        .try_iter()
+        .filter_map(|msg| match msg {
+            Message::Response(response) => Some(response),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].id, RequestId::from(7));
+    assert_eq!(
+        responses[0].error.as_ref().unwrap().code,
+        ErrorCode::RequestCanceled as i32
+    );
+    assert!(state.in_flight.is_empty());
+}
+
+#[tokio::test]
+async fn client_responses_reach_waiting_handlers() {
+    LazyLock::force(&TEST_TRACING);
+    let (uri, _) = test_doc_1();
+    let state = handler_tests_state().await;
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender.clone());
+
+    let mut channel = BufferOpChannelHandler::new();
+    let edit = BufferOperation::text_edit_params(
+        uri,
+        vec![TextEdit::new(
+            Range::new(Position::new(0, 0), Position::new(0, 0)),
+            "a".to_owned(),
+        )],
+    );
+    let handler_state = state.clone();
+    let mut handler_sender = channel.sender.clone();
+    let handler = tokio::spawn(async move {
+        handler_sender
+            .apply_edit(&handler_state.outgoing, edit)
+            .await
+    });
+
+    let op = match channel.receiver.recv().await.unwrap().unwrap() {
+        BufferOpChannelStatus::Working(op) => op,
+        other => panic!("expected the edit to be sent, got {other:?}"),
+    };
+    op.do_operation(sender, &state.outgoing).await.unwrap();
+    let request = match receiver.try_recv().unwrap() {
+        Message::Request(request) => request,
+        other => panic!("edits should be requests, got {other:?}"),
+    };
+    assert_eq!(request.method, "workspace/applyEdit");
+
+    let rejected = ApplyWorkspaceEditResponse {
+        applied: false,
+        failure_reason: Some("document changed".to_owned()),
+        failed_change: None,
+    };
+    dispatcher.dispatch(Message::Response(Response::new_ok(request.id, rejected)));
+    dispatcher.wait_idle().await;
+
+    let response = handler.await.unwrap().unwrap();
+    assert!(!response.applied);
+    assert!(state.outgoing.is_empty());
+}
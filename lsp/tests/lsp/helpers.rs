@@ -2,7 +2,8 @@ use crate::test_docs::test_doc_1;
 
 use super::config::test_config;
 use espx_lsp_server::{
-    handle::buffer_operations::BufferOpChannelHandler, interact::lexer::Lexer, state::SharedState,
+    handle::buffer_operations::BufferOpChannelHandler,
+    state::{document::Document, SharedState},
 };
 use std::sync::LazyLock;
 use tracing::{info, subscriber::set_global_default, Subscriber};
@@ -30,31 +31,18 @@ pub fn test_buff_op_channel() -> BufferOpChannelHandler {
 }
 
 pub async fn handler_tests_state() -> SharedState {
-    let mut state = test_state(false).await;
-    let mut update_state = || {
-        let mut w = state.get_write().unwrap();
-        let (uri, content) = test_doc_1();
-        let uri_str = uri.to_string();
+    let state = test_state(false).await;
+    let mut w = state.get_write().await;
+    let (uri, content) = test_doc_1();
+    let uri_str = uri.to_string();
 
-        let ext = uri_str
-            .rsplit_once('.')
-            .expect("uri does not have extension")
-            .1;
-        let mut lexer = Lexer::new(&content, ext);
-        let new_tokens = lexer.lex_input(&w.registry);
-
-        match w.documents.get_mut(&uri) {
-            Some(tokens) => {
-                *tokens = new_tokens;
-            }
-            None => {
-                w.documents.insert(uri, new_tokens);
-            }
-        }
-        // w.update_docs_from_text(uri, content).unwrap();
-    };
-
-    update_state();
+    let ext = uri_str
+        .rsplit_once('.')
+        .expect("uri does not have extension")
+        .1;
+    let doc = Document::new(content, ext, w.position_encoding, &w.registry);
+    w.documents.insert(uri, doc);
+    drop(w);
     state
 }
 
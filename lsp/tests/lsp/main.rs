@@ -1,7 +1,9 @@
 pub mod config;
 pub mod database;
+pub mod dispatch;
 pub mod helpers;
 pub mod notifications;
 pub mod requests;
+pub mod stress;
 pub mod test_docs;
 pub mod tokens;
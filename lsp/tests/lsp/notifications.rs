@@ -2,10 +2,24 @@ use crate::{
     helpers::{handler_tests_state, test_buff_op_channel, TEST_TRACING},
     test_docs::test_doc_1,
 };
-use espx_lsp_server::handle::notifications::handle_didSave;
-use lsp_types::{DidSaveTextDocumentParams, TextDocumentIdentifier, Uri};
+use espx_lsp_server::{
+    handle::{
+        diagnostics::{
+            LspDiagnostic, EMPTY_PROMPT_CODE, INTERACT_CODE, INVALID_FLAG_CODE, NO_MODEL_CODE,
+            PUSH_WITHOUT_BLOCK_CODE, REDUNDANT_PUSH_CODE, UNKNOWN_SCOPE_CODE,
+        },
+        notifications::{handle_cancel, handle_didChange, handle_didSave},
+    },
+    state::document::Document,
+};
+use lsp_server::RequestId;
+use lsp_types::{
+    CancelParams, DiagnosticSeverity, DidChangeTextDocumentParams, DidSaveTextDocumentParams,
+    NumberOrString, Position, Range, TextDocumentContentChangeEvent, TextDocumentIdentifier, Uri,
+    VersionedTextDocumentIdentifier,
+};
 use serde::Serialize;
-use std::sync::LazyLock;
+use std::{str::FromStr, sync::LazyLock};
 use tracing::warn;
 
 pub fn into_lsp_notification<P: Serialize>(params: P, method: &str) -> lsp_server::Notification {
@@ -23,13 +37,101 @@ fn create_didsave_params(uri: Uri, text: Option<String>) -> DidSaveTextDocumentP
     }
 }
 
+fn create_didchange_params(uri: Uri, changes: Vec<(Range, &str)>) -> DidChangeTextDocumentParams {
+    DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier { uri, version: 0 },
+        content_changes: changes
+            .into_iter()
+            .map(|(range, text)| TextDocumentContentChangeEvent {
+                range: Some(range),
+                range_length: None,
+                text: text.to_owned(),
+            })
+            .collect(),
+    }
+}
+
+fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+    Range {
+        start: Position {
+            line: start.0,
+            character: start.1,
+        },
+        end: Position {
+            line: end.0,
+            character: end.1,
+        },
+    }
+}
+
+#[tokio::test]
+async fn handles_didchange_correctly() {
+    LazyLock::force(&TEST_TRACING);
+    let (uri, text) = test_doc_1();
+    let state = handler_tests_state().await;
+
+    let r = state.get_read().await;
+    let custom_cache_len_before = r
+        .agents
+        .as_ref()
+        .unwrap()
+        .custom_agent_ref("c")
+        .unwrap()
+        .cache
+        .len();
+    drop(r);
+
+    let buffer_op_channel = test_buff_op_channel();
+
+    let changes = vec![
+        (range((3, 5), (3, 8)), "what does this do?"),
+        (range((13, 0), (13, 4)), "// +c\nstruct AlsoPushed;\n"),
+    ];
+    let params = create_didchange_params(uri.clone(), changes);
+    let noti = into_lsp_notification(params, "textDocument/didChange");
+
+    handle_didChange(noti, state.clone(), buffer_op_channel.sender.clone())
+        .await
+        .unwrap();
+
+    let expected_text = text.replace("// @_hey", "// @_what does this do?").replace(
+        "struct ToBePushed;\n    ",
+        "struct ToBePushed;\n// +c\nstruct AlsoPushed;\n",
+    );
+
+    let r = state.get_read().await;
+    let doc = r.documents.get(&uri).unwrap();
+    let relexed = Document::new(
+        expected_text.clone(),
+        "rs",
+        r.position_encoding,
+        &r.registry,
+    );
+    assert_eq!(doc.text, expected_text);
+    assert_eq!(doc.tokens.as_ref(), relexed.tokens.as_ref());
+
+    let agents = r.agents.as_ref().unwrap();
+    assert_eq!(
+        custom_cache_len_before + 1,
+        agents.custom_agent_ref("c").unwrap().cache.len()
+    );
+
+    assert!(agents
+        .doc_agent_ref(&uri)
+        .unwrap()
+        .cache
+        .as_ref()
+        .iter()
+        .any(|m| m.content == expected_text));
+}
+
 #[tokio::test]
 async fn handles_didsave_correctly() {
     LazyLock::force(&TEST_TRACING);
     let (uri, text) = test_doc_1();
     let state = handler_tests_state().await;
 
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
 
     let agent_cache_before = r.agents.as_ref().unwrap().global_agent_ref().cache.clone();
     drop(r);
@@ -43,7 +145,7 @@ async fn handles_didsave_correctly() {
         .await
         .unwrap();
 
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
     let agent_cache_len_after = r.agents.as_ref().unwrap().global_agent_ref().cache.len();
     drop(r);
 
@@ -57,10 +159,151 @@ async fn handles_didsave_correctly() {
     handle_didSave(noti, state.clone(), buffer_op_channel.sender)
         .await
         .unwrap();
-    let r = state.get_read().unwrap();
+    let r = state.get_read().await;
 
     warn!("agent cache before: {agent_cache_before:#?}",);
     let agent_cache_after = r.agents.as_ref().unwrap().global_agent_ref().cache.clone();
     warn!("agent cache after: {agent_cache_after:#?}",);
     assert_eq!(agent_cache_after.len(), agent_cache_before.len());
 }
+
+#[tokio::test]
+async fn bad_interact_flags_are_diagnosed() {
+    LazyLock::force(&TEST_TRACING);
+    let (uri, _) = test_doc_1();
+    let state = handler_tests_state().await;
+    let buffer_op_channel = test_buff_op_channel();
+
+    let changes = vec![(
+        range((3, 5), (3, 8)),
+        " --model haiku --verbose --model gpt4 explain",
+    )];
+    let params = create_didchange_params(uri.clone(), changes);
+    let noti = into_lsp_notification(params, "textDocument/didChange");
+
+    handle_didChange(noti, state.clone(), buffer_op_channel.sender.clone())
+        .await
+        .unwrap();
+
+    let mut w = state.get_write().await;
+    let params = match LspDiagnostic::diagnose_document(uri, &mut w).unwrap() {
+        LspDiagnostic::Publish(params) => params,
+        other => panic!("expected diagnostics to be published, got {other:?}"),
+    };
+    let errors: Vec<_> = params
+        .diagnostics
+        .iter()
+        .filter(|d| d.severity == Some(DiagnosticSeverity::ERROR))
+        .collect();
+
+    // the test config uses anthropic, so only haiku is usable
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].range, range((3, 20), (3, 29)));
+    assert!(errors[0].message.contains("--verbose"));
+    assert_eq!(errors[1].range, range((3, 30), (3, 42)));
+    for error in errors.iter() {
+        assert_eq!(
+            error.code,
+            Some(NumberOrString::String(INVALID_FLAG_CODE.to_owned()))
+        );
+        let related = error.related_information.as_ref().unwrap();
+        assert_eq!(related[0].location.uri, params.uri);
+    }
+}
+
+#[tokio::test]
+async fn malformed_interacts_are_diagnosed() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let uri = Uri::from_str("malformed.rs").unwrap();
+    let text = "// @x what is this\nfn a() {}\n// @_\nfn b() {}\n// +^\nfn c() {}\n// +_";
+
+    let mut w = state.get_write().await;
+    w.update_doc_and_agents_from_text(uri.clone(), text.to_owned())
+        .unwrap();
+    let coded = |w: &mut espx_lsp_server::state::LspState| {
+        let params = match LspDiagnostic::diagnose_document(uri.clone(), w).unwrap() {
+            LspDiagnostic::Publish(params) => params,
+            other => panic!("expected diagnostics to be published, got {other:?}"),
+        };
+        params
+            .diagnostics
+            .into_iter()
+            .filter_map(|d| match d.code.clone() {
+                Some(NumberOrString::String(code)) => Some((code, d)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let diagnostics = coded(&mut w);
+    let codes: Vec<&str> = diagnostics.iter().map(|(code, _)| code.as_str()).collect();
+    assert_eq!(
+        codes,
+        vec![
+            UNKNOWN_SCOPE_CODE,
+            INTERACT_CODE,
+            EMPTY_PROMPT_CODE,
+            INTERACT_CODE,
+            REDUNDANT_PUSH_CODE,
+            INTERACT_CODE,
+            PUSH_WITHOUT_BLOCK_CODE
+        ]
+    );
+    let (_, unknown) = &diagnostics[0];
+    assert_eq!(unknown.range, range((0, 4), (0, 5)));
+    assert_eq!(unknown.severity, Some(DiagnosticSeverity::ERROR));
+    assert_eq!(diagnostics[1].1.severity, Some(DiagnosticSeverity::HINT));
+    assert_eq!(diagnostics[2].1.severity, Some(DiagnosticSeverity::WARNING));
+    for (_, diagnostic) in diagnostics.iter() {
+        let related = diagnostic.related_information.as_ref().unwrap();
+        assert_eq!(related[0].location.uri, uri);
+    }
+
+    w.agents = None;
+    let no_model = coded(&mut w)
+        .into_iter()
+        .filter(|(code, _)| code == NO_MODEL_CODE)
+        .count();
+    assert_eq!(no_model, 3);
+}
+
+#[tokio::test]
+async fn cancel_notifications_cancel_in_flight_requests() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let first = state.in_flight.track(RequestId::from(1));
+    let second = state.in_flight.track(RequestId::from(2));
+    let third = state.in_flight.track(RequestId::from("third".to_owned()));
+
+    let noti = into_lsp_notification(
+        CancelParams {
+            id: NumberOrString::Number(1),
+        },
+        "$/cancelRequest",
+    );
+    assert!(handle_cancel(&noti, &state).unwrap());
+    assert!(state.in_flight.token(&RequestId::from(1)).is_cancelled());
+    assert!(!state.in_flight.token(&RequestId::from(2)).is_cancelled());
+
+    let noti = into_lsp_notification(serde_json::json!({ "id": 2 }), "espx/cancel");
+    assert!(handle_cancel(&noti, &state).unwrap());
+    assert!(state.in_flight.token(&RequestId::from(2)).is_cancelled());
+    assert!(!state
+        .in_flight
+        .token(&RequestId::from("third".to_owned()))
+        .is_cancelled());
+
+    let noti = into_lsp_notification((), "espx/cancel");
+    assert!(handle_cancel(&noti, &state).unwrap());
+    assert!(state
+        .in_flight
+        .token(&RequestId::from("third".to_owned()))
+        .is_cancelled());
+
+    let noti = into_lsp_notification((), "textDocument/didClose");
+    assert!(!handle_cancel(&noti, &state).unwrap());
+
+    drop((first, second, third));
+    assert!(state.in_flight.is_empty());
+}
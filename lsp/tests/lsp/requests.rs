@@ -1,12 +1,26 @@
-use crate::helpers::{handler_tests_state, test_buff_op_channel, TEST_TRACING};
-use espx_lsp_server::handle::{
-    buffer_operations::{BufferOpChannelHandler, BufferOpChannelStatus, BufferOperation},
-    requests::handle_goto_definition,
+use crate::{
+    config::test_config,
+    helpers::{handler_tests_state, test_buff_op_channel, TEST_TRACING},
+    test_docs::{test_doc_1, test_doc_2},
 };
-use lsp_server::RequestId;
+use espionox::agents::memory::{Message, MessageRole, MessageStack, OtherRoleTo};
+use espx_lsp_server::{
+    handle::{
+        buffer_operations::{BufferOpChannelHandler, BufferOpChannelStatus, BufferOperation},
+        requests::{handle_goto_definition, handle_request},
+    },
+    state::{document::Document, SharedState},
+};
+use lsp_server::{ErrorCode, RequestId, Response};
 use lsp_types::{
-    GotoDefinitionParams, HoverParams, PartialResultParams, Position, TextDocumentIdentifier,
-    TextDocumentPositionParams, Uri, WorkDoneProgressParams,
+    CodeActionContext, CodeActionOrCommand, CodeActionParams, CodeActionResponse, CompletionItem,
+    CompletionParams, CompletionResponse, CompletionTextEdit, DocumentDiagnosticParams,
+    DocumentDiagnosticReport, DocumentDiagnosticReportResult, Documentation, ExecuteCommandParams,
+    FullDocumentDiagnosticReport, GotoDefinitionParams, HoverContents, HoverParams,
+    InlineCompletionContext, InlineCompletionParams, InlineCompletionTriggerKind,
+    PartialResultParams, Position, PreviousResultId, Range, TextDocumentContentChangeEvent,
+    TextDocumentIdentifier, TextDocumentPositionParams, Uri, WorkDoneProgressParams,
+    WorkspaceDiagnosticParams, WorkspaceDiagnosticReportResult, WorkspaceDocumentDiagnosticReport,
 };
 use serde::Serialize;
 use std::{str::FromStr, sync::LazyLock};
@@ -20,6 +34,9 @@ enum BufferOpType {
     WorkspaceEdit,
     GotoFile,
     HoverResponse,
+    ClientRequest,
+    LogMessage,
+    Response,
 }
 
 #[derive(Debug)]
@@ -28,7 +45,7 @@ struct BufferOpTypeVec(Vec<(BufferOpType, Option<usize>)>);
 impl BufferOpType {
     fn same_as(&self, op: &BufferOperation) -> bool {
         match op {
-            BufferOperation::WorkDone(_) => {
+            BufferOperation::WorkDone { .. } => {
                 if let Self::WorkDone = self {
                     return true;
                 }
@@ -58,6 +75,21 @@ impl BufferOpType {
                     return true;
                 }
             }
+            BufferOperation::ClientRequest(_) => {
+                if let Self::ClientRequest = self {
+                    return true;
+                }
+            }
+            BufferOperation::LogMessage(_) => {
+                if let Self::LogMessage = self {
+                    return true;
+                }
+            }
+            BufferOperation::Response(_) => {
+                if let Self::Response = self {
+                    return true;
+                }
+            }
         }
         false
     }
@@ -139,7 +171,7 @@ pub fn create_gotodef_params(position: Position, doc_uri: Uri) -> GotoDefinition
     }
 }
 
-fn create_hover_params(position: Position, doc_uri: Uri) -> HoverParams {
+pub fn create_hover_params(position: Position, doc_uri: Uri) -> HoverParams {
     let work_done_progress_params = WorkDoneProgressParams {
         work_done_token: None,
     };
@@ -179,7 +211,7 @@ async fn handles_prompt_gotodef_correctly() {
     buffer_op_channel.sender.send_finish().await.unwrap();
 
     let expected_ops = BufferOpTypeVec(vec![
-        (BufferOpType::ShowMessage, Some(2)),
+        (BufferOpType::LogMessage, Some(2)),
         (BufferOpType::WorkspaceEdit, Some(1)),
         (BufferOpType::WorkDone, None),
         (BufferOpType::ShowMessage, Some(1)),
@@ -189,3 +221,667 @@ async fn handles_prompt_gotodef_correctly() {
 
     assert!(expected_ops.validate_buffer_ops(all));
 }
+
+#[tokio::test]
+async fn rag_push_without_database_errors() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let uri = Uri::from_str("rag_push.rs").unwrap();
+    {
+        let mut w = state.get_write().await;
+        assert!(w.database.is_none());
+        let doc = Document::new(
+            "// $_ what do we eat\nfn eat() {}\n".to_owned(),
+            "rs",
+            w.position_encoding,
+            &w.registry,
+        );
+        w.documents.insert(uri.clone(), doc);
+    }
+
+    let buffer_op_channel = test_buff_op_channel();
+    let params = create_gotodef_params(Position::new(0, 4), uri);
+    let req = into_lsp_request(params, 1, "textDocument/definition");
+    let err = handle_goto_definition(req, state.clone(), buffer_op_channel.sender.clone())
+        .await
+        .expect_err("rag push should need a database");
+    assert!(err.to_string().contains("Database Not Present"));
+}
+
+async fn response_to(req: lsp_server::Request, state: SharedState) -> Response {
+    let mut handler = handle_request(req, state).await.unwrap();
+    let mut responses: Vec<Response> = poll_into_vec(&mut handler)
+        .await
+        .into_iter()
+        .filter_map(|op| match op {
+            BufferOperation::Response(response) => Some(response),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(responses.len(), 1, "expected exactly one response");
+    responses.remove(0)
+}
+
+#[tokio::test]
+async fn every_request_gets_a_response() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+
+    // no interact in the comment, so there is nothing to hover
+    let params = create_hover_params(Position::new(1, 4), uri.clone());
+    let response = response_to(
+        into_lsp_request(params, 1, "textDocument/hover"),
+        state.clone(),
+    )
+    .await;
+    assert_eq!(response.id, RequestId::from(1));
+    assert_eq!(response.result, Some(serde_json::Value::Null));
+    assert!(response.error.is_none());
+
+    let params = create_gotodef_params(Position::new(6, 4), uri.clone());
+    let response = response_to(
+        into_lsp_request(params, 2, "textDocument/definition"),
+        state.clone(),
+    )
+    .await;
+    let error = response
+        .error
+        .expect("gotodef outside a comment should fail");
+    assert_eq!(error.code, ErrorCode::InternalError as i32);
+    assert!(error.message.contains("no comment at gotodef position"));
+
+    let response = response_to(
+        into_lsp_request(serde_json::json!({ "bad": true }), 3, "textDocument/hover"),
+        state.clone(),
+    )
+    .await;
+    assert_eq!(
+        response.error.unwrap().code,
+        ErrorCode::InvalidParams as i32
+    );
+
+    let response = response_to(into_lsp_request((), 4, "espx/doesNotExist"), state.clone()).await;
+    assert_eq!(
+        response.error.unwrap().code,
+        ErrorCode::MethodNotFound as i32
+    );
+
+    let response = response_to(into_lsp_request((), 5, "shutdown"), state).await;
+    assert!(response.error.is_none());
+}
+
+fn diagnostic_params(uri: Uri, previous_result_id: Option<String>) -> DocumentDiagnosticParams {
+    DocumentDiagnosticParams {
+        text_document: TextDocumentIdentifier { uri },
+        identifier: None,
+        previous_result_id,
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+    }
+}
+
+async fn pull_diagnostics(
+    uri: Uri,
+    previous_result_id: Option<String>,
+    state: SharedState,
+) -> DocumentDiagnosticReport {
+    let params = diagnostic_params(uri, previous_result_id);
+    let req = into_lsp_request(params, 1, "textDocument/diagnostic");
+    let result = response_to(req, state).await.result.unwrap();
+    match serde_json::from_value(result).unwrap() {
+        DocumentDiagnosticReportResult::Report(report) => report,
+        other => panic!("expected a whole report, got {other:?}"),
+    }
+}
+
+fn full_report(report: DocumentDiagnosticReport) -> FullDocumentDiagnosticReport {
+    match report {
+        DocumentDiagnosticReport::Full(report) => report.full_document_diagnostic_report,
+        other => panic!("expected a full report, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn pulled_diagnostics_are_unchanged_until_the_document_is() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+
+    let first = full_report(pull_diagnostics(uri.clone(), None, state.clone()).await);
+    assert_eq!(first.items.len(), 2);
+    let result_id = first.result_id.expect("reports should have result ids");
+
+    match pull_diagnostics(uri.clone(), Some(result_id.clone()), state.clone()).await {
+        DocumentDiagnosticReport::Unchanged(report) => assert_eq!(
+            report.unchanged_document_diagnostic_report.result_id,
+            result_id
+        ),
+        other => panic!("expected the report to be unchanged, got {other:?}"),
+    }
+
+    {
+        let mut w = state.get_write().await;
+        let change = TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(3, 5), Position::new(3, 5))),
+            range_length: None,
+            text: " --temperature 9 ".to_owned(),
+        };
+        w.update_doc_and_agents_from_changes(uri.clone(), &[change])
+            .unwrap();
+    }
+    let changed = full_report(pull_diagnostics(uri, Some(result_id.clone()), state).await);
+    assert_eq!(changed.items.len(), 3);
+    assert_ne!(changed.result_id, Some(result_id));
+}
+
+#[tokio::test]
+async fn workspace_diagnostics_cover_every_document() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+    let (other_uri, other_text) = test_doc_2();
+    {
+        let mut w = state.get_write().await;
+        w.update_doc_and_agents_from_text(other_uri.clone(), other_text)
+            .unwrap();
+    }
+    let previous = full_report(pull_diagnostics(uri.clone(), None, state.clone()).await);
+
+    let params = WorkspaceDiagnosticParams {
+        identifier: None,
+        previous_result_ids: vec![PreviousResultId {
+            uri: uri.clone(),
+            value: previous.result_id.unwrap(),
+        }],
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+    };
+    let req = into_lsp_request(params, 2, "workspace/diagnostic");
+    let result = response_to(req, state).await.result.unwrap();
+    let report = match serde_json::from_value(result).unwrap() {
+        WorkspaceDiagnosticReportResult::Report(report) => report,
+        other => panic!("expected a whole report, got {other:?}"),
+    };
+
+    assert_eq!(report.items.len(), 2);
+    for item in report.items {
+        match item {
+            WorkspaceDocumentDiagnosticReport::Unchanged(unchanged) => {
+                assert_eq!(unchanged.uri, uri)
+            }
+            WorkspaceDocumentDiagnosticReport::Full(full) => {
+                assert_eq!(full.uri, other_uri);
+                assert_eq!(full.full_document_diagnostic_report.items.len(), 1);
+            }
+        }
+    }
+}
+
+fn code_action_titles(result: serde_json::Value) -> Vec<String> {
+    let actions: CodeActionResponse = serde_json::from_value(result).unwrap();
+    actions
+        .into_iter()
+        .map(|action| match action {
+            CodeActionOrCommand::CodeAction(action) => action.title,
+            CodeActionOrCommand::Command(command) => command.title,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn code_actions_follow_the_interact_under_the_cursor() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+
+    let mut titles = vec![];
+    for (id, line) in [(1, 3), (2, 11), (3, 1)] {
+        let params = CodeActionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range::new(Position::new(line, 4), Position::new(line, 4)),
+            context: CodeActionContext::default(),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+        };
+        let req = into_lsp_request(params, id, "textDocument/codeAction");
+        let response = response_to(req, state.clone()).await;
+        titles.push(code_action_titles(response.result.unwrap()));
+    }
+
+    assert_eq!(
+        titles[0],
+        vec![
+            "Run prompt",
+            "Run prompt in scope…",
+            "Clear scope",
+            "Show scope history"
+        ]
+    );
+    assert_eq!(
+        titles[1],
+        vec![
+            "Convert push to RAG push",
+            "Clear scope",
+            "Show scope history"
+        ]
+    );
+    // the comment has no interact to act on
+    assert!(titles[2].is_empty());
+}
+
+#[tokio::test]
+async fn selections_can_be_asked_about() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+
+    let selection = Range::new(Position::new(5, 4), Position::new(6, 15));
+    let params = CodeActionParams {
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+        range: selection,
+        context: CodeActionContext::default(),
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+    };
+    let req = into_lsp_request(params, 1, "textDocument/codeAction");
+    let actions: CodeActionResponse =
+        serde_json::from_value(response_to(req, state.clone()).await.result.unwrap()).unwrap();
+    let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+        panic!("expected a code action, got {actions:?}");
+    };
+    assert_eq!(actions.len(), 1);
+    assert_eq!(action.title, "Ask about selection");
+    let command = action.command.clone().unwrap();
+    assert_eq!(command.command, "espx.askAboutSelection");
+    assert_eq!(
+        command.arguments.unwrap()[0],
+        serde_json::json!({ "uri": uri.clone(), "range": selection })
+    );
+
+    // a selection of only whitespace has nothing to ask about
+    let empty = Range::new(Position::new(2, 0), Position::new(3, 0));
+    let req = execute_command_request(
+        2,
+        "espx.askAboutSelection",
+        serde_json::json!({ "uri": uri, "range": empty, "question": "why?", "scope": "_" }),
+    );
+    let error = response_to(req, state).await.error.unwrap();
+    assert!(error.message.contains("nothing selected"));
+}
+
+pub fn execute_command_request(
+    id: i32,
+    command: &str,
+    argument: serde_json::Value,
+) -> lsp_server::Request {
+    let params = ExecuteCommandParams {
+        command: command.to_owned(),
+        arguments: vec![argument],
+        work_done_progress_params: WorkDoneProgressParams::default(),
+    };
+    into_lsp_request(params, id, "workspace/executeCommand")
+}
+
+#[tokio::test]
+async fn scope_commands_clear_show_and_convert() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+    {
+        let mut w = state.get_write().await;
+        let agent = w.agents.as_mut().unwrap().global_agent_mut();
+        agent.cache.push(Message::new_user("what is raw?"));
+        agent.cache.push(Message::new_assistant("what was read"));
+    }
+    let on_global = serde_json::json!({ "uri": uri.clone(), "scope": "_" });
+
+    let req = execute_command_request(1, "espx.dumpScope", on_global.clone());
+    let history = response_to(req, state.clone()).await.result.unwrap();
+    let history: MessageStack = serde_json::from_value(history).unwrap();
+    assert_eq!(history.as_ref().last().unwrap().content, "what was read");
+
+    let req = execute_command_request(2, "espx.clearScope", on_global);
+    let response = response_to(req, state.clone()).await;
+    assert!(response.error.is_none());
+    {
+        let r = state.get_read().await;
+        let cache = &r.agents.as_ref().unwrap().global_agent_ref().cache;
+        // only the system prompt is left
+        assert_eq!(cache.len(), 1);
+    }
+
+    let req = execute_command_request(
+        3,
+        "espx.convertToRagPush",
+        serde_json::json!({ "uri": uri.clone(), "position": Position::new(11, 4) }),
+    );
+    let mut handler = handle_request(req, state.clone()).await.unwrap();
+    let edit = poll_into_vec(&mut handler)
+        .await
+        .into_iter()
+        .find_map(|op| match op {
+            BufferOperation::WorkspaceEdit(params) => Some(params.edit),
+            _ => None,
+        })
+        .expect("push should be converted with an edit");
+    let edits = edit.changes.unwrap().remove(&uri).unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].new_text, "$");
+    assert_eq!(
+        edits[0].range,
+        Range::new(Position::new(11, 3), Position::new(11, 4))
+    );
+
+    let req = execute_command_request(
+        4,
+        "espx.clearScope",
+        serde_json::json!({ "uri": uri, "scope": "nope" }),
+    );
+    let error = response_to(req, state).await.error.unwrap();
+    assert_eq!(error.code, ErrorCode::RequestFailed as i32);
+}
+
+#[tokio::test]
+async fn push_command_pushes_a_range_into_a_scope() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+    let range = Range::new(Position::new(4, 0), Position::new(5, 32));
+    let argument = serde_json::json!({ "uri": uri.clone(), "scope": "review", "range": range });
+
+    for id in 0..2 {
+        let req = execute_command_request(id, "espx.push", argument.clone());
+        assert!(response_to(req, state.clone()).await.error.is_none());
+    }
+
+    let r = state.get_read().await;
+    let cache = &r
+        .agents
+        .as_ref()
+        .unwrap()
+        .custom_agent_ref("review")
+        .unwrap()
+        .cache;
+    // pushing the same range again replaces what it pushed before
+    assert_eq!(cache.len(), 2);
+    let pushed = cache.as_ref().last().unwrap();
+    assert_eq!(
+        pushed.content,
+        "fn main() {\n    let mut raw = String::new();"
+    );
+    assert_eq!(
+        pushed.role,
+        MessageRole::Other {
+            alias: "test_doc_1.rs (lines 5-6)".to_owned(),
+            coerce_to: OtherRoleTo::User,
+        }
+    );
+}
+
+#[tokio::test]
+async fn commands_fail_without_what_they_need() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+
+    for (id, command) in [(1, "espx.saveState"), (2, "espx.reindexWorkspace")] {
+        let req = execute_command_request(id, command, serde_json::Value::Null);
+        let error = response_to(req, state.clone()).await.error.unwrap();
+        assert!(error.message.contains("Database Not Present"), "{command}");
+    }
+
+    let req = execute_command_request(
+        3,
+        "espx.prompt",
+        serde_json::json!({ "uri": uri.clone(), "scope": "_", "text": "  " }),
+    );
+    let error = response_to(req, state.clone()).await.error.unwrap();
+    assert!(error.message.contains("nothing to prompt"));
+
+    let req = execute_command_request(4, "espx.doesNotExist", serde_json::Value::Null);
+    let error = response_to(req, state).await.error.unwrap();
+    assert_eq!(error.code, ErrorCode::InvalidParams as i32);
+}
+
+fn completion_items(id: i32, uri: &Uri, position: Position) -> lsp_server::Request {
+    let params = CompletionParams {
+        text_document_position: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position,
+        },
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        partial_result_params: PartialResultParams::default(),
+        context: None,
+    };
+    into_lsp_request(params, id, "textDocument/completion")
+}
+
+#[tokio::test]
+async fn commands_and_scopes_are_completed_in_comments() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let uri = Uri::from_str("file:///tmp/completion.rs").unwrap();
+    {
+        let mut w = state.get_write().await;
+        w.update_doc_and_agents_from_text(uri.clone(), "// \n// @re\nfn main() {}\n".to_owned())
+            .unwrap();
+        let agent = w
+            .agents
+            .as_mut()
+            .unwrap()
+            .custom_agent_mut("review")
+            .unwrap();
+        agent.cache.push(Message::new_user("is this fine?"));
+    }
+
+    let mut responses = vec![];
+    for (id, position) in [
+        (1, Position::new(0, 3)),
+        (2, Position::new(1, 6)),
+        (3, Position::new(2, 4)),
+    ] {
+        let req = completion_items(id, &uri, position);
+        let result = response_to(req, state.clone()).await.result.unwrap();
+        let items: Vec<CompletionItem> =
+            match serde_json::from_value::<CompletionResponse>(result).unwrap() {
+                CompletionResponse::Array(items) => items,
+                CompletionResponse::List(list) => list.items,
+            };
+        responses.push(items);
+    }
+
+    let labels = |items: &Vec<CompletionItem>| -> Vec<String> {
+        items.iter().map(|item| item.label.clone()).collect()
+    };
+    assert_eq!(labels(&responses[0]), vec!["@", "+", "$"]);
+
+    let scopes = labels(&responses[1]);
+    for scope in ["_", "^", "c", "b", "review"] {
+        assert!(scopes.contains(&scope.to_owned()), "{scope} in {scopes:?}");
+    }
+    let review = responses[1]
+        .iter()
+        .find(|item| item.label == "review")
+        .unwrap();
+    let Some(Documentation::MarkupContent(docs)) = &review.documentation else {
+        panic!("review has no markdown documentation");
+    };
+    assert!(docs.value.contains("review prompt"), "{}", docs.value);
+    assert!(docs.value.contains("**Messages:** 2"), "{}", docs.value);
+    let Some(CompletionTextEdit::Edit(edit)) = &review.text_edit else {
+        panic!("review does not replace what was typed");
+    };
+    assert_eq!(
+        edit.range,
+        Range::new(Position::new(1, 4), Position::new(1, 6))
+    );
+
+    // outside of comments there is nothing to complete
+    assert!(responses[2].is_empty());
+}
+
+#[tokio::test]
+async fn inline_completion_has_to_be_enabled() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (uri, _) = test_doc_1();
+    let params = InlineCompletionParams {
+        work_done_progress_params: WorkDoneProgressParams::default(),
+        text_document_position: TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: Position::new(5, 4),
+        },
+        context: InlineCompletionContext {
+            trigger_kind: InlineCompletionTriggerKind::Automatic,
+            selected_completion_info: None,
+        },
+    };
+    let req = into_lsp_request(params, 1, "textDocument/inlineCompletion");
+    let error = response_to(req, state).await.error.unwrap();
+    assert_eq!(error.message, "inline completion is not enabled");
+}
+
+#[tokio::test]
+async fn hover_summarizes_any_scope() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let uri = Uri::from_str("file:///tmp/hover.rs").unwrap();
+    {
+        let mut w = state.get_write().await;
+        w.update_doc_and_agents_from_text(
+            uri.clone(),
+            "// @review is this fine?\n\n// +review\nfn main() {\n    todo!()\n}\n".to_owned(),
+        )
+        .unwrap();
+        let agent = w
+            .agents
+            .as_mut()
+            .unwrap()
+            .custom_agent_mut("review")
+            .unwrap();
+        agent.cache.push(Message::new_user("is this fine?"));
+        agent.cache.push(Message::new_assistant("it does nothing"));
+    }
+
+    let params = create_hover_params(Position::new(0, 4), uri.clone());
+    let mut handler = handle_request(into_lsp_request(params, 1, "textDocument/hover"), state)
+        .await
+        .unwrap();
+    let contents = poll_into_vec(&mut handler)
+        .await
+        .into_iter()
+        .find_map(|op| match op {
+            BufferOperation::HoverResponse { contents, .. } => Some(contents),
+            _ => None,
+        })
+        .expect("hovering a prompt should respond");
+    let HoverContents::Markup(summary) = contents else {
+        panic!("hover should be markdown");
+    };
+    for expected in [
+        "Custom scope `review`",
+        "**System prompt:** review prompt",
+        "**Messages:** 4",
+        "- `file:///tmp/hover.rs` (lines 4-6)",
+        "**User:** is this fine?",
+        "**Assistant:** it does nothing",
+    ] {
+        assert!(
+            summary.value.contains(expected),
+            "{expected} in {}",
+            summary.value
+        );
+    }
+}
+
+#[tokio::test]
+async fn custom_scopes_work_in_goto_definition_and_hover() {
+    LazyLock::force(&TEST_TRACING);
+    let state = SharedState::init(test_config(false).unwrap())
+        .await
+        .unwrap();
+    let uri = Uri::from_str("file:///tmp/custom_scopes.rs").unwrap();
+    {
+        let mut w = state.get_write().await;
+        w.update_doc_and_agents_from_text(
+            uri.clone(),
+            "// @c\n\n// +b\nfn b() {}\n\n// @b what does b do?\n".to_owned(),
+        )
+        .unwrap();
+    }
+
+    // the push and the prompt in b both resolve to b's agent, without asking the model anything
+    {
+        let mut w = state.get_write().await;
+        for line in [2, 5] {
+            let integer = w
+                .documents
+                .get(&uri)
+                .unwrap()
+                .tokens
+                .comment_in_position(&Position::new(line, 4))
+                .unwrap()
+                .0
+                .try_get_interact_integer()
+                .unwrap();
+            let b = w.agents.as_ref().unwrap().custom_agent_ref("b").unwrap() as *const _;
+            assert!(std::ptr::eq(
+                w.agent_ref_from_scope(integer.scope, &uri).unwrap(),
+                b
+            ));
+            assert!(std::ptr::eq(
+                w.agent_mut_from_interact_integer(integer, &uri).unwrap(),
+                b
+            ));
+        }
+    }
+
+    let params = create_gotodef_params(Position::new(2, 4), uri.clone());
+    let mut handler = handle_request(
+        into_lsp_request(params, 1, "textDocument/definition"),
+        state.clone(),
+    )
+    .await
+    .unwrap();
+    assert!(poll_into_vec(&mut handler).await.iter().any(|op| matches!(
+        op,
+        BufferOperation::ShowMessage(message) if message.message == "Push command has no GOTO function"
+    )));
+
+    let mut summaries = vec![];
+    for (id, line) in [(2, 0), (3, 2), (4, 5)] {
+        let params = create_hover_params(Position::new(line, 4), uri.clone());
+        let mut handler = handle_request(
+            into_lsp_request(params, id, "textDocument/hover"),
+            state.clone(),
+        )
+        .await
+        .unwrap();
+        let summary = poll_into_vec(&mut handler)
+            .await
+            .into_iter()
+            .find_map(|op| match op {
+                BufferOperation::HoverResponse {
+                    contents: HoverContents::Markup(summary),
+                    ..
+                } => Some(summary.value),
+                _ => None,
+            })
+            .expect("every interact should be hoverable");
+        summaries.push(summary);
+    }
+
+    assert!(summaries[0].contains("Custom scope `c`"));
+    assert!(summaries[0].contains("No exchanges yet"));
+    // the push and the prompt in b are in the same scope
+    for summary in &summaries[1..] {
+        assert!(summary.contains("Custom scope `b`"), "{summary}");
+        assert!(summary.contains("**System prompt:** prompt"), "{summary}");
+        assert!(
+            summary.contains("- `file:///tmp/custom_scopes.rs` (lines 4-4)"),
+            "{summary}"
+        );
+        assert!(summary.contains("No exchanges yet"), "{summary}");
+    }
+}
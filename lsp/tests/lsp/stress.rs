@@ -0,0 +1,118 @@
+use crate::{
+    dispatch::insert_at_start,
+    helpers::{handler_tests_state, test_buff_op_channel, TEST_TRACING},
+    notifications::into_lsp_notification,
+    requests::{create_hover_params, into_lsp_request},
+    test_docs::{test_doc_1, test_doc_2},
+};
+use espx_lsp_server::handle::{
+    dispatch::Dispatcher,
+    notifications::{handle_didChange, handle_didSave},
+    requests::handle_hover,
+};
+use lsp_server::Message;
+use lsp_types::{
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, Position, TextDocumentIdentifier,
+    TextDocumentItem, Uri,
+};
+use std::{str::FromStr, sync::LazyLock, time::Duration};
+use tokio::task::JoinSet;
+
+const HANDLERS_PER_KIND: usize = 32;
+
+fn open_document(uri: &Uri, text: &str) -> Message {
+    let params = DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_owned(),
+            version: 0,
+            text: text.to_owned(),
+        },
+    };
+    Message::Notification(into_lsp_notification(params, "textDocument/didOpen"))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn many_handlers_at_once_never_contend() {
+    LazyLock::force(&TEST_TRACING);
+    let (uri, text) = test_doc_1();
+    let state = handler_tests_state().await;
+    let (saved_uri, saved_text) = test_doc_2();
+    let mut tasks = JoinSet::new();
+    // handlers fail to send operations once the receiving end is gone
+    let mut channels = vec![];
+
+    // holding the lock while the handlers start makes every one of them wait on it
+    let held = state.get_write().await;
+    for i in 0..HANDLERS_PER_KIND {
+        let Message::Notification(noti) = insert_at_start(uri.clone(), "a") else {
+            unreachable!("changes are notifications")
+        };
+        let channel = test_buff_op_channel();
+        tasks.spawn(handle_didChange(
+            noti,
+            state.clone(),
+            channel.sender.clone(),
+        ));
+        channels.push(channel);
+
+        let params = create_hover_params(Position::new(3, 4), uri.clone());
+        let req = into_lsp_request(params, i as i32, "textDocument/hover");
+        let channel = test_buff_op_channel();
+        tasks.spawn(handle_hover(req, state.clone(), channel.sender.clone()));
+        channels.push(channel);
+
+        let params = DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier {
+                uri: saved_uri.clone(),
+            },
+            text: Some(saved_text.clone()),
+        };
+        let noti = into_lsp_notification(params, "textDocument/didSave");
+        let channel = test_buff_op_channel();
+        tasks.spawn(handle_didSave(noti, state.clone(), channel.sender.clone()));
+        channels.push(channel);
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    drop(held);
+
+    while let Some(result) = tasks.join_next().await {
+        result
+            .unwrap()
+            .expect("handler failed while others held the state");
+    }
+
+    let r = state.get_read().await;
+    let expected = "a".repeat(HANDLERS_PER_KIND) + &text;
+    assert_eq!(r.documents.get(&uri).unwrap().text, expected);
+    assert_eq!(r.documents.get(&saved_uri).unwrap().text, saved_text);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn dispatcher_handles_many_documents_concurrently() {
+    LazyLock::force(&TEST_TRACING);
+    let state = handler_tests_state().await;
+    let (_, text) = test_doc_2();
+    let uris: Vec<Uri> = (0..8)
+        .map(|i| Uri::from_str(&format!("stress_doc_{i}.rs")).unwrap())
+        .collect();
+    let (sender, _receiver) = crossbeam_channel::unbounded();
+    let mut dispatcher = Dispatcher::new(state.clone(), sender);
+
+    for uri in &uris {
+        dispatcher.dispatch(open_document(uri, &text));
+    }
+    let letters = ["a", "b", "c", "d", "e", "f", "g", "h"];
+    for letter in letters {
+        for uri in &uris {
+            dispatcher.dispatch(insert_at_start(uri.clone(), letter));
+        }
+    }
+    dispatcher.wait_idle().await;
+
+    let r = state.get_read().await;
+    let expected: String = letters.iter().rev().copied().collect::<String>() + &text;
+    for uri in &uris {
+        assert_eq!(r.documents.get(uri).unwrap().text, expected);
+    }
+}
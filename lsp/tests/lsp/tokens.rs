@@ -1,7 +1,11 @@
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    time::{Duration, Instant},
+};
 
 use espx_lsp_server::interact::{
-    id::{GLOBAL_ID, PROMPT_ID},
+    encoding::PositionEncoding,
+    id::{InteractInteger, DOCUMENT_ID, GLOBAL_ID, PROMPT_ID, PUSH_ID},
     lexer::{cmp_pos_range, Lexer, ParsedComment, Token},
     registry::InteractRegistry,
 };
@@ -121,16 +125,16 @@ pub struct MoreCode;
         )),
         Token::CommentStr,
         Token::Comment(ParsedComment::new(
-            Some(PROMPT_ID.as_ref() + GLOBAL_ID.as_ref()),
+            Some((InteractInteger::new(PROMPT_ID, GLOBAL_ID), " @_".len())),
             " @_Comment",
             Range {
                 start: lsp_types::Position {
                     line: 8,
-                    character: 3,
+                    character: 2,
                 },
                 end: lsp_types::Position {
                     line: 8,
-                    character: 13,
+                    character: 12,
                 },
             },
         )),
@@ -149,11 +153,11 @@ pub struct MoreCode;
             lsp_types::Range {
                 start: lsp_types::Position {
                     line: 14,
-                    character: 3,
+                    character: 2,
                 },
                 end: lsp_types::Position {
                     line: 17,
-                    character: 1,
+                    character: 0,
                 },
             },
         )),
@@ -181,14 +185,248 @@ pub struct MoreCode;
         },
         end: lsp_types::Position {
             line: 8,
-            character: 13,
+            character: 12,
         },
     };
 
     let expected_content = "Comment".to_string();
 
-    let (range, content) = first_parsed_comment.text_for_interact().unwrap();
+    let (range, content) = first_parsed_comment
+        .text_for_interact(PositionEncoding::default())
+        .unwrap();
 
     assert_eq!(expected_range, range);
     assert_eq!(expected_content, content);
 }
+
+#[test]
+fn multibyte_ranges_follow_position_encoding() {
+    let registry = InteractRegistry::default();
+    // '🦀' is two utf-16 code units and '漢' is one, both are a single char
+    let input = "fn main() {} // 🦀漢\nlet x = \"🦀\"; // @_ explain\n".to_owned();
+
+    let comment_start = |encoding: PositionEncoding| encoding.str_len("let x = \"🦀\"; //") as u32;
+    let comment_end =
+        |encoding: PositionEncoding| encoding.str_len("let x = \"🦀\"; // @_ explain") as u32;
+
+    for encoding in [
+        PositionEncoding::Utf8,
+        PositionEncoding::Utf16,
+        PositionEncoding::Utf32,
+    ] {
+        let tokens = Lexer::new(&input, "rs")
+            .with_encoding(encoding)
+            .lex_input(&registry);
+        assert_eq!(tokens.encoding(), encoding);
+
+        let first = tokens.clone().into_iter().next().unwrap();
+        assert_eq!(first.range.start, Position::new(0, 15));
+        assert_eq!(
+            first.range.end,
+            Position::new(0, encoding.str_len("fn main() {} // 🦀漢") as u32)
+        );
+
+        let (comment, _) = tokens
+            .comment_in_position(&Position::new(1, comment_start(encoding) + 1))
+            .expect("position should be in the prompt comment");
+        assert_eq!(
+            comment.range,
+            Range::new(
+                Position::new(1, comment_start(encoding)),
+                Position::new(1, comment_end(encoding)),
+            )
+        );
+        assert!(tokens
+            .comment_in_position(&Position::new(1, comment_end(encoding) + 1))
+            .is_none());
+
+        let (range, text) = comment.text_for_interact(encoding).unwrap();
+        assert_eq!(text, " explain");
+        assert_eq!(range.start, Position::new(1, comment_start(encoding) + 3));
+    }
+}
+
+#[test]
+fn unterminated_comment_runs_to_end_of_input() {
+    let registry = InteractRegistry::default();
+    let input = "fn main() {}\n// @_ unfinished".to_owned();
+    let tokens = Lexer::new(&input, "rs").lex_input(&registry);
+    assert_eq!(tokens.comment_indices().len(), 1);
+    match tokens.get(tokens.comment_indices()[0]) {
+        Some(Token::Comment(comment)) => {
+            assert_eq!(comment.content, " @_ unfinished");
+            assert!(comment.try_get_interact_integer().is_ok());
+        }
+        other => panic!("expected comment, got {other:?}"),
+    }
+    assert_eq!(tokens.as_ref().last(), Some(&Token::End));
+
+    let input = "fn main() {}\n/* never closed\nstruct Foo;".to_owned();
+    let tokens = Lexer::new(&input, "rs").lex_input(&registry);
+    assert_eq!(
+        tokens
+            .as_ref()
+            .iter()
+            .filter(|t| **t == Token::CommentStr)
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn interact_args_are_skipped_in_prompt_text() {
+    let registry = InteractRegistry::default();
+    let input =
+        "// @_ --model haiku --temperature 0.2 explain this\n// @_ --max-tokens\n".to_owned();
+    let tokens = Lexer::new(&input, "rs").lex_input(&registry);
+    let comments: Vec<ParsedComment> = tokens.into_iter().collect();
+
+    let flags: Vec<_> = comments[0].args.iter().map(|a| a.flag.as_str()).collect();
+    assert_eq!(flags, vec!["model", "temperature"]);
+    assert_eq!(
+        comments[0].arg_range(&comments[0].args[1], PositionEncoding::default()),
+        Range::new(Position::new(0, 20), Position::new(0, 37))
+    );
+    let (range, text) = comments[0]
+        .text_for_interact(PositionEncoding::default())
+        .unwrap();
+    assert_eq!(text, " explain this");
+    assert_eq!(range.start, Position::new(0, 37));
+
+    assert_eq!(comments[1].args.len(), 1);
+    assert_eq!(comments[1].args[0].value, None);
+    let (_, text) = comments[1]
+        .text_for_interact(PositionEncoding::default())
+        .unwrap();
+    assert_eq!(text, "");
+}
+
+#[test]
+fn named_scopes_match_longest_token() {
+    let mut registry = InteractRegistry::default();
+    let review = registry.register_scope("review").unwrap();
+    let r = registry.register_scope("r").unwrap();
+    let db = registry.register_scope("db").unwrap();
+    assert!(registry.register_scope("has space").is_err());
+
+    let input = r#"// @review is this sound?
+// @r short one
+// +db
+struct Pushed;
+// @_ still works
+// +^
+struct AlsoPushed;
+// @nothing registered
+"#
+    .to_owned();
+
+    for encoding in [PositionEncoding::Utf8, PositionEncoding::Utf16] {
+        let tokens = Lexer::new(&input, "rs")
+            .with_encoding(encoding)
+            .lex_input(&registry);
+        let comments: Vec<ParsedComment> = tokens.into_iter().collect();
+        assert_eq!(comments.len(), 6);
+
+        let expected = [
+            (
+                Some(InteractInteger::new(PROMPT_ID, review)),
+                " is this sound?",
+                10,
+            ),
+            (Some(InteractInteger::new(PROMPT_ID, r)), " short one", 5),
+            (Some(InteractInteger::new(PUSH_ID, db)), "", 6),
+            (
+                Some(InteractInteger::new(PROMPT_ID, GLOBAL_ID)),
+                " still works",
+                5,
+            ),
+            (Some(InteractInteger::new(PUSH_ID, DOCUMENT_ID)), "", 5),
+            (None, "", 0),
+        ];
+
+        for (comment, (interact, text, start)) in comments.iter().zip(expected) {
+            assert_eq!(comment.try_get_interact_integer().ok(), interact);
+            match comment.text_for_interact(encoding) {
+                Some((range, text_for_interact)) => {
+                    assert_eq!(text_for_interact, text);
+                    assert_eq!(range.start, Position::new(comment.range.start.line, start));
+                }
+                None => assert!(interact.is_none()),
+            }
+        }
+    }
+}
+
+/// Builds a rust document of roughly `lines` lines with a prompt, a push and a multiline comment
+/// every 20 lines
+fn large_input(lines: usize) -> String {
+    let chunk = r#"// @_ what does this function do?
+fn some_function(input: &str) -> usize {
+    let mut total = 0;
+    for ch in input.chars() {
+        total += ch.len_utf8();
+    }
+    total
+}
+
+// +_
+struct Pushed {
+    field: String,
+}
+
+/*
+Multiline comment with some ünicode 🦀
+*/
+fn other() {}
+const LIMIT: usize = 10;
+
+"#;
+    chunk.repeat(lines / chunk.lines().count())
+}
+
+fn fastest_lex(input: &str, registry: &InteractRegistry) -> (Duration, usize) {
+    let mut fastest = Duration::MAX;
+    let mut comments = 0;
+    for _ in 0..3 {
+        let start = Instant::now();
+        let tokens = Lexer::new(input, "rs").lex_input(registry);
+        fastest = fastest.min(start.elapsed());
+        comments = tokens.comment_indices().len();
+    }
+    (fastest, comments)
+}
+
+/// Timed, so only run on demand with `cargo test -- --ignored`
+#[test]
+#[ignore = "benchmark"]
+fn lexing_time_scales_linearly() {
+    let registry = InteractRegistry::default();
+    let small = large_input(2_000);
+    let big = large_input(8_000);
+
+    let (small_time, small_comments) = fastest_lex(&small, &registry);
+    let (big_time, big_comments) = fastest_lex(&big, &registry);
+
+    assert_eq!(small_comments * 4, big_comments);
+    // A quadratic lexer would take roughly 16 times as long on 4 times the input
+    assert!(
+        big_time < small_time * 8,
+        "lexing 4x the input took {big_time:?} compared to {small_time:?}"
+    );
+}
+
+/// Timed, so only run on demand with `cargo test -- --ignored`
+#[test]
+#[ignore = "benchmark"]
+fn lexing_large_file_is_fast() {
+    let registry = InteractRegistry::default();
+    let input = large_input(20_000);
+
+    let (time, comments) = fastest_lex(&input, &registry);
+
+    assert_eq!(comments, 3_000);
+    assert!(
+        time < Duration::from_secs(2),
+        "lexing 20000 lines took {time:?}"
+    );
+}